@@ -0,0 +1,40 @@
+// Compares byte-at-a-time CRC32 with `update_slice` on a few megabytes.
+// Run with `cargo bench --bench crc32`.
+use gziprust::crc32;
+use std::time::{Duration, Instant};
+
+const ITERATIONS: u32 = 10;
+
+fn time(f: impl Fn() -> u32) -> (Duration, u32) {
+  let start = Instant::now();
+  let mut crc = 0;
+  for _ in 0..ITERATIONS {
+    crc = f();
+  }
+  (start.elapsed() / ITERATIONS, crc)
+}
+
+fn main() {
+  let data: Vec<u8> = (0..8 * 1024 * 1024_u32)
+    .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+    .collect();
+  let (bytewise, a) = time(|| {
+    data.iter().fold(crc32::initial_value(), |crc, &byte| {
+      crc32::update(crc, byte)
+    })
+  });
+  let (slice, b) = time(|| crc32::update_slice(crc32::initial_value(), &data));
+  assert_eq!(a, b);
+
+  let mb = data.len() as f64 / (1024.0 * 1024.0);
+  println!(
+    "bytewise {:>10.2?} ({:.0} MB/s)",
+    bytewise,
+    mb / bytewise.as_secs_f64()
+  );
+  println!(
+    "slice    {:>10.2?} ({:.0} MB/s)",
+    slice,
+    mb / slice.as_secs_f64()
+  );
+}
@@ -0,0 +1,50 @@
+// Compares the tree and table Huffman decoders on the gzip fixtures.
+// Run with `cargo bench`.
+use gziprust::deflate::{DecodeMode, Inflate};
+use std::fs;
+use std::time::{Duration, Instant};
+
+const ITERATIONS: u32 = 10;
+
+// The fixtures are gzip files with only the FNAME flag set; skip past their
+// headers to get at the deflate stream
+fn deflate_stream(gzip: &[u8]) -> &[u8] {
+  let name_len = gzip[10..].iter().position(|&b| b == 0).unwrap();
+  &gzip[(10 + name_len + 1)..]
+}
+
+fn time_inflate(stream: &[u8], mode: DecodeMode) -> Duration {
+  let mut buf = vec![0; 64 * 1024];
+  let start = Instant::now();
+  for _ in 0..ITERATIONS {
+    let mut inflate = Inflate::with_mode(stream.iter().cloned(), mode);
+    while inflate.read(&mut buf).expect("Failed to inflate") > 0 {}
+  }
+  start.elapsed() / ITERATIONS
+}
+
+fn main() {
+  let mut paths: Vec<_> = fs::read_dir("tests/gzip/compressed/")
+    .expect("failed to read dir")
+    .map(|entry| entry.expect("failed entry").path())
+    .collect();
+  paths.sort();
+
+  println!(
+    "{:<32} {:>12} {:>12} {:>8}",
+    "file", "tree", "table", "speedup"
+  );
+  for path in paths {
+    let gzip = fs::read(&path).expect("Failed to read file");
+    let stream = deflate_stream(&gzip);
+    let tree = time_inflate(stream, DecodeMode::Tree);
+    let table = time_inflate(stream, DecodeMode::Table);
+    println!(
+      "{:<32} {:>10.2?} {:>10.2?} {:>7.1}x",
+      path.file_name().unwrap().to_string_lossy(),
+      tree,
+      table,
+      tree.as_secs_f64() / table.as_secs_f64()
+    );
+  }
+}
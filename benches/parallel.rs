@@ -0,0 +1,81 @@
+// Compares decompressing a multi-member file on one thread, with `Gzip` and
+// with `par_decompress`, against `par_decompress` on several. Run with `cargo bench --bench parallel`.
+use gziprust::deflate::CompressionLevel;
+use gziprust::gzip::{self, GzEncoder, Gzip};
+use std::fs;
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const ITERATIONS: u32 = 5;
+
+// Data per member, about what pigz and BGZF use or less
+const MEMBER_SIZE: usize = 256 * 1024;
+
+// Several megabytes of the fixtures over and over, compressed a member at a
+// time, as `cat a.gz b.gz` or a parallel compressor would leave it
+fn multi_member() -> (Vec<u8>, usize) {
+  let mut src = vec![];
+  for name in &["testpng", "gunzip", "raven"] {
+    src.extend(fs::read(format!("tests/gzip/src/{}", name)).expect("Failed to read file"));
+  }
+  let data = src.repeat((16 << 20) / src.len());
+  let mut gzip = vec![];
+  for chunk in data.chunks(MEMBER_SIZE) {
+    let mut encoder = GzEncoder::with_level(vec![], CompressionLevel::default());
+    encoder.write_all(chunk).unwrap();
+    gzip.extend(encoder.finish().unwrap());
+  }
+  (gzip, data.len())
+}
+
+fn time(mut decompress: impl FnMut() -> u64, len: usize) -> Duration {
+  let start = Instant::now();
+  for _ in 0..ITERATIONS {
+    assert_eq!(decompress(), len as u64);
+  }
+  start.elapsed() / ITERATIONS
+}
+
+fn main() {
+  let (gzip, len) = multi_member();
+  println!(
+    "{} bytes in {} members, {} bytes of data",
+    gzip.len(),
+    len.div_ceil(MEMBER_SIZE),
+    len
+  );
+
+  let serial = time(
+    || {
+      Gzip::new(gzip.clone())
+        .expect("Failed to inflate")
+        .data
+        .len() as u64
+    },
+    len,
+  );
+  println!("{:<20} {:>10.2?}", "Gzip::new", serial);
+
+  let single = time(
+    || gzip::par_decompress(&gzip, 1, &mut io::sink()).expect("Failed to inflate"),
+    len,
+  );
+  println!("{:<20} {:>10.2?}", "1 thread", single);
+
+  let cores = thread::available_parallelism().map_or(4, |n| n.get());
+  let mut threads = 2;
+  while threads <= cores.max(4) {
+    let parallel = time(
+      || gzip::par_decompress(&gzip, threads, &mut io::sink()).expect("Failed to inflate"),
+      len,
+    );
+    println!(
+      "{:<20} {:>10.2?} {:>7.1}x",
+      format!("{} threads", threads),
+      parallel,
+      single.as_secs_f64() / parallel.as_secs_f64()
+    );
+    threads *= 2;
+  }
+}
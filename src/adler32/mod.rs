@@ -0,0 +1,109 @@
+// Adler-32 (RFC 1950): two sums mod the largest prime below 2^16, `a` of the
+// bytes (plus one), and `b` of the successive values of `a`. Laid out like
+// `crc32`, for zlib streams.
+pub fn adler32(bytes: &[u8]) -> u32 {
+  finalize(update_slice(initial_value(), bytes))
+}
+
+pub const fn initial_value() -> u32 {
+  1
+}
+
+pub fn update(cur_adler: u32, byte: u8) -> u32 {
+  let a = ((cur_adler & 0xffff) + u32::from(byte)) % MOD_ADLER;
+  let b = ((cur_adler >> 16) + a) % MOD_ADLER;
+  b << 16 | a
+}
+
+// Unlike CRC32, there is nothing to undo at the end; this is here so the two
+// can be used the same way
+pub const fn finalize(cur_adler: u32) -> u32 {
+  cur_adler
+}
+
+const MOD_ADLER: u32 = 65521;
+
+// The most bytes that can be summed before `b` could overflow a u32, taking
+// both sums to start just below MOD_ADLER (zlib's NMAX)
+const NMAX: usize = 5552;
+
+// The same as calling `update` on each byte, but taking the modulo only once
+// every NMAX bytes
+pub fn update_slice(cur_adler: u32, bytes: &[u8]) -> u32 {
+  let mut a = cur_adler & 0xffff;
+  let mut b = cur_adler >> 16;
+  for chunk in bytes.chunks(NMAX) {
+    for &byte in chunk {
+      a += u32::from(byte);
+      b += a;
+    }
+    a %= MOD_ADLER;
+    b %= MOD_ADLER;
+  }
+  b << 16 | a
+}
+
+// The Adler-32 of two pieces of data joined together, given the Adler-32 of
+// each and the length of the second
+pub fn combine(adler_a: u32, adler_b: u32, len_b: u64) -> u32 {
+  let rem = (len_b % u64::from(MOD_ADLER)) as u32;
+  let a_a = adler_a & 0xffff;
+  let b_a = adler_a >> 16;
+  let a_b = adler_b & 0xffff;
+  let b_b = adler_b >> 16;
+
+  // Each byte of the second piece added `a_a` to `b` once more than it
+  // would have alone, and `a` starts from `a_a` rather than 1
+  let a = (a_a + a_b + MOD_ADLER - 1) % MOD_ADLER;
+  let b = (b_a + b_b + rem * a_a % MOD_ADLER + MOD_ADLER - rem) % MOD_ADLER;
+  b << 16 | a
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_adler32() {
+    assert_eq!(adler32(b""), 1);
+    assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    // Big enough for both sums to wrap many times over
+    assert_eq!(adler32(&[0xff; 100_000]), 0x149a_302c);
+  }
+
+  #[test]
+  fn test_update_slice_matches_update() {
+    let data: Vec<u8> = (0..20_000_u32)
+      .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+      .collect();
+    for len in [0, 1, 15, 16, 17, NMAX - 1, NMAX, NMAX + 1, data.len()] {
+      let bytewise = data[..len]
+        .iter()
+        .fold(initial_value(), |adler, &byte| update(adler, byte));
+      assert_eq!(update_slice(initial_value(), &data[..len]), bytewise);
+    }
+    // Starting from sums just below the modulus is the worst case for NMAX
+    let start = (MOD_ADLER - 1) << 16 | (MOD_ADLER - 1);
+    let high = [0xff; NMAX * 2];
+    let bytewise = high.iter().fold(start, |adler, &byte| update(adler, byte));
+    assert_eq!(update_slice(start, &high), bytewise);
+  }
+
+  #[test]
+  fn test_combine() {
+    let data = include_bytes!("../../tests/gzip/src/raven");
+    for split in [0, 1, 1000, data.len() - 1, data.len()] {
+      let (first, second) = data.split_at(split);
+      assert_eq!(
+        combine(adler32(first), adler32(second), second.len() as u64),
+        adler32(data)
+      );
+    }
+    // Longer than the modulus
+    let long = vec![0xab; 200_000];
+    assert_eq!(
+      combine(adler32(b"abc"), adler32(&long), long.len() as u64),
+      adler32(&[&b"abc"[..], &long[..]].concat())
+    );
+  }
+}
@@ -0,0 +1,91 @@
+// CRC32 by folding 16 bytes at a time with carry-less multiplication, from
+// Intel's "Fast CRC Computation for Generic Polynomials Using PCLMULQDQ
+// Instruction". The constants are powers of x mod the (bit-reflected) CRC32
+// polynomial, from the paper.
+use super::update_slice_portable;
+use std::arch::x86_64::*;
+
+const K1: i64 = 0x1_5444_2bd4; // x^(4*128+32) mod P, for folding 64 bytes
+const K2: i64 = 0x1_c6e4_1596; // x^(4*128-32) mod P
+const K3: i64 = 0x1_7519_97d0; // x^(128+32) mod P, for folding 16 bytes
+const K4: i64 = 0x0_ccaa_009e; // x^(128-32) mod P
+const K5: i64 = 0x1_63cd_6124; // x^64 mod P, for going from 96 bits to 64
+const P_X: i64 = 0x1_db71_0641; // P itself
+const U_PRIME: i64 = 0x1_f701_1641; // x^64 / P, for the Barrett reduction
+
+// Below this the set-up costs more than it saves
+const MIN_LEN: usize = 128;
+
+pub fn is_available() -> bool {
+  is_x86_feature_detected!("pclmulqdq")
+    && is_x86_feature_detected!("sse2")
+    && is_x86_feature_detected!("sse4.1")
+}
+
+pub fn update_slice(cur_crc: u32, bytes: &[u8]) -> u32 {
+  if bytes.len() < MIN_LEN {
+    return update_slice_portable(cur_crc, bytes);
+  }
+  // Only called once `is_available` has checked for the instructions
+  unsafe { fold(cur_crc, bytes) }
+}
+
+#[target_feature(enable = "pclmulqdq", enable = "sse2", enable = "sse4.1")]
+unsafe fn fold(cur_crc: u32, mut bytes: &[u8]) -> u32 {
+  // Four 128-bit accumulators, so that the multiplications can overlap. The
+  // current CRC (which is kept inverted, as `update` does) is XORed into the
+  // first of them.
+  let mut x3 = load(&mut bytes);
+  let mut x2 = load(&mut bytes);
+  let mut x1 = load(&mut bytes);
+  let mut x0 = load(&mut bytes);
+  x3 = _mm_xor_si128(x3, _mm_cvtsi32_si128(cur_crc as i32));
+
+  let k1k2 = _mm_set_epi64x(K2, K1);
+  while bytes.len() >= 64 {
+    x3 = fold_into(x3, load(&mut bytes), k1k2);
+    x2 = fold_into(x2, load(&mut bytes), k1k2);
+    x1 = fold_into(x1, load(&mut bytes), k1k2);
+    x0 = fold_into(x0, load(&mut bytes), k1k2);
+  }
+
+  let k3k4 = _mm_set_epi64x(K4, K3);
+  let mut x = fold_into(x3, x2, k3k4);
+  x = fold_into(x, x1, k3k4);
+  x = fold_into(x, x0, k3k4);
+  while bytes.len() >= 16 {
+    x = fold_into(x, load(&mut bytes), k3k4);
+  }
+
+  // 128 bits down to 64
+  let low_32 = _mm_set_epi32(0, 0, 0, !0);
+  let x = _mm_xor_si128(_mm_clmulepi64_si128(x, k3k4, 0x10), _mm_srli_si128(x, 8));
+  let x = _mm_xor_si128(
+    _mm_clmulepi64_si128(_mm_and_si128(x, low_32), _mm_set_epi64x(0, K5), 0x00),
+    _mm_srli_si128(x, 4),
+  );
+
+  // Barrett reduction from 64 bits to the 32-bit remainder, which is in the
+  // upper half because the bits are reflected
+  let pu = _mm_set_epi64x(U_PRIME, P_X);
+  let t1 = _mm_clmulepi64_si128(_mm_and_si128(x, low_32), pu, 0x10);
+  let t2 = _mm_clmulepi64_si128(_mm_and_si128(t1, low_32), pu, 0x00);
+  let crc = _mm_extract_epi32(_mm_xor_si128(x, t2), 1) as u32;
+
+  update_slice_portable(crc, bytes)
+}
+
+// Multiply `acc` on by 128 bits' worth of x (mod P), and add in `next`
+#[target_feature(enable = "pclmulqdq", enable = "sse2")]
+unsafe fn fold_into(acc: __m128i, next: __m128i, keys: __m128i) -> __m128i {
+  let lo = _mm_clmulepi64_si128(acc, keys, 0x00);
+  let hi = _mm_clmulepi64_si128(acc, keys, 0x11);
+  _mm_xor_si128(_mm_xor_si128(next, lo), hi)
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn load(bytes: &mut &[u8]) -> __m128i {
+  let value = _mm_loadu_si128(bytes.as_ptr() as *const __m128i);
+  *bytes = &bytes[16..];
+  value
+}
@@ -0,0 +1,406 @@
+use std::mem;
+
+// Reads the deflate bit stream from a byte source. Bits are held in a 64-bit
+// accumulator, next bit lowest, which is topped up several bytes at a time
+// whenever it runs short. Running out of bytes is not final: if the source
+// has more by the next call, reading picks up where it left off.
+//
+// The accumulator may hold whole bytes that have been pulled from the source
+// but not read. `remaining_bytes`-style readers get them back by reading on
+// through the BitReader; callers that can seek their source take them back
+// with `unread_bytes`.
+pub struct BitReader<I: Iterator<Item = u8>> {
+  bytes: I,
+  bitbuf: u64,
+  bitcount: u32,
+  position: usize,             // the number of bits consumed so far
+  recorded: Option<Vec<bool>>, // the bits consumed since last taken, if recording
+}
+
+// Everything needed to return a BitReader to an earlier position, given that
+// its byte source is also returned to where it was
+#[derive(Clone, Copy)]
+pub struct Checkpoint {
+  bitbuf: u64,
+  bitcount: u32,
+  position: usize,
+}
+
+impl<I: Iterator<Item = u8>> BitReader<I> {
+  pub fn new(bytes: I) -> Self {
+    BitReader {
+      bytes,
+      bitbuf: 0,
+      bitcount: 0,
+      position: 0,
+      recorded: None,
+    }
+  }
+
+  pub fn checkpoint(&self) -> Checkpoint {
+    Checkpoint {
+      bitbuf: self.bitbuf,
+      bitcount: self.bitcount,
+      position: self.position,
+    }
+  }
+
+  pub fn rewind(&mut self, checkpoint: Checkpoint) {
+    self.bitbuf = checkpoint.bitbuf;
+    self.bitcount = checkpoint.bitcount;
+    self.position = checkpoint.position;
+    self.clear_recorded();
+  }
+
+  // The offset, in bits from the start of the stream, of the next bit
+  pub fn position(&self) -> usize {
+    self.position
+  }
+
+  // Count positions from here on, as for a new stream that follows on
+  // from the same source
+  pub fn reset_position(&mut self) {
+    self.position = 0;
+  }
+
+  pub fn get_ref(&self) -> &I {
+    &self.bytes
+  }
+
+  pub fn get_mut(&mut self) -> &mut I {
+    &mut self.bytes
+  }
+
+  // Keep a copy of every bit consumed from now on, for the visualizer. This
+  // costs a Vec push per bit, so it is off unless asked for.
+  pub fn start_recording(&mut self) {
+    self.recorded = Some(vec![]);
+  }
+
+  pub fn clear_recorded(&mut self) {
+    if let Some(recorded) = &mut self.recorded {
+      recorded.clear();
+    }
+  }
+
+  // The bits consumed since the last call, or nothing if not recording
+  pub fn take_recorded(&mut self) -> Vec<bool> {
+    match &mut self.recorded {
+      Some(recorded) => mem::take(recorded),
+      None => vec![],
+    }
+  }
+
+  // Top the accumulator up with as many whole bytes as fit
+  pub fn refill(&mut self) {
+    while self.bitcount <= 56 {
+      match self.bytes.next() {
+        Some(byte) => {
+          self.bitbuf |= u64::from(byte) << self.bitcount;
+          self.bitcount += 8;
+        }
+        None => break,
+      }
+    }
+  }
+
+  // The number of bits that can be consumed without another refill
+  pub fn available(&self) -> u32 {
+    self.bitcount
+  }
+
+  // The next `count` (at most 32) bits, next bit lowest, without consuming
+  // them. Bits beyond the end of the input read as 0; check `available` to
+  // tell whether there were enough.
+  pub fn peek(&mut self, count: u32) -> u32 {
+    if self.bitcount < count {
+      self.refill();
+    }
+    (self.bitbuf & ((1 << count) - 1)) as u32
+  }
+
+  // Drop `count` bits, which must already be available
+  pub fn consume(&mut self, count: u32) {
+    if let Some(recorded) = &mut self.recorded {
+      for i in 0..count {
+        recorded.push(self.bitbuf & (1 << i) != 0);
+      }
+    }
+    self.bitbuf >>= count;
+    self.bitcount -= count;
+    self.position += count as usize;
+  }
+
+  // Read `count` bits as a number, first bit lowest. Returns `None`, having
+  // consumed nothing, if there are fewer than `count` bits left.
+  pub fn read_bits_inv(&mut self, count: u8) -> Option<u32> {
+    let count = u32::from(count);
+    let value = self.peek(count);
+    if self.bitcount < count {
+      return None;
+    }
+    self.consume(count);
+    Some(value)
+  }
+
+  // Read `count` bits as a number, first bit highest
+  pub fn read_bits(&mut self, count: u8) -> Option<u32> {
+    let value = self.read_bits_inv(count)?;
+    Some(value.reverse_bits() >> (32 - u32::from(count)))
+  }
+
+  // Skip to the next byte boundary, as stored blocks and trailers start there
+  pub fn align_to_byte(&mut self) {
+    let extra = self.bitcount % 8;
+    self.bitbuf >>= extra;
+    self.bitcount -= extra;
+    self.position += extra as usize;
+  }
+
+  // At a byte boundary, append up to `count` bytes to `out`: first the
+  // whole bytes already pulled into the accumulator, then more straight from
+  // the source. Returns how many there were, fewer only if the source ran
+  // out. The bits are not recorded, as stored bytes have no DecodeItem.
+  pub fn read_bytes(&mut self, count: usize, out: &mut Vec<u8>) -> usize {
+    let mut read = 0;
+    while read < count && self.bitcount >= 8 {
+      out.push(self.bitbuf as u8);
+      self.bitbuf >>= 8;
+      self.bitcount -= 8;
+      read += 1;
+    }
+    let before = out.len();
+    out.extend(self.bytes.by_ref().take(count - read));
+    read += out.len() - before;
+    self.position += read * 8;
+    read
+  }
+
+  // Drop the whole bytes that have been pulled from the source but not read,
+  // returning how many there were so that the caller can step its source
+  // back over them
+  pub fn unread_bytes(&mut self) -> usize {
+    let whole = self.bitcount / 8;
+    self.bitcount -= whole * 8;
+    self.bitbuf &= (1 << self.bitcount) - 1;
+    whole as usize
+  }
+}
+
+impl<I: Iterator<Item = u8>> Iterator for BitReader<I> {
+  type Item = bool;
+  fn next(&mut self) -> Option<Self::Item> {
+    self.read_bits_inv(1).map(|bit| bit == 1)
+  }
+}
+
+#[allow(dead_code)]
+pub fn byte_to_bits(byte: u8) -> [bool; 8] {
+  [
+    byte & (1 << 7) != 0,
+    byte & (1 << 6) != 0,
+    byte & (1 << 5) != 0,
+    byte & (1 << 4) != 0,
+    byte & (1 << 3) != 0,
+    byte & (1 << 2) != 0,
+    byte & (1 << 1) != 0,
+    byte & 1 != 0,
+  ]
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_read_bits_inv_to_expected_file() {
+    // This is taken verbatim from https://commandlinefanatic.com/cgi-bin/showarticle.cgi?article=art053#figure3_bottom
+    let bytes = vec![0xbd, 0x1b, 0xfd, 0x6f, 0xda];
+    let mut iter = BitReader::new(bytes.into_iter());
+    assert_eq!(iter.read_bits_inv(1), Some(1));
+    assert_eq!(iter.read_bits_inv(2), Some(2));
+    assert_eq!(iter.read_bits_inv(5), Some(23));
+    assert_eq!(iter.read_bits_inv(5), Some(27));
+    assert_eq!(iter.read_bits_inv(4), Some(8));
+    assert_eq!(iter.position(), 17);
+  }
+
+  #[test]
+  fn test_read_past_end() {
+    let bytes = vec![0b0001_1000].into_iter();
+    let mut iter = BitReader::new(bytes);
+    assert_eq!(iter.read_bits_inv(6), Some(0b01_1000));
+    assert_eq!(iter.read_bits_inv(3), None);
+  }
+
+  #[test]
+  fn test_align_to_byte_position() {
+    let bytes = vec![0xff, 0b0000_0101].into_iter();
+    let mut iter = BitReader::new(bytes);
+    assert_eq!(iter.read_bits_inv(3), Some(0b111));
+    iter.align_to_byte();
+    assert_eq!(iter.position(), 8);
+    assert_eq!(iter.read_bits_inv(3), Some(0b101));
+    assert_eq!(iter.position(), 11);
+  }
+
+  #[test]
+  fn test_resumes_after_running_out() {
+    // A byte source that runs dry after its first byte (which the first refill
+    // finds, and the second is told), but has more data by the fourth call
+    let mut calls = 0;
+    let bytes = std::iter::from_fn(move || {
+      calls += 1;
+      match calls {
+        1 => Some(0b1010_1010),
+        4 => Some(0b0000_0011),
+        _ => None,
+      }
+    });
+    let mut iter = BitReader::new(bytes);
+    assert_eq!(iter.read_bits_inv(8), Some(0b1010_1010));
+    assert_eq!(iter.read_bits_inv(1), None);
+    assert_eq!(iter.read_bits_inv(2), Some(0b11));
+  }
+
+  #[test]
+  fn test_peek_and_consume() {
+    let bytes = vec![0b1100_0101, 0b0000_1111];
+    let mut iter = BitReader::new(bytes.into_iter());
+    assert_eq!(iter.available(), 0);
+    assert_eq!(iter.peek(3), 0b101);
+    // Both bytes were pulled in, though only three bits were asked for
+    assert_eq!(iter.available(), 16);
+    assert_eq!(iter.peek(20), 0b0000_1111_1100_0101);
+    iter.consume(3);
+    assert_eq!(iter.peek(13), 0b0_0001_1111_1000);
+    assert_eq!(iter.position(), 3);
+    assert_eq!(iter.read_bits_inv(5), Some(0b11000));
+  }
+
+  #[test]
+  fn test_unread_bytes() {
+    let bytes = vec![0b1100_0101, 0b0000_1111, 0xff];
+    let mut iter = BitReader::new(bytes.into_iter());
+    assert_eq!(iter.read_bits_inv(3), Some(0b101));
+    // The rest of the first byte stays; the other two go back
+    assert_eq!(iter.unread_bytes(), 2);
+    assert_eq!(iter.available(), 5);
+    assert_eq!(iter.read_bits_inv(5), Some(0b11000));
+    assert_eq!(iter.read_bits_inv(1), None);
+    assert_eq!(iter.position(), 8);
+  }
+
+  #[test]
+  fn test_read_bytes() {
+    let bytes = vec![0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b];
+    let mut iter = BitReader::new(bytes.into_iter());
+    assert_eq!(iter.read_bits_inv(8), Some(0x01));
+    let mut out = vec![];
+    // Some come from the accumulator and the rest from the source
+    assert_eq!(iter.read_bytes(8, &mut out), 8);
+    assert_eq!(out, vec![2, 3, 4, 5, 6, 7, 8, 9]);
+    assert_eq!(iter.position(), 72);
+    assert_eq!(iter.read_bytes(5, &mut out), 2);
+    assert_eq!(&out[8..], &[0x0a, 0x0b]);
+    assert_eq!(iter.read_bytes(1, &mut out), 0);
+  }
+
+  #[test]
+  fn test_recording() {
+    let bytes = vec![0b1100_0101, 0b0000_1111];
+    let mut iter = BitReader::new(bytes.into_iter());
+    iter.read_bits_inv(2);
+    assert_eq!(iter.take_recorded(), vec![]);
+
+    iter.start_recording();
+    iter.read_bits_inv(3);
+    assert_eq!(iter.take_recorded(), vec![true, false, false]);
+    iter.read_bits(2);
+    iter.clear_recorded();
+    iter.read_bits(2);
+    assert_eq!(iter.take_recorded(), vec![true, true]);
+    assert_eq!(iter.take_recorded(), vec![]);
+  }
+
+  #[test]
+  fn test_rewind() {
+    let bytes = vec![0b1100_0101, 0b0000_1111];
+    let mut iter = BitReader::new(bytes.into_iter());
+    assert_eq!(iter.read_bits_inv(3), Some(0b101));
+    let checkpoint = iter.checkpoint();
+    assert_eq!(iter.read_bits_inv(4), Some(0b1000));
+    iter.rewind(checkpoint);
+    assert_eq!(iter.position(), 3);
+    assert_eq!(iter.read_bits_inv(5), Some(0b11000));
+  }
+
+  #[test]
+  fn test_read_bits_inv() {
+    let bytes = vec![0b0001_1000].into_iter();
+    let mut iter = BitReader::new(bytes);
+    assert_eq!(iter.read_bits_inv(4), Some(8));
+    assert_eq!(iter.read_bits_inv(4), Some(1));
+
+    let bytes = vec![0b0101_1101].into_iter();
+    let mut iter = BitReader::new(bytes);
+    assert_eq!(iter.read_bits_inv(5), Some(0b11101));
+    assert_eq!(iter.read_bits_inv(3), Some(0b010));
+
+    let bytes = vec![0b1].into_iter();
+    let mut iter = BitReader::new(bytes);
+    assert_eq!(iter.read_bits_inv(1), Some(1));
+
+    let bytes = vec![0b0].into_iter();
+    let mut iter = BitReader::new(bytes);
+    assert_eq!(iter.read_bits_inv(1), Some(0));
+  }
+
+  #[test]
+  fn test_read_bits() {
+    let bytes = vec![0b0001_1000].into_iter();
+    let mut iter = BitReader::new(bytes);
+    assert_eq!(iter.read_bits(4), Some(1));
+    assert_eq!(iter.read_bits(4), Some(8));
+
+    let bytes = vec![0b1101_1101].into_iter();
+    let mut iter = BitReader::new(bytes);
+    assert_eq!(iter.read_bits(5), Some(0b10111));
+    assert_eq!(iter.read_bits(3), Some(0b011));
+
+    let bytes = vec![0b1].into_iter();
+    let mut iter = BitReader::new(bytes);
+    assert_eq!(iter.read_bits_inv(1), Some(1));
+
+    let bytes = vec![0b0].into_iter();
+    let mut iter = BitReader::new(bytes);
+    assert_eq!(iter.read_bits_inv(1), Some(0));
+  }
+
+  #[test]
+  fn test_byte_to_bits() {
+    let byte = 0;
+    assert_eq!(
+      byte_to_bits(byte),
+      [false, false, false, false, false, false, false, false]
+    );
+
+    let byte = 1;
+    assert_eq!(
+      byte_to_bits(byte),
+      [false, false, false, false, false, false, false, true]
+    );
+
+    let byte = 0b1111_1111;
+    assert_eq!(
+      byte_to_bits(byte),
+      [true, true, true, true, true, true, true, true]
+    );
+
+    let byte = 0b1010_0101;
+    assert_eq!(
+      byte_to_bits(byte),
+      [true, false, true, false, false, true, false, true]
+    );
+  }
+}
@@ -0,0 +1,120 @@
+// Writes the deflate bit stream: the mirror image of `BitReader`. Bits are
+// gathered in a 64-bit accumulator, first bit lowest, and moved out to
+// `bytes` a whole byte at a time.
+pub struct BitWriter {
+  bytes: Vec<u8>,
+  bitbuf: u64,
+  bitcount: u32,
+}
+
+impl BitWriter {
+  pub fn new() -> BitWriter {
+    BitWriter {
+      bytes: vec![],
+      bitbuf: 0,
+      bitcount: 0,
+    }
+  }
+
+  // Write the low `count` (at most 32) bits of `value`, lowest first
+  pub fn write_bits(&mut self, value: u32, count: u32) {
+    debug_assert!(count <= 32 && u64::from(value) < 1 << count);
+    self.bitbuf |= u64::from(value) << self.bitcount;
+    self.bitcount += count;
+    if self.bitcount >= 32 {
+      self
+        .bytes
+        .extend_from_slice(&(self.bitbuf as u32).to_le_bytes());
+      self.bitbuf >>= 32;
+      self.bitcount -= 32;
+    }
+  }
+
+  // Pad with zero bits up to the next byte boundary
+  pub fn align_to_byte(&mut self) {
+    let padding = (8 - self.bitcount % 8) % 8;
+    self.write_bits(0, padding);
+    while self.bitcount > 0 {
+      self.bytes.push(self.bitbuf as u8);
+      self.bitbuf >>= 8;
+      self.bitcount -= 8;
+    }
+  }
+
+  // Write whole bytes, as stored blocks do. Must be on a byte boundary.
+  pub fn write_bytes(&mut self, bytes: &[u8]) {
+    debug_assert_eq!(self.bitcount % 8, 0);
+    self.align_to_byte();
+    self.bytes.extend_from_slice(bytes);
+  }
+
+  // The number of bits written so far
+  pub fn position(&self) -> usize {
+    self.bytes.len() * 8 + self.bitcount as usize
+  }
+
+  // Take the whole bytes written so far, leaving any partial byte behind
+  pub fn take_bytes(&mut self) -> Vec<u8> {
+    while self.bitcount >= 8 {
+      self.bytes.push(self.bitbuf as u8);
+      self.bitbuf >>= 8;
+      self.bitcount -= 8;
+    }
+    std::mem::take(&mut self.bytes)
+  }
+}
+
+impl Default for BitWriter {
+  fn default() -> BitWriter {
+    BitWriter::new()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::deflate::bit_reader::BitReader;
+
+  #[test]
+  fn test_round_trip() {
+    let values: Vec<(u32, u32)> = (0..200_u32)
+      .map(|i| {
+        let count = i % 33;
+        let value = if count == 0 {
+          0
+        } else {
+          i.wrapping_mul(2_654_435_761).wrapping_mul(i) >> (32 - count)
+        };
+        (value, count)
+      })
+      .collect();
+
+    let mut writer = BitWriter::new();
+    for &(value, count) in &values {
+      writer.write_bits(value, count);
+    }
+    let bits: usize = values.iter().map(|&(_, count)| count as usize).sum();
+    assert_eq!(writer.position(), bits);
+    writer.align_to_byte();
+    let bytes = writer.take_bytes();
+    assert_eq!(bytes.len(), bits.div_ceil(8));
+
+    let mut reader = BitReader::new(bytes.into_iter());
+    for &(value, count) in &values {
+      assert_eq!(reader.read_bits_inv(count as u8), Some(value));
+    }
+  }
+
+  #[test]
+  fn test_align_and_write_bytes() {
+    let mut writer = BitWriter::new();
+    writer.write_bits(0b101, 3);
+    assert_eq!(writer.take_bytes(), vec![]);
+    writer.align_to_byte();
+    writer.write_bytes(&[0xab, 0xcd]);
+    writer.write_bits(1, 1);
+    assert_eq!(writer.take_bytes(), vec![0b101, 0xab, 0xcd]);
+    writer.align_to_byte();
+    assert_eq!(writer.take_bytes(), vec![1]);
+  }
+}
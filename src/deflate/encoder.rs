@@ -0,0 +1,645 @@
+use super::bit_writer::BitWriter;
+use super::huffman::{self, HuffmanCodes, CODE_LENGTH_ORDER};
+use super::lz77::{Matcher, Token};
+use super::window::WINDOW_SIZE;
+use super::BlockEncoding;
+
+// The most input that goes into one block: all a stored block can hold
+const BLOCK_SIZE: usize = 0xffff;
+
+const END_OF_BLOCK: usize = 256;
+const LITERAL_CODES: usize = 286;
+const DISTANCE_CODES: usize = 30;
+const CODE_LENGTH_CODES: usize = 19;
+
+// The first length and distance of each code, and how many extra bits
+// follow it, see https://tools.ietf.org/html/rfc1951#section-3.2.5
+const LENGTH_BASE: [u16; 29] = [
+  3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+  163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+  0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; DISTANCE_CODES] = [
+  1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049,
+  3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; DISTANCE_CODES] = [
+  0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+// How much effort to put into compression, from 0 (none: the data is only
+// stored) through 1 (fastest) to 9 (smallest output), as in zlib
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompressionLevel(u8);
+
+impl CompressionLevel {
+  // `None` if `level` is not 0-9
+  pub fn new(level: u8) -> Option<CompressionLevel> {
+    if level <= 9 {
+      Some(CompressionLevel(level))
+    } else {
+      None
+    }
+  }
+
+  pub fn none() -> CompressionLevel {
+    CompressionLevel(0)
+  }
+
+  pub fn fastest() -> CompressionLevel {
+    CompressionLevel(1)
+  }
+
+  pub fn best() -> CompressionLevel {
+    CompressionLevel(9)
+  }
+
+  pub fn level(self) -> u8 {
+    self.0
+  }
+}
+
+impl Default for CompressionLevel {
+  fn default() -> CompressionLevel {
+    CompressionLevel(6)
+  }
+}
+
+// Compresses data into a deflate stream. Input is gathered until there is a
+// block's worth, and each block is written as whichever of stored, fixed or
+// dynamic Huffman comes out smallest.
+pub struct Encoder {
+  matcher: Option<Matcher>, // none at level 0
+  buf: Vec<u8>,             // up to a window of earlier input, then input not yet encoded
+  base: usize,              // the position in the whole input of `buf[0]`
+  pending: usize,           // the index in `buf` of the first byte not yet encoded
+  tokens: Vec<Token>,
+  writer: BitWriter,
+  finished: bool,
+}
+
+impl Encoder {
+  pub fn new() -> Encoder {
+    Encoder::with_level(CompressionLevel::default())
+  }
+
+  pub fn with_level(level: CompressionLevel) -> Encoder {
+    Encoder {
+      matcher: match level.0 {
+        0 => None,
+        level => Some(Matcher::new(level)),
+      },
+      buf: vec![],
+      base: 0,
+      pending: 0,
+      tokens: vec![],
+      writer: BitWriter::new(),
+      finished: false,
+    }
+  }
+
+  // Compress as if `dictionary` had come before the input, so that the
+  // input can refer back to it. The decoder needs the same dictionary (see
+  // `Inflater::with_dictionary`).
+  pub fn with_dictionary(level: CompressionLevel, dictionary: &[u8]) -> Encoder {
+    let mut encoder = Encoder::with_level(level);
+    let dictionary = &dictionary[dictionary.len().saturating_sub(WINDOW_SIZE)..];
+    encoder.buf.extend_from_slice(dictionary);
+    encoder.pending = dictionary.len();
+    if let Some(matcher) = &mut encoder.matcher {
+      matcher.index(&encoder.buf);
+    }
+    encoder
+  }
+
+  pub fn write(&mut self, data: &[u8]) {
+    assert!(!self.finished, "write after finish");
+    self.buf.extend_from_slice(data);
+    while self.buf.len() - self.pending >= BLOCK_SIZE {
+      self.encode_block(BLOCK_SIZE, false);
+    }
+  }
+
+  // Encode the input so far as a block that is not the last, then write an
+  // empty stored block so that the output ends on a byte boundary, as
+  // zlib's Z_SYNC_FLUSH does. More can be written afterwards, or the output
+  // can be followed by the blocks of another stream.
+  pub fn sync_flush(&mut self) {
+    assert!(!self.finished, "flush after finish");
+    if self.pending < self.buf.len() {
+      self.encode_block(self.buf.len() - self.pending, false);
+    }
+    write_stored_block(&mut self.writer, &[], false);
+  }
+
+  // Encode whatever input is left as the final block. Nothing can be
+  // written after this.
+  pub fn finish(&mut self) {
+    if !self.finished {
+      self.encode_block(self.buf.len() - self.pending, true);
+      self.writer.align_to_byte();
+      self.finished = true;
+    }
+  }
+
+  // The compressed bytes written since the last call. Until `finish`, the
+  // last few bits are held back until there is a whole byte of them.
+  pub fn take_output(&mut self) -> Vec<u8> {
+    self.writer.take_bytes()
+  }
+
+  fn encode_block(&mut self, len: usize, is_last: bool) {
+    let end = self.pending + len;
+    let data = &self.buf[self.pending..end];
+    match &mut self.matcher {
+      Some(matcher) => {
+        self.tokens.clear();
+        matcher.tokenize(&self.buf, self.base, self.pending, end, &mut self.tokens);
+        write_block(&mut self.writer, &self.tokens, data, is_last);
+      }
+      None => write_stored_block(&mut self.writer, data, is_last),
+    }
+    self.pending = end;
+
+    // Only the last window of input can be referred back to
+    if self.pending > WINDOW_SIZE {
+      let drop = self.pending - WINDOW_SIZE;
+      self.buf.drain(..drop);
+      self.base += drop;
+      self.pending -= drop;
+    }
+  }
+}
+
+impl Default for Encoder {
+  fn default() -> Encoder {
+    Encoder::new()
+  }
+}
+
+// Compress `data` into a complete deflate stream
+pub fn encode(data: &[u8]) -> Vec<u8> {
+  encode_with_level(data, CompressionLevel::default())
+}
+
+pub fn encode_with_level(data: &[u8], level: CompressionLevel) -> Vec<u8> {
+  let mut encoder = Encoder::with_level(level);
+  encoder.write(data);
+  encoder.finish();
+  encoder.take_output()
+}
+
+fn length_code(length: u16) -> usize {
+  LENGTH_BASE.partition_point(|&base| base <= length) - 1
+}
+
+fn distance_code(distance: u16) -> usize {
+  DISTANCE_BASE.partition_point(|&base| base <= distance) - 1
+}
+
+// How often each literal/length and distance symbol occurs in `tokens`
+fn frequencies(tokens: &[Token]) -> (Vec<u32>, Vec<u32>) {
+  let mut literals = vec![0; LITERAL_CODES];
+  let mut distances = vec![0; DISTANCE_CODES];
+  for token in tokens {
+    match *token {
+      Token::Literal(byte) => literals[byte as usize] += 1,
+      Token::Match { length, distance } => {
+        literals[257 + length_code(length)] += 1;
+        distances[distance_code(distance)] += 1;
+      }
+    }
+  }
+  literals[END_OF_BLOCK] = 1;
+  (literals, distances)
+}
+
+// The bits needed for the data of a block with the given code lengths,
+// including extra bits
+fn data_cost(
+  (literal_freqs, distance_freqs): &(Vec<u32>, Vec<u32>),
+  literal_lengths: &[u8],
+  distance_lengths: &[u8],
+) -> usize {
+  let literals: usize = literal_freqs
+    .iter()
+    .enumerate()
+    .map(|(symbol, &freq)| {
+      let extra = if symbol > 256 {
+        LENGTH_EXTRA[symbol - 257]
+      } else {
+        0
+      };
+      freq as usize * usize::from(literal_lengths[symbol] + extra)
+    })
+    .sum();
+  let distances: usize = distance_freqs
+    .iter()
+    .enumerate()
+    .map(|(symbol, &freq)| {
+      freq as usize * usize::from(distance_lengths[symbol] + DISTANCE_EXTRA[symbol])
+    })
+    .sum();
+  literals + distances
+}
+
+// The header of a dynamic block: the literal/length and distance code
+// lengths, run-length encoded and then Huffman coded
+struct DynamicHeader {
+  literal_lengths: Vec<u8>,
+  distance_lengths: Vec<u8>,
+  symbols: Vec<(u8, u8)>, // code length symbols, with their extra bits
+  code_length_lengths: Vec<u8>,
+  hclen: usize, // how many code length code lengths are written
+}
+
+impl DynamicHeader {
+  fn new(literal_lengths: Vec<u8>, distance_lengths: Vec<u8>) -> DynamicHeader {
+    let lengths: Vec<u8> = literal_lengths
+      .iter()
+      .chain(&distance_lengths)
+      .cloned()
+      .collect();
+    let symbols = huffman::run_length_encode(&lengths);
+    let mut code_length_freqs = vec![0; CODE_LENGTH_CODES];
+    for &(symbol, _) in &symbols {
+      code_length_freqs[symbol as usize] += 1;
+    }
+    let code_length_lengths = huffman::code_lengths_from_frequencies(&code_length_freqs, 7);
+    // Trailing zeros (in the order they are written) can be left off
+    let hclen = CODE_LENGTH_ORDER
+      .iter()
+      .rposition(|&symbol| code_length_lengths[symbol] != 0)
+      .map_or(0, |last| last + 1)
+      .max(4);
+    DynamicHeader {
+      literal_lengths,
+      distance_lengths,
+      symbols,
+      code_length_lengths,
+      hclen,
+    }
+  }
+
+  fn cost(&self) -> usize {
+    let symbols: usize = self
+      .symbols
+      .iter()
+      .map(|&(symbol, _)| {
+        usize::from(
+          self.code_length_lengths[symbol as usize] + huffman::code_length_extra_bits(symbol),
+        )
+      })
+      .sum();
+    5 + 5 + 4 + 3 * self.hclen + symbols
+  }
+
+  fn write(&self, writer: &mut BitWriter) {
+    writer.write_bits(self.literal_lengths.len() as u32 - 257, 5);
+    writer.write_bits(self.distance_lengths.len() as u32 - 1, 5);
+    writer.write_bits(self.hclen as u32 - 4, 4);
+    for &symbol in &CODE_LENGTH_ORDER[..self.hclen] {
+      writer.write_bits(u32::from(self.code_length_lengths[symbol]), 3);
+    }
+    let codes = HuffmanCodes::from_code_lengths(&self.code_length_lengths);
+    for &(symbol, extra) in &self.symbols {
+      codes.write(symbol as usize, writer);
+      writer.write_bits(
+        u32::from(extra),
+        u32::from(huffman::code_length_extra_bits(symbol)),
+      );
+    }
+  }
+}
+
+// Code lengths for a dynamic block, with unused codes at the end dropped
+fn dynamic_lengths(freqs: &[u32], min_codes: usize) -> Vec<u8> {
+  let mut lengths = huffman::code_lengths_from_frequencies(freqs, 15);
+  if lengths.iter().all(|&len| len == 0) {
+    // A block with no matches still needs a distance code
+    lengths[0] = 1;
+  }
+  let used = lengths.iter().rposition(|&len| len != 0).unwrap_or(0) + 1;
+  lengths.truncate(used.max(min_codes));
+  lengths
+}
+
+fn write_block(writer: &mut BitWriter, tokens: &[Token], data: &[u8], is_last: bool) {
+  let freqs = frequencies(tokens);
+  let header = DynamicHeader::new(dynamic_lengths(&freqs.0, 257), dynamic_lengths(&freqs.1, 1));
+  let fixed_literals = huffman::fixed_literal_lengths();
+  let fixed_distances = [5; DISTANCE_CODES];
+
+  // Stored blocks start on a byte boundary, and give their length twice
+  let padding = (8 - (writer.position() + 3) % 8) % 8;
+  let stored_cost = padding + 32 + 8 * data.len();
+  let fixed_cost = data_cost(&freqs, &fixed_literals, &fixed_distances);
+  let dynamic_cost = header.cost()
+    + data_cost(
+      &freqs,
+      &pad_lengths(&header.literal_lengths, LITERAL_CODES),
+      &pad_lengths(&header.distance_lengths, DISTANCE_CODES),
+    );
+
+  let encoding = if stored_cost <= fixed_cost.min(dynamic_cost) {
+    BlockEncoding::Stored
+  } else if fixed_cost <= dynamic_cost {
+    BlockEncoding::HuffmanFixed
+  } else {
+    BlockEncoding::HuffmanDynamic
+  };
+
+  match encoding {
+    BlockEncoding::Stored => write_stored_block(writer, data, is_last),
+    BlockEncoding::HuffmanFixed => {
+      writer.write_bits(is_last as u32, 1);
+      writer.write_bits(1, 2);
+      write_tokens(
+        writer,
+        tokens,
+        &HuffmanCodes::from_code_lengths(&fixed_literals),
+        &HuffmanCodes::from_code_lengths(&fixed_distances),
+      );
+    }
+    BlockEncoding::HuffmanDynamic => {
+      writer.write_bits(is_last as u32, 1);
+      writer.write_bits(2, 2);
+      header.write(writer);
+      write_tokens(
+        writer,
+        tokens,
+        &HuffmanCodes::from_code_lengths(&header.literal_lengths),
+        &HuffmanCodes::from_code_lengths(&header.distance_lengths),
+      );
+    }
+  }
+}
+
+fn write_stored_block(writer: &mut BitWriter, data: &[u8], is_last: bool) {
+  writer.write_bits(is_last as u32, 1);
+  writer.write_bits(0, 2);
+  writer.align_to_byte();
+  let len = data.len() as u16;
+  writer.write_bytes(&len.to_le_bytes());
+  writer.write_bytes(&(!len).to_le_bytes());
+  writer.write_bytes(data);
+}
+
+// Code lengths for the whole alphabet, from the ones a header gives
+fn pad_lengths(lengths: &[u8], len: usize) -> Vec<u8> {
+  let mut lengths = lengths.to_vec();
+  lengths.resize(len, 0);
+  lengths
+}
+
+fn write_tokens(
+  writer: &mut BitWriter,
+  tokens: &[Token],
+  literals: &HuffmanCodes,
+  distances: &HuffmanCodes,
+) {
+  for token in tokens {
+    match *token {
+      Token::Literal(byte) => literals.write(byte as usize, writer),
+      Token::Match { length, distance } => {
+        let code = length_code(length);
+        literals.write(257 + code, writer);
+        writer.write_bits(
+          u32::from(length - LENGTH_BASE[code]),
+          u32::from(LENGTH_EXTRA[code]),
+        );
+        let code = distance_code(distance);
+        distances.write(code, writer);
+        writer.write_bits(
+          u32::from(distance - DISTANCE_BASE[code]),
+          u32::from(DISTANCE_EXTRA[code]),
+        );
+      }
+    }
+  }
+  literals.write(END_OF_BLOCK, writer);
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::deflate::{inflate, inflate_with_dictionary, Inflate, InflateErrorKind};
+  use std::fs;
+
+  fn round_trip(data: &[u8]) -> Vec<u8> {
+    let compressed = encode(data);
+    let result = inflate(&mut compressed.iter().cloned()).expect("Failed to inflate");
+    assert_eq!(result.data, data);
+    assert_eq!(result.compressed_len, compressed.len());
+    compressed
+  }
+
+  #[test]
+  fn test_length_and_distance_codes() {
+    assert_eq!(length_code(3), 0);
+    assert_eq!(length_code(10), 7);
+    assert_eq!(length_code(11), 8);
+    assert_eq!(length_code(12), 8);
+    assert_eq!(length_code(257), 27);
+    assert_eq!(length_code(258), 28);
+    assert_eq!(distance_code(1), 0);
+    assert_eq!(distance_code(4), 3);
+    assert_eq!(distance_code(6), 4);
+    assert_eq!(distance_code(32768), 29);
+  }
+
+  #[test]
+  fn test_empty() {
+    // A single empty fixed block
+    assert_eq!(round_trip(&[]), vec![0x03, 0x00]);
+  }
+
+  #[test]
+  fn test_block_types() {
+    // Incompressible data is stored
+    let mut state = 1_u32;
+    let noise: Vec<u8> = (0..1000)
+      .map(|_| {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (state >> 16) as u8
+      })
+      .collect();
+    let compressed = round_trip(&noise);
+    let result = inflate(&mut compressed.iter().cloned()).unwrap();
+    assert_eq!(result.blocks[0].encoding, BlockEncoding::Stored);
+
+    // Short text gets the fixed code, long text a dynamic one
+    let compressed = round_trip(b"hello, hello, hello!");
+    let result = inflate(&mut compressed.iter().cloned()).unwrap();
+    assert_eq!(result.blocks[0].encoding, BlockEncoding::HuffmanFixed);
+
+    let text = include_bytes!("../../tests/gzip/src/gunzip");
+    let compressed = round_trip(text);
+    let result = inflate(&mut compressed.iter().cloned()).unwrap();
+    assert_eq!(result.blocks[0].encoding, BlockEncoding::HuffmanDynamic);
+  }
+
+  #[test]
+  fn test_fixtures() {
+    for entry in fs::read_dir("tests/gzip/src/").expect("failed to read src dir") {
+      let src = fs::read(entry.expect("failed entry").path()).expect("Failed to read file");
+      let compressed = round_trip(&src);
+      assert!(compressed.len() <= src.len() + 5 * (src.len() / BLOCK_SIZE + 1));
+    }
+  }
+
+  #[test]
+  fn test_long_matches_across_blocks() {
+    // Several blocks, with matches reaching back into earlier ones
+    let src = include_bytes!("../../tests/gzip/src/raven");
+    let data: Vec<u8> = src
+      .iter()
+      .cycle()
+      .take(BLOCK_SIZE * 3 + 17)
+      .cloned()
+      .collect();
+    let compressed = round_trip(&data);
+    assert!(compressed.len() < src.len());
+  }
+
+  #[test]
+  fn test_level_out_of_range() {
+    assert_eq!(CompressionLevel::new(9), Some(CompressionLevel::best()));
+    assert_eq!(CompressionLevel::new(10), None);
+  }
+
+  #[test]
+  fn test_levels() {
+    let text = include_bytes!("../../tests/gzip/src/gunzip");
+    let sizes: Vec<usize> = (0..=9)
+      .map(|level| {
+        let compressed = encode_with_level(text, CompressionLevel::new(level).unwrap());
+        let result = inflate(&mut compressed.iter().cloned()).expect("Failed to inflate");
+        assert_eq!(&result.data[..], &text[..]);
+        if level == 0 {
+          assert!(result
+            .blocks
+            .iter()
+            .all(|block| block.encoding == BlockEncoding::Stored));
+        }
+        compressed.len()
+      })
+      .collect();
+    // Stored is biggest, and the fastest level is bigger than the default,
+    // which is no smaller than the best
+    assert!(sizes[0] > text.len());
+    assert!(sizes[1] > sizes[6]);
+    assert!(sizes[6] >= sizes[9]);
+  }
+
+  #[test]
+  fn test_levels_fixtures() {
+    for entry in fs::read_dir("tests/gzip/src/").expect("failed to read src dir") {
+      let src = fs::read(entry.expect("failed entry").path()).expect("Failed to read file");
+      for level in 0..=9 {
+        let compressed = encode_with_level(&src, CompressionLevel::new(level).unwrap());
+        let result = inflate(&mut compressed.iter().cloned()).expect("Failed to inflate");
+        assert_eq!(result.data, src);
+      }
+    }
+  }
+
+  #[test]
+  fn test_small_writes() {
+    let src = include_bytes!("../../tests/gzip/src/gunzip");
+    let mut encoder = Encoder::new();
+    let mut compressed = vec![];
+    for chunk in src.chunks(100) {
+      encoder.write(chunk);
+      compressed.extend(encoder.take_output());
+    }
+    encoder.finish();
+    compressed.extend(encoder.take_output());
+    assert_eq!(compressed, encode(src));
+  }
+
+  #[test]
+  fn test_sync_flush() {
+    let src = include_bytes!("../../tests/gzip/src/gunzip");
+    let (first, second) = src.split_at(5000);
+    let mut encoder = Encoder::new();
+    encoder.write(first);
+    encoder.sync_flush();
+    let flushed = encoder.take_output();
+    // An empty stored block, which leaves the output byte-aligned
+    assert!(flushed.ends_with(&[0x00, 0x00, 0xff, 0xff]));
+    let mut partial = Inflate::new(flushed.iter().cloned());
+    let mut buf = vec![0; first.len()];
+    let mut read = 0;
+    while read < first.len() {
+      read += partial.read(&mut buf[read..]).expect("Failed to inflate");
+    }
+    assert_eq!(&buf[..read], first);
+
+    // Flushing again with no new input only adds another empty block
+    encoder.sync_flush();
+    assert_eq!(encoder.take_output(), [0x00, 0x00, 0x00, 0xff, 0xff]);
+
+    encoder.write(second);
+    encoder.finish();
+    let compressed = [
+      flushed,
+      vec![0x00, 0x00, 0x00, 0xff, 0xff],
+      encoder.take_output(),
+    ]
+    .concat();
+    let result = inflate(&mut compressed.iter().cloned()).expect("Failed to inflate");
+    assert_eq!(&result.data[..], &src[..]);
+  }
+
+  #[test]
+  fn test_dictionary() {
+    let src = include_bytes!("../../tests/gzip/src/raven");
+    let (dictionary, data) = src.split_at(1500);
+    for level in [0, 1, 6, 9] {
+      let level = CompressionLevel::new(level).unwrap();
+      let mut encoder = Encoder::with_dictionary(level, dictionary);
+      encoder.write(data);
+      encoder.finish();
+      let compressed = encoder.take_output();
+
+      let result = inflate_with_dictionary(&mut compressed.iter().cloned(), dictionary)
+        .expect("Failed to inflate");
+      assert_eq!(result.data, data);
+      if level.level() > 0 {
+        assert!(compressed.len() < encode_with_level(data, level).len());
+      }
+    }
+
+    // Without the dictionary, the first match into it is too far back
+    let mut encoder = Encoder::with_dictionary(CompressionLevel::default(), dictionary);
+    encoder.write(data);
+    encoder.finish();
+    match inflate(&mut encoder.take_output().into_iter()) {
+      Err(error) => assert!(matches!(
+        error.kind,
+        InflateErrorKind::DistanceTooFar { .. }
+      )),
+      Ok(_) => panic!("Expected inflate to fail"),
+    }
+  }
+
+  #[test]
+  fn test_long_dictionary() {
+    // Only the last window of the dictionary is kept
+    let src = include_bytes!("../../tests/gzip/src/gunzip");
+    let dictionary: Vec<u8> = (0..WINDOW_SIZE as u32)
+      .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+      .chain(src.iter().cloned())
+      .collect();
+    let mut encoder = Encoder::with_dictionary(CompressionLevel::default(), &dictionary);
+    encoder.write(src);
+    encoder.finish();
+    let compressed = encoder.take_output();
+    assert!(compressed.len() * 10 < encode(src).len());
+    let result = inflate_with_dictionary(&mut compressed.iter().cloned(), &dictionary).unwrap();
+    assert_eq!(&result.data[..], &src[..]);
+  }
+}
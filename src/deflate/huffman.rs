@@ -1,3 +1,7 @@
+use super::bit_reader::BitReader;
+use super::bit_writer::BitWriter;
+use super::InflateErrorKind;
+
 #[derive(Clone, Debug)]
 pub struct TreeNode {
   len: u32,
@@ -17,21 +21,78 @@ pub enum HuffmanEncoding {
   Dynamic,
 }
 
+// How Huffman codes are decoded. `Tree` walks a `HuffmanNode` one bit at a
+// time; `Table` looks codes up several bits at a time in a `HuffmanTable`.
+// Both record the same bits for each DecodeItem.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DecodeMode {
+  Tree,
+  #[default]
+  Table,
+}
+
+pub enum Decoder {
+  Tree(HuffmanNode),
+  Table(HuffmanTable),
+}
+
+impl Decoder {
+  pub fn from_code_lengths(code_lengths: &[u8], mode: DecodeMode) -> Decoder {
+    match mode {
+      DecodeMode::Tree => Decoder::Tree(HuffmanNode::from_code_lengths(code_lengths)),
+      DecodeMode::Table => Decoder::Table(HuffmanTable::from_code_lengths(code_lengths)),
+    }
+  }
+
+  pub fn from_header_code_lengths(code_lengths: Vec<u8>, mode: DecodeMode) -> Decoder {
+    match mode {
+      DecodeMode::Tree => Decoder::Tree(HuffmanNode::from_header_code_lengths(code_lengths)),
+      DecodeMode::Table => Decoder::Table(HuffmanTable::from_code_lengths(&header_code_lengths(
+        &code_lengths,
+      ))),
+    }
+  }
+
+  pub fn fixed(mode: DecodeMode) -> Decoder {
+    match mode {
+      DecodeMode::Tree => Decoder::Tree(HuffmanNode::fixed()),
+      DecodeMode::Table => Decoder::Table(HuffmanTable::fixed()),
+    }
+  }
+
+  pub fn decode<I: Iterator<Item = u8>>(
+    &self,
+    bits: &mut BitReader<I>,
+  ) -> Result<u32, InflateErrorKind> {
+    match self {
+      Decoder::Tree(node) => node.decode_stream(bits),
+      Decoder::Table(table) => table.decode(bits),
+    }
+  }
+}
+
 impl HuffmanNode {
-  pub fn decode_stream<I: Iterator<Item = bool>>(&self, bits: &mut I) -> Option<u32> {
-    match self.code {
-      Some(v) => Some(v),
-      None => match bits.next() {
-        Some(true) => match &self.one {
-          Some(node) => node.decode_stream(bits),
-          None => panic!("Unexpected decode_stream traversal looking for ONE"),
-        },
-        Some(false) => match &self.zero {
-          Some(node) => node.decode_stream(bits),
-          None => panic!("Unexpected decode_stream traversal looking for ZERO"),
-        },
-        None => None,
-      },
+  // Walk the tree one bit at a time until reaching a leaf. Incomplete trees
+  // (which RFC 1951 permits) have bit sequences that lead nowhere, and those
+  // are reported as `InvalidCode`.
+  pub fn decode_stream<I: Iterator<Item = bool>>(
+    &self,
+    bits: &mut I,
+  ) -> Result<u32, InflateErrorKind> {
+    let mut node = self;
+    loop {
+      if let Some(v) = node.code {
+        return Ok(v);
+      }
+      let next = match bits.next() {
+        Some(true) => &node.one,
+        Some(false) => &node.zero,
+        None => return Err(InflateErrorKind::UnexpectedEnd),
+      };
+      node = match next {
+        Some(next) => next,
+        None => return Err(InflateErrorKind::InvalidCode),
+      };
     }
   }
 
@@ -91,7 +152,7 @@ impl HuffmanNode {
 
     // build the tree
     let mut root = HuffmanNode::default();
-    for n in 0..=(ranges[range_len - 1 as usize].end) {
+    for n in 0..=(ranges[range_len - 1].end) {
       let mut node = &mut root;
       if tree[n as usize].len != 0 {
         let mut bits = tree[n as usize].len;
@@ -124,6 +185,389 @@ impl HuffmanNode {
   }
 }
 
+// A set of code lengths can only be turned into a prefix code if it does not
+// use more codes of a given length than are available (the Kraft inequality).
+// Incomplete codes are allowed, e.g. a distance tree with a single code.
+pub fn check_code_lengths(code_lengths: &[u8]) -> Result<(), InflateErrorKind> {
+  const MAX_BITS: u8 = 15;
+  let mut available: i32 = 1;
+  for len in 1..=MAX_BITS {
+    available <<= 1;
+    available -= code_lengths.iter().filter(|&&l| l == len).count() as i32;
+    if available < 0 {
+      return Err(InflateErrorKind::OversubscribedCodeLengths);
+    }
+  }
+  Ok(())
+}
+
+// Codes up to this long are decoded with a single lookup; longer ones go on
+// to a second-level table. 9 bits covers the whole fixed literal/length code.
+const ROOT_BITS: u8 = 9;
+
+// The longest code deflate allows, and so the most bits a lookup can need
+const MAX_CODE_LENGTH: u32 = 15;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TableEntry {
+  Invalid,
+  Symbol { symbol: u16, len: u8 },
+  Subtable { offset: u16, bits: u8 },
+}
+
+// A lookup table indexed by the next `root_bits` bits of the stream (which,
+// as Huffman codes are packed starting from their most significant bit, are
+// the codes bit-reversed). Codes longer than `root_bits` point to a subtable
+// indexed by the bits that follow.
+#[derive(Debug, PartialEq)]
+pub struct HuffmanTable {
+  root_bits: u8,
+  entries: Vec<TableEntry>,
+}
+
+impl HuffmanTable {
+  pub fn from_code_lengths(code_lengths: &[u8]) -> HuffmanTable {
+    let max_len = code_lengths.iter().cloned().max().unwrap_or(0);
+    let root_bits = max_len.min(ROOT_BITS);
+    let codes = canonical_codes(code_lengths);
+
+    let mut entries = vec![TableEntry::Invalid; 1 << root_bits];
+    // The longest code that shares each root-table prefix, for sizing subtables
+    let mut subtable_bits = vec![0; 1 << root_bits];
+    for (symbol, &len) in code_lengths.iter().enumerate() {
+      if len > root_bits {
+        let prefix = reverse_bits(codes[symbol], len) as usize & ((1 << root_bits) - 1);
+        subtable_bits[prefix] = subtable_bits[prefix].max(len - root_bits);
+      }
+    }
+    for (prefix, &bits) in subtable_bits.iter().enumerate() {
+      if bits > 0 {
+        entries[prefix] = TableEntry::Subtable {
+          offset: entries.len() as u16,
+          bits,
+        };
+        entries.extend(vec![TableEntry::Invalid; 1 << bits]);
+      }
+    }
+
+    for (symbol, &len) in code_lengths.iter().enumerate() {
+      if len == 0 {
+        continue;
+      }
+      let entry = TableEntry::Symbol {
+        symbol: symbol as u16,
+        len,
+      };
+      let reversed = reverse_bits(codes[symbol], len) as usize;
+      // Fill every slot whose low bits are this code, whatever follows it
+      let (start, end, step) = if len <= root_bits {
+        (reversed, 1 << root_bits, 1 << len)
+      } else {
+        let prefix = reversed & ((1 << root_bits) - 1);
+        match entries[prefix] {
+          TableEntry::Subtable { offset, bits } => {
+            let offset = offset as usize;
+            (
+              offset + (reversed >> root_bits),
+              offset + (1 << bits),
+              1 << (len - root_bits),
+            )
+          }
+          _ => unreachable!("No subtable for long code"),
+        }
+      };
+      for slot in (start..end).step_by(step) {
+        entries[slot] = entry;
+      }
+    }
+
+    HuffmanTable { root_bits, entries }
+  }
+
+  pub fn fixed() -> HuffmanTable {
+    HuffmanTable::from_code_lengths(&fixed_literal_lengths())
+  }
+
+  // Look the next code up in the root table, and then in its subtable if the
+  // code is longer than `root_bits`
+  pub fn decode<I: Iterator<Item = u8>>(
+    &self,
+    bits: &mut BitReader<I>,
+  ) -> Result<u32, InflateErrorKind> {
+    let root_bits = u32::from(self.root_bits);
+    let buf = bits.peek(MAX_CODE_LENGTH);
+    let available = bits.available();
+    let mut entry = self.entries[(buf & ((1 << root_bits) - 1)) as usize];
+    let mut needed = root_bits;
+    if let TableEntry::Subtable {
+      offset,
+      bits: sub_bits,
+    } = entry
+    {
+      let sub_index = (buf >> root_bits) as usize & ((1 << sub_bits) - 1);
+      entry = self.entries[offset as usize + sub_index];
+      needed += u32::from(sub_bits);
+    }
+    match entry {
+      TableEntry::Symbol { symbol, len } if u32::from(len) <= available => {
+        bits.consume(u32::from(len));
+        Ok(u32::from(symbol))
+      }
+      TableEntry::Symbol { .. } => Err(InflateErrorKind::UnexpectedEnd),
+      // Every bit that could matter is here, and matches no code
+      _ if needed <= available => Err(InflateErrorKind::InvalidCode),
+      _ => Err(InflateErrorKind::UnexpectedEnd),
+    }
+  }
+}
+
+// The canonical code for each symbol, see
+// https://tools.ietf.org/html/rfc1951#section-3.2.2
+fn canonical_codes(code_lengths: &[u8]) -> Vec<u32> {
+  const MAX_BITS: usize = 15;
+  let mut bl_count = [0; MAX_BITS + 1];
+  for &len in code_lengths {
+    bl_count[len as usize] += 1;
+  }
+  bl_count[0] = 0;
+
+  let mut next_code = [0; MAX_BITS + 1];
+  let mut code = 0;
+  for bits in 1..=MAX_BITS {
+    code = (code + bl_count[bits - 1]) << 1;
+    next_code[bits] = code;
+  }
+
+  code_lengths
+    .iter()
+    .map(|&len| {
+      let code = next_code[len as usize];
+      if len != 0 {
+        next_code[len as usize] += 1;
+      }
+      code
+    })
+    .collect()
+}
+
+fn reverse_bits(code: u32, len: u8) -> u32 {
+  code.reverse_bits() >> (32 - u32::from(len))
+}
+
+// The code lengths of the fixed literal/length code, see
+// https://tools.ietf.org/html/rfc1951#section-3.2.6
+pub fn fixed_literal_lengths() -> Vec<u8> {
+  let mut code_lengths = vec![8; 144];
+  code_lengths.extend_from_slice(&[9; 112]);
+  code_lengths.extend_from_slice(&[7; 24]);
+  code_lengths.extend_from_slice(&[8; 8]);
+  code_lengths
+}
+
+// The encoder's side of a Huffman code: each symbol's code, bit-reversed so
+// that it can be written first bit lowest, and its length
+pub struct HuffmanCodes {
+  codes: Vec<u16>,
+  lengths: Vec<u8>,
+}
+
+impl HuffmanCodes {
+  pub fn from_code_lengths(code_lengths: &[u8]) -> HuffmanCodes {
+    let codes = canonical_codes(code_lengths)
+      .iter()
+      .zip(code_lengths)
+      .map(|(&code, &len)| {
+        if len == 0 {
+          0
+        } else {
+          reverse_bits(code, len) as u16
+        }
+      })
+      .collect();
+    HuffmanCodes {
+      codes,
+      lengths: code_lengths.to_vec(),
+    }
+  }
+
+  pub fn write(&self, symbol: usize, writer: &mut BitWriter) {
+    debug_assert!(self.lengths[symbol] > 0, "no code for symbol {}", symbol);
+    writer.write_bits(
+      u32::from(self.codes[symbol]),
+      u32::from(self.lengths[symbol]),
+    );
+  }
+}
+
+// Optimal code lengths for symbols that occur with the given frequencies,
+// none of them longer than `max_len`, found by package-merge (Larmore and
+// Hirschberg). Symbols that never occur get no code.
+//
+// Package-merge works on `max_len` lists. The first is the symbols sorted by
+// frequency; each later one is the symbols again, merged with the pairs
+// ("packages") of items from the list before. Taking the 2n - 2 lightest
+// items from the last list, every time a symbol appears among them
+// (directly, or inside a package) adds one to its code length.
+pub fn code_lengths_from_frequencies(frequencies: &[u32], max_len: u8) -> Vec<u8> {
+  let mut lengths = vec![0; frequencies.len()];
+  let mut symbols: Vec<usize> = (0..frequencies.len())
+    .filter(|&symbol| frequencies[symbol] > 0)
+    .collect();
+  if symbols.len() == 1 {
+    // A code still needs a bit to say which symbol it is
+    lengths[symbols[0]] = 1;
+  }
+  if symbols.len() <= 1 {
+    return lengths;
+  }
+  assert!(
+    symbols.len() <= 1 << max_len,
+    "{} symbols do not fit in {}-bit codes",
+    symbols.len(),
+    max_len
+  );
+  symbols.sort_by_key(|&symbol| frequencies[symbol]);
+
+  let wanted = 2 * symbols.len() - 2;
+  let leaves: Vec<PackageItem> = symbols
+    .iter()
+    .map(|&symbol| PackageItem {
+      weight: u64::from(frequencies[symbol]),
+      symbol: Some(symbol),
+    })
+    .collect();
+  let mut lists = vec![leaves.clone()];
+  for _ in 1..max_len {
+    let previous = lists.last().unwrap();
+    let packages = previous.chunks_exact(2).map(|pair| PackageItem {
+      weight: pair[0].weight + pair[1].weight,
+      symbol: None,
+    });
+    let mut list = merge_by_weight(&leaves, packages);
+    list.truncate(wanted);
+    lists.push(list);
+  }
+
+  // Walk back through the lists: the packages taken from one list account
+  // for twice as many items from the one before
+  let mut count = wanted;
+  for list in lists.iter().rev() {
+    let mut packages = 0;
+    for item in &list[..count] {
+      match item.symbol {
+        Some(symbol) => lengths[symbol] += 1,
+        None => packages += 1,
+      }
+    }
+    count = 2 * packages;
+  }
+  lengths
+}
+
+#[derive(Clone, Copy)]
+struct PackageItem {
+  weight: u64,
+  symbol: Option<usize>, // `None` for a package
+}
+
+// Merge two lists sorted by weight, with leaves first among equals
+fn merge_by_weight(
+  leaves: &[PackageItem],
+  packages: impl Iterator<Item = PackageItem>,
+) -> Vec<PackageItem> {
+  let mut merged = Vec::with_capacity(leaves.len() * 2);
+  let mut packages = packages.peekable();
+  let mut leaves = leaves.iter().peekable();
+  loop {
+    let take_leaf = match (leaves.peek(), packages.peek()) {
+      (Some(leaf), Some(package)) => leaf.weight <= package.weight,
+      (Some(_), None) => true,
+      (None, Some(_)) => false,
+      (None, None) => return merged,
+    };
+    if take_leaf {
+      merged.push(*leaves.next().unwrap());
+    } else {
+      merged.push(packages.next().unwrap());
+    }
+  }
+}
+
+// The code length alphabet's repeat codes, see
+// https://tools.ietf.org/html/rfc1951#section-3.2.7
+pub const REPEAT_PREVIOUS: u8 = 16; // 3-6 copies of the previous length
+pub const REPEAT_ZERO: u8 = 17; // 3-10 zeros
+pub const REPEAT_ZERO_LONG: u8 = 18; // 11-138 zeros
+
+// The number of extra bits after each code length symbol
+pub fn code_length_extra_bits(symbol: u8) -> u8 {
+  match symbol {
+    REPEAT_PREVIOUS => 2,
+    REPEAT_ZERO => 3,
+    REPEAT_ZERO_LONG => 7,
+    _ => 0,
+  }
+}
+
+// Run-length encode code lengths into the symbols of the code length
+// alphabet, each with the value of its extra bits, as a dynamic block header
+// gives them. This follows zlib's `scan_tree`, except that it takes the
+// literal/length and distance lengths as one sequence, since runs may carry
+// on from one into the other.
+pub fn run_length_encode(lengths: &[u8]) -> Vec<(u8, u8)> {
+  let mut symbols = vec![];
+  let mut previous = None;
+  let mut i = 0;
+  while i < lengths.len() {
+    let len = lengths[i];
+    let mut run = lengths[i..].iter().take_while(|&&l| l == len).count();
+    if len == 0 && run >= 3 {
+      run = run.min(138);
+      if run <= 10 {
+        symbols.push((REPEAT_ZERO, (run - 3) as u8));
+      } else {
+        symbols.push((REPEAT_ZERO_LONG, (run - 11) as u8));
+      }
+      i += run;
+    } else if len != 0 && (run >= 4 || (run >= 3 && previous == Some(len))) {
+      // A repeat copies the length before it, so that has to go first
+      if previous != Some(len) {
+        symbols.push((len, 0));
+        i += 1;
+        run -= 1;
+      }
+      run = run.min(6);
+      symbols.push((REPEAT_PREVIOUS, (run - 3) as u8));
+      i += run;
+    } else {
+      symbols.push((len, 0));
+      i += 1;
+    }
+    previous = Some(len);
+  }
+  symbols
+}
+
+// The DEFLATE spec defines a special ordering of the code lengths
+// for the initial, encoded huffman tree that is used to decode
+// the literals/lengths and distances trees
+fn header_code_lengths(keys: &[u8]) -> [u8; MAX_HEADER_CODE_KEYS] {
+  assert!(keys.len() <= MAX_HEADER_CODE_KEYS);
+
+  let mut code_lengths = [0; MAX_HEADER_CODE_KEYS];
+  for i in 0..keys.len() {
+    code_lengths[CODE_LENGTH_ORDER[i]] = keys[i];
+  }
+  code_lengths
+}
+
+// The order in which a dynamic block header gives the code length code lengths
+pub const CODE_LENGTH_ORDER: [usize; MAX_HEADER_CODE_KEYS] = [
+  16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const MAX_HEADER_CODE_KEYS: usize = 19;
+
 #[allow(dead_code)]
 pub fn fixed_byte_bit_lengths() -> Vec<u8> {
   let mut byte_bit_lengths = vec![8; 144];
@@ -185,22 +629,8 @@ impl HuffmanRange {
     ranges
   }
 
-  // The DEFLATE spec defines a special ordering of the code lengths
-  // for the initial, encoded huffman tree that is used to decode
-  // the literals/lengths and distances trees
   fn from_header_code_keys(keys: &[u8]) -> Vec<HuffmanRange> {
-    const MAX_HEADER_CODE_KEYS: usize = 19;
-    assert!(keys.len() <= MAX_HEADER_CODE_KEYS);
-
-    const HUFFMAN_LENGTH_OFFSETS: [usize; MAX_HEADER_CODE_KEYS] = [
-      16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
-    ];
-    let mut code_lengths = [0; MAX_HEADER_CODE_KEYS];
-    for i in 0..keys.len() {
-      code_lengths[HUFFMAN_LENGTH_OFFSETS[i]] = keys[i];
-    }
-
-    HuffmanRange::from_code_lengths(&code_lengths)
+    HuffmanRange::from_code_lengths(&header_code_lengths(keys))
   }
 }
 
@@ -309,6 +739,213 @@ mod tests {
     code_lengths
   }
 
+  #[test]
+  fn test_decode_stream() {
+    let root = HuffmanNode::fixed();
+    let mut bits = to_bits(0b0011_1001, 8).into_iter();
+    assert_eq!(root.decode_stream(&mut bits), Ok(9));
+
+    let mut bits = to_bits(0b0011, 4).into_iter();
+    assert_eq!(
+      root.decode_stream(&mut bits),
+      Err(InflateErrorKind::UnexpectedEnd)
+    );
+
+    // A single 1-bit code leaves the "1" branch empty
+    let root = HuffmanNode::from_code_lengths(&[0, 1]);
+    assert_eq!(root.decode_stream(&mut vec![false].into_iter()), Ok(1));
+    assert_eq!(
+      root.decode_stream(&mut vec![true].into_iter()),
+      Err(InflateErrorKind::InvalidCode)
+    );
+  }
+
+  // Pack bits in stream order into bytes, least-significant bit first
+  fn to_stream(bits: &[bool]) -> Vec<u8> {
+    let mut bytes = vec![0; bits.len().div_ceil(8)];
+    for (i, &bit) in bits.iter().enumerate() {
+      bytes[i / 8] |= (bit as u8) << (i % 8);
+    }
+    bytes
+  }
+
+  // Decode every code with both the tree and the table, checking they agree
+  // with each other and with the canonical codes
+  fn check_table(code_lengths: &[u8]) {
+    let table = HuffmanTable::from_code_lengths(code_lengths);
+    let tree = HuffmanNode::from_code_lengths(code_lengths);
+    let codes = canonical_codes(code_lengths);
+    for (symbol, &len) in code_lengths.iter().enumerate() {
+      if len == 0 {
+        continue;
+      }
+      let code_bits = to_bits(codes[symbol], len as usize);
+      assert_eq!(tree.decode(&code_bits), Some(symbol as u32));
+
+      // Follow the code with some other bits, which must be left alone
+      let mut stream_bits = code_bits.clone();
+      stream_bits.extend_from_slice(&[true, false, true]);
+      let mut bits = BitReader::new(to_stream(&stream_bits).into_iter());
+      assert_eq!(table.decode(&mut bits), Ok(symbol as u32));
+      assert_eq!(bits.position(), len as usize);
+      assert_eq!(bits.read_bits_inv(3), Some(0b101));
+    }
+  }
+
+  #[test]
+  fn test_table_fixed() {
+    check_table(&fixed_code_lengths());
+    assert_eq!(
+      HuffmanTable::from_code_lengths(&fixed_code_lengths()),
+      HuffmanTable::fixed()
+    );
+  }
+
+  #[test]
+  fn test_table_dist_codes() {
+    check_table(&[
+      0, 7, 0, 6, 7, 6, 4, 5, 4, 4, 4, 4, 3, 4, 3, 5, 4, 4, 5, 4, 5, 6,
+    ]);
+    check_table(&[0, 1]);
+  }
+
+  #[test]
+  fn test_table_subtables() {
+    // Codes of every length from 1 to 15, most of which need a subtable
+    let mut code_lengths: Vec<u8> = (1..=15).collect();
+    code_lengths.push(15);
+    check_table(&code_lengths);
+    let table = HuffmanTable::from_code_lengths(&code_lengths);
+    assert!(table.entries.len() > 1 << ROOT_BITS);
+  }
+
+  #[test]
+  fn test_table_errors() {
+    // Only "0" is a code, so "1" is invalid
+    let table = HuffmanTable::from_code_lengths(&[0, 1]);
+    let mut bits = BitReader::new(vec![0b1].into_iter());
+    assert_eq!(table.decode(&mut bits), Err(InflateErrorKind::InvalidCode));
+
+    // The fixed code for 144 is 9 bits long, but the stream stops after 8
+    let table = HuffmanTable::fixed();
+    let code_bits = to_bits(0b1_1001_0000, 9);
+    let mut bits = BitReader::new(to_stream(&code_bits[..8]).into_iter());
+    assert_eq!(
+      table.decode(&mut bits),
+      Err(InflateErrorKind::UnexpectedEnd)
+    );
+  }
+
+  #[test]
+  fn test_code_lengths_from_frequencies() {
+    assert_eq!(code_lengths_from_frequencies(&[0, 0, 0], 15), vec![0, 0, 0]);
+    assert_eq!(code_lengths_from_frequencies(&[0, 5, 0], 15), vec![0, 1, 0]);
+    assert_eq!(
+      code_lengths_from_frequencies(&[10, 1, 0, 1, 2], 15),
+      vec![1, 3, 0, 3, 2]
+    );
+
+    // Fibonacci frequencies make the deepest possible tree, which has to be
+    // flattened to fit
+    let mut frequencies = vec![1, 1];
+    for i in 2..30 {
+      frequencies.push(frequencies[i - 1] + frequencies[i - 2]);
+    }
+    assert_eq!(
+      *code_lengths_from_frequencies(&frequencies, 50)
+        .iter()
+        .max()
+        .unwrap(),
+      29
+    );
+    let lengths = code_lengths_from_frequencies(&frequencies, 15);
+    assert!(lengths.iter().all(|&len| len > 0 && len <= 15));
+    assert_eq!(check_code_lengths(&lengths), Ok(()));
+    // Every code is used, so the lengths fill the code space exactly
+    let kraft: f64 = lengths.iter().map(|&len| 0.5_f64.powi(len.into())).sum();
+    assert_eq!(kraft, 1.0);
+  }
+
+  // The total bits that `lengths` would take to code `frequencies`
+  fn cost(frequencies: &[u32], lengths: &[u8]) -> u64 {
+    frequencies
+      .iter()
+      .zip(lengths)
+      .map(|(&freq, &len)| u64::from(freq) * u64::from(len))
+      .sum()
+  }
+
+  #[test]
+  fn test_code_lengths_are_optimal() {
+    // Unconstrained, package-merge gives a Huffman code
+    let frequencies = [5, 9, 12, 13, 16, 45];
+    let lengths = code_lengths_from_frequencies(&frequencies, 15);
+    assert_eq!(cost(&frequencies, &lengths), 224);
+
+    // Four symbols in two bits is a flat code, however skewed
+    assert_eq!(
+      code_lengths_from_frequencies(&[1000, 100, 10, 1], 2),
+      vec![2, 2, 2, 2]
+    );
+    // Unconstrained this would be [1, 2, 3, 4, 4]; in three bits the second
+    // symbol has to give up its short code
+    assert_eq!(
+      code_lengths_from_frequencies(&[1000, 100, 10, 1, 1], 3),
+      vec![1, 3, 3, 3, 3]
+    );
+  }
+
+  #[test]
+  fn test_run_length_encode() {
+    assert_eq!(run_length_encode(&[]), vec![]);
+    assert_eq!(
+      run_length_encode(&[3, 3, 3, 0, 0, 5]),
+      vec![(3, 0), (3, 0), (3, 0), (0, 0), (0, 0), (5, 0)]
+    );
+    assert_eq!(
+      run_length_encode(&[8, 8, 8, 8, 0, 0, 0]),
+      vec![(8, 0), (REPEAT_PREVIOUS, 0), (REPEAT_ZERO, 0)]
+    );
+    // Long runs are split, and a repeat can follow a repeat
+    assert_eq!(
+      run_length_encode(&[7; 14]),
+      vec![(7, 0), (REPEAT_PREVIOUS, 3), (REPEAT_PREVIOUS, 3), (7, 0)]
+    );
+    assert_eq!(
+      run_length_encode(&[0; 150]),
+      vec![(REPEAT_ZERO_LONG, 127), (REPEAT_ZERO_LONG, 1)]
+    );
+
+    // Expanding the symbols gives the lengths back
+    let mut lengths = fixed_literal_lengths();
+    lengths.extend_from_slice(&[0; 40]);
+    lengths.extend_from_slice(&[5; 30]);
+    let mut expanded: Vec<u8> = vec![];
+    for (symbol, extra) in run_length_encode(&lengths) {
+      match symbol {
+        REPEAT_PREVIOUS => {
+          let previous = *expanded.last().unwrap();
+          expanded.extend(vec![previous; 3 + extra as usize]);
+        }
+        REPEAT_ZERO => expanded.extend(vec![0; 3 + extra as usize]),
+        REPEAT_ZERO_LONG => expanded.extend(vec![0; 11 + extra as usize]),
+        len => expanded.push(len),
+      }
+    }
+    assert_eq!(expanded, lengths);
+  }
+
+  #[test]
+  fn test_check_code_lengths() {
+    assert_eq!(check_code_lengths(&fixed_code_lengths()), Ok(()));
+    assert_eq!(check_code_lengths(&[0, 1]), Ok(()));
+    assert_eq!(check_code_lengths(&[1, 2, 2]), Ok(()));
+    assert_eq!(
+      check_code_lengths(&[1, 2, 2, 2]),
+      Err(InflateErrorKind::OversubscribedCodeLengths)
+    );
+  }
+
   #[test]
   fn test_generation_of_fixed_huffman_ranges() {
     assert_eq!(
@@ -339,7 +976,7 @@ mod tests {
     assert_eq!(root.decode(&to_bits(0b111_1111, 7)), Some(18));
   }
 
-  #[allow(clippy::cyclomatic_complexity)]
+  #[allow(clippy::cognitive_complexity)]
   #[test]
   fn test_dynamic() {
     // Example 6 reconstructed from https://commandlinefanatic.com/cgi-bin/showarticle.cgi?article=art001
@@ -385,7 +1022,7 @@ mod tests {
     assert_eq!(root.decode(&to_bits(0b11_1000, 6)), None);
   }
 
-  #[allow(clippy::cyclomatic_complexity)]
+  #[allow(clippy::cognitive_complexity)]
   #[test]
   fn test_dist_codes() {
     // This was the one that was occasionally showing up as problematic
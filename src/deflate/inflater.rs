@@ -0,0 +1,297 @@
+use super::{Inflate, InflateError, InflateErrorKind};
+
+// A push-based wrapper around `Inflate`, for callers that receive compressed
+// data in arbitrary chunks and cannot block waiting for more (the zlib
+// `inflate()` model). Each call to `feed` decodes as much as it can from the
+// input it is given, into the output space it is given, and reports how much
+// of each it used.
+//
+// Decoding happens in the same atomic steps as `Inflate` (a block header, a
+// symbol or a run of stored bytes). A step that runs out of input part-way through is
+// rewound, and the bytes it had started on are kept here until the next call
+// supplies the rest.
+//
+// Bytes the bit reader has pulled in ahead of the last step are handed back
+// to `Input` at the end of each call, so that `consumed` always stops at the
+// last byte decoding actually reached into.
+pub struct Inflater {
+  inflate: Inflate<Input>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Status {
+  pub consumed: usize, // bytes of input used
+  pub produced: usize, // bytes of output written
+  pub state: StreamState,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StreamState {
+  // All of the input was consumed; call again with more
+  NeedsInput,
+  // The output is full; call again with more space and the unconsumed input
+  NeedsOutput,
+  // The final block has been decoded and all of its output written. Any
+  // unconsumed input follows the end of the deflate stream.
+  StreamEnd,
+}
+
+// The bytes `Inflate` reads from. They are only discarded at the start of a
+// call to `feed`, once no step can need to be retried from them.
+struct Input {
+  buf: Vec<u8>,
+  pos: usize,
+}
+
+impl Iterator for Input {
+  type Item = u8;
+  fn next(&mut self) -> Option<u8> {
+    let byte = self.buf.get(self.pos).cloned();
+    if byte.is_some() {
+      self.pos += 1;
+    }
+    byte
+  }
+}
+
+impl Inflater {
+  pub fn new() -> Inflater {
+    Inflater {
+      inflate: Inflate::new(Input {
+        buf: vec![],
+        pos: 0,
+      }),
+    }
+  }
+
+  // Matches can reach back into `dictionary`, which is not itself output
+  pub fn with_dictionary(dictionary: &[u8]) -> Inflater {
+    Inflater {
+      inflate: Inflate::with_dictionary(
+        Input {
+          buf: vec![],
+          pos: 0,
+        },
+        dictionary,
+      ),
+    }
+  }
+
+  pub fn feed(&mut self, input: &[u8], output: &mut [u8]) -> Result<Status, InflateError> {
+    {
+      let pending = self.inflate.get_mut();
+      pending.buf.drain(..pending.pos);
+      pending.pos = 0;
+    }
+    // Bytes already held from earlier calls have been reported as consumed
+    let held = self.inflate.get_ref().buf.len();
+    self.inflate.get_mut().buf.extend_from_slice(input);
+
+    let mut produced = self.write_output(output);
+    let state = loop {
+      // Decoded bytes that did not fit are held until the next call
+      if !self.inflate.output.is_empty() {
+        break StreamState::NeedsOutput;
+      }
+      if self.inflate.is_finished() {
+        break StreamState::StreamEnd;
+      }
+
+      let checkpoint = self.inflate.bits.checkpoint();
+      let input_pos = self.inflate.get_ref().pos;
+      match self.inflate.step() {
+        Ok(()) => produced += self.write_output(&mut output[produced..]),
+        Err(ref e) if e.kind == InflateErrorKind::UnexpectedEnd => {
+          self.inflate.bits.rewind(checkpoint);
+          self.inflate.get_mut().pos = input_pos;
+          break StreamState::NeedsInput;
+        }
+        Err(e) => return Err(e),
+      }
+    };
+
+    // The bit reader may have pulled in bytes beyond the last step; step the
+    // input back over them so they are reported (and kept) as unconsumed
+    let unread = self.inflate.bits.unread_bytes();
+    self.inflate.get_mut().pos -= unread;
+
+    let consumed = match state {
+      StreamState::NeedsInput => input.len(),
+      _ => {
+        // Hand back whatever was not needed, so that it can be passed again
+        // (or, at the end of the stream, used by whatever comes next)
+        let pending = self.inflate.get_mut();
+        let unread = pending.buf.len() - pending.pos.max(held);
+        pending.buf.truncate(pending.pos.max(held));
+        input.len() - unread
+      }
+    };
+    Ok(Status {
+      consumed,
+      produced,
+      state,
+    })
+  }
+
+  // The CRC32 of all of the data decoded so far
+  pub fn crc32(&self) -> u32 {
+    self.inflate.crc32()
+  }
+
+  pub fn total_out(&self) -> u64 {
+    self.inflate.total_out()
+  }
+
+  // Move as much pending output as fits into `output`
+  fn write_output(&mut self, output: &mut [u8]) -> usize {
+    self.inflate.write_output(output)
+  }
+}
+
+impl Default for Inflater {
+  fn default() -> Inflater {
+    Inflater::new()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::fs;
+
+  // The fixtures are gzip files with only the FNAME flag set; skip past
+  // their headers to get at the deflate stream (and the trailer after it)
+  fn deflate_stream(gzip: &[u8]) -> &[u8] {
+    assert_eq!(gzip[3], 0b1000);
+    let name_len = gzip[10..].iter().position(|&b| b == 0).unwrap();
+    &gzip[(10 + name_len + 1)..]
+  }
+
+  fn fixtures() -> Vec<(Vec<u8>, Vec<u8>)> {
+    fs::read_dir("tests/gzip/src/")
+      .expect("failed to read src dir")
+      .map(|entry| {
+        let path = entry.expect("failed entry").path();
+        let src = fs::read(&path).expect("Failed to read file");
+        let compressed_path = path.to_str().unwrap().replace("/src/", "/compressed/");
+        let compressed = fs::read(compressed_path).expect("Failed to read file");
+        (compressed, src)
+      })
+      .collect()
+  }
+
+  // Feed `input` in chunks of `in_chunk` bytes, with `out_chunk` bytes of
+  // output space per call, returning the output and the unconsumed input
+  fn inflate_in_chunks(input: &[u8], in_chunk: usize, out_chunk: usize) -> (Vec<u8>, usize) {
+    let mut inflater = Inflater::new();
+    let mut data = vec![];
+    let mut output = vec![0; out_chunk];
+    let mut pos = 0;
+    loop {
+      let end = (pos + in_chunk).min(input.len());
+      let status = inflater
+        .feed(&input[pos..end], &mut output)
+        .expect("Failed to inflate");
+      pos += status.consumed;
+      data.extend_from_slice(&output[..status.produced]);
+      match status.state {
+        StreamState::StreamEnd => return (data, input.len() - pos),
+        StreamState::NeedsInput => assert_eq!(pos, end),
+        StreamState::NeedsOutput => (),
+      }
+    }
+  }
+
+  #[test]
+  fn test_whole_input() {
+    for (compressed, src) in fixtures() {
+      let (data, unconsumed) = inflate_in_chunks(deflate_stream(&compressed), usize::MAX, 1 << 20);
+      assert_eq!(data, src);
+      // Only the gzip trailer is left over
+      assert_eq!(unconsumed, 8);
+    }
+  }
+
+  #[test]
+  fn test_small_chunks() {
+    for (compressed, src) in fixtures() {
+      let input = deflate_stream(&compressed);
+      for &(in_chunk, out_chunk) in &[(1, 1), (3, 1000), (1000, 5)] {
+        let (data, unconsumed) = inflate_in_chunks(input, in_chunk, out_chunk);
+        assert_eq!(data, src);
+        assert_eq!(unconsumed, 8);
+      }
+    }
+  }
+
+  #[test]
+  fn test_crc32() {
+    let compressed = include_bytes!("../../tests/gzip/dynamic_encoding/gunzip.c.gz");
+    let input = deflate_stream(compressed);
+    let mut inflater = Inflater::new();
+    let mut output = vec![0; 1 << 20];
+    let status = inflater.feed(input, &mut output).unwrap();
+    assert_eq!(status.state, StreamState::StreamEnd);
+
+    let trailer = &input[status.consumed..];
+    let crc32 = u32::from(trailer[0])
+      | u32::from(trailer[1]) << 8
+      | u32::from(trailer[2]) << 16
+      | u32::from(trailer[3]) << 24;
+    assert_eq!(inflater.crc32(), crc32);
+    assert_eq!(inflater.total_out(), status.produced as u64);
+  }
+
+  #[test]
+  fn test_stream_end_is_sticky() {
+    // An empty final fixed block
+    let mut inflater = Inflater::new();
+    let mut output = [0; 10];
+    let status = inflater.feed(&[0x03, 0x00, 0xff], &mut output).unwrap();
+    assert_eq!(
+      status,
+      Status {
+        consumed: 2,
+        produced: 0,
+        state: StreamState::StreamEnd
+      }
+    );
+    let status = inflater.feed(&[0xff], &mut output).unwrap();
+    assert_eq!(status.consumed, 0);
+    assert_eq!(status.state, StreamState::StreamEnd);
+  }
+
+  #[test]
+  fn test_corrupt_input() {
+    let mut inflater = Inflater::new();
+    let error = inflater.feed(&[0b111], &mut [0; 10]).unwrap_err();
+    assert_eq!(error.kind, InflateErrorKind::ReservedBlockType);
+  }
+
+  #[test]
+  fn test_dictionary() {
+    // Made by Python's zlib with `zdict` set to the first 1500 bytes of the
+    // source; the two-byte header and the DICTID are skipped
+    let compressed = include_bytes!("../../tests/zlib/raven-dictionary.zz");
+    let src = include_bytes!("../../tests/gzip/src/raven");
+    let (dictionary, data) = src.split_at(1500);
+
+    let mut inflater = Inflater::with_dictionary(dictionary);
+    let mut output = vec![0; 1 << 16];
+    let mut produced = 0;
+    for chunk in compressed[6..].chunks(7) {
+      let status = inflater.feed(chunk, &mut output[produced..]).unwrap();
+      produced += status.produced;
+    }
+    assert_eq!(&output[..produced], data);
+    assert_eq!(inflater.total_out(), data.len() as u64);
+    assert_eq!(inflater.crc32(), crate::crc32::crc32(data));
+
+    let mut inflater = Inflater::new();
+    let error = inflater.feed(&compressed[6..], &mut output).unwrap_err();
+    assert!(matches!(
+      error.kind,
+      InflateErrorKind::DistanceTooFar { .. }
+    ));
+  }
+}
@@ -0,0 +1,362 @@
+use super::window::WINDOW_SIZE;
+
+pub const MIN_MATCH: usize = 3;
+pub const MAX_MATCH: usize = 258;
+
+// Matches may reach back at most this far. One less than the window, so that
+// a position's slot in `prev` is never reused while it can still be matched.
+const MAX_DISTANCE: usize = WINDOW_SIZE - 1;
+
+const HASH_BITS: u32 = 15;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+
+// Marks an empty `head` or `prev` slot
+const NONE: usize = usize::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Token {
+  Literal(u8),
+  Match { length: u16, distance: u16 },
+}
+
+// How hard to look for matches, as in zlib's `configuration_table`
+#[derive(Debug, Clone, Copy)]
+struct Config {
+  good_length: usize, // search less hard once a match at least this long is found
+  max_lazy: usize,    // don't look for a better match after one this long (lazy)
+  // or don't index the positions inside a match longer than this (greedy)
+  nice_length: usize, // stop searching at a match this long
+  max_chain: usize,   // the most candidates to try for each position
+  lazy: bool,         // whether to check the next position for a longer match
+}
+
+const fn config(
+  good_length: usize,
+  max_lazy: usize,
+  nice_length: usize,
+  max_chain: usize,
+  lazy: bool,
+) -> Config {
+  Config {
+    good_length,
+    max_lazy,
+    nice_length,
+    max_chain,
+    lazy,
+  }
+}
+
+// Indexed by level. Level 0 stores data without looking for matches.
+const CONFIGURATION_TABLE: [Config; 10] = [
+  config(0, 0, 0, 0, false),
+  config(4, 4, 8, 4, false),
+  config(4, 5, 16, 8, false),
+  config(4, 6, 32, 32, false),
+  config(4, 4, 16, 16, true),
+  config(8, 16, 32, 32, true),
+  config(8, 16, 128, 128, true),
+  config(8, 32, 128, 256, true),
+  config(32, 128, 258, 1024, true),
+  config(32, 258, 258, 4096, true),
+];
+
+// A match of the shortest length this far back costs more than the three
+// literals it replaces would
+const TOO_FAR: usize = 4096;
+
+// Finds repeated strings with hash chains, as zlib does: `head` holds the
+// most recent position whose next three bytes hash to each value, and
+// `prev` links every position to the one before it with the same hash.
+// Positions are absolute (counted from the start of the stream), so the
+// caller can drop old data from its buffer without telling the matcher.
+pub struct Matcher {
+  head: Vec<usize>,
+  prev: Vec<usize>,
+  config: Config,
+}
+
+impl Matcher {
+  // `level` runs from 1 (fastest) to 9 (smallest output), as in zlib
+  pub fn new(level: u8) -> Matcher {
+    assert!((1..=9).contains(&level), "no matching at level {}", level);
+    Matcher {
+      head: vec![NONE; HASH_SIZE],
+      prev: vec![NONE; WINDOW_SIZE],
+      config: CONFIGURATION_TABLE[level as usize],
+    }
+  }
+
+  // Add every position of `data`, the start of the stream, to the hash
+  // chains without tokenizing it, so that later input can match it
+  pub fn index(&mut self, data: &[u8]) {
+    for pos in 0..data.len() {
+      self.insert(data, 0, pos);
+    }
+  }
+
+  // Turn `data[start..end]` into tokens. `data[..start]` is earlier input
+  // that matches may refer back to, and `data[0]` is at absolute position
+  // `base`.
+  pub fn tokenize(
+    &mut self,
+    data: &[u8],
+    base: usize,
+    start: usize,
+    end: usize,
+    tokens: &mut Vec<Token>,
+  ) {
+    if self.config.lazy {
+      self.tokenize_lazy(data, base, start, end, tokens)
+    } else {
+      self.tokenize_greedy(data, base, start, end, tokens)
+    }
+  }
+
+  // Take the longest match at each position (zlib's `deflate_fast`)
+  fn tokenize_greedy(
+    &mut self,
+    data: &[u8],
+    base: usize,
+    start: usize,
+    end: usize,
+    tokens: &mut Vec<Token>,
+  ) {
+    let mut pos = start;
+    while pos < end {
+      let (length, distance) = self.longest_match(data, base, pos, end, 0);
+      if length >= MIN_MATCH {
+        tokens.push(match_token(length, distance));
+        // Only the positions inside short matches are worth indexing
+        if length <= self.config.max_lazy {
+          for p in (pos + 1)..(pos + length) {
+            self.insert(data, base, p);
+          }
+        }
+        pos += length;
+      } else {
+        tokens.push(Token::Literal(data[pos]));
+        pos += 1;
+      }
+    }
+  }
+
+  // Before taking a match, see whether the next position has a longer one,
+  // and if so emit a literal instead (zlib's `deflate_slow`)
+  fn tokenize_lazy(
+    &mut self,
+    data: &[u8],
+    base: usize,
+    start: usize,
+    end: usize,
+    tokens: &mut Vec<Token>,
+  ) {
+    // The match found at `pos - 1`, if `pos - 1` has not been emitted yet
+    let mut previous: Option<(usize, usize)> = None;
+    let mut pos = start;
+    while pos < end {
+      let prev_length = previous.map_or(0, |(length, _)| length);
+      let (mut length, distance) = if prev_length < self.config.max_lazy {
+        self.longest_match(data, base, pos, end, prev_length)
+      } else {
+        self.insert(data, base, pos);
+        (0, 0)
+      };
+      if length == MIN_MATCH && distance > TOO_FAR {
+        length = 0;
+      }
+
+      match previous {
+        Some((prev_length, prev_distance)) if prev_length >= MIN_MATCH && length <= prev_length => {
+          tokens.push(match_token(prev_length, prev_distance));
+          // `pos` is already indexed; the rest of the match is indexed now
+          let match_end = pos - 1 + prev_length;
+          for p in (pos + 1)..match_end {
+            self.insert(data, base, p);
+          }
+          previous = None;
+          pos = match_end;
+        }
+        Some(_) => {
+          tokens.push(Token::Literal(data[pos - 1]));
+          previous = Some((length, distance));
+          pos += 1;
+        }
+        None => {
+          previous = Some((length, distance));
+          pos += 1;
+        }
+      }
+    }
+    if previous.is_some() {
+      tokens.push(Token::Literal(data[end - 1]));
+    }
+  }
+
+  // Add position `pos` to its hash chain, returning the previous head
+  fn insert(&mut self, data: &[u8], base: usize, pos: usize) -> usize {
+    if pos + MIN_MATCH > data.len() {
+      return NONE;
+    }
+    let hash = hash(&data[pos..]);
+    let abs = base + pos;
+    let previous = self.head[hash];
+    self.head[hash] = abs;
+    self.prev[abs % WINDOW_SIZE] = previous;
+    previous
+  }
+
+  // Insert `pos` and search its chain for the longest match that ends by
+  // `end`, returning its length and distance. `prev_length` is the length
+  // of a match already in hand, which a new one has to beat.
+  fn longest_match(
+    &mut self,
+    data: &[u8],
+    base: usize,
+    pos: usize,
+    end: usize,
+    prev_length: usize,
+  ) -> (usize, usize) {
+    let mut candidate = self.insert(data, base, pos);
+    let abs = base + pos;
+    let max_length = MAX_MATCH.min(end - pos);
+    let nice_length = self.config.nice_length.min(max_length);
+    let mut chain = self.config.max_chain;
+    if prev_length >= self.config.good_length {
+      chain /= 4;
+    }
+    let mut best = (prev_length.min(max_length.saturating_sub(1)), 0);
+    while candidate != NONE && chain > 0 {
+      // Stale entries, and anything that has fallen out of the buffer, end
+      // the chain
+      if candidate >= abs || abs - candidate > MAX_DISTANCE || candidate < base {
+        break;
+      }
+      let cand_pos = candidate - base;
+      // Only a match that beats the best so far is worth comparing in full
+      if data[cand_pos + best.0] == data[pos + best.0] {
+        let length = match_length(&data[cand_pos..], &data[pos..pos + max_length]);
+        if length > best.0 {
+          best = (length, abs - candidate);
+          if length >= nice_length {
+            break;
+          }
+        }
+      }
+      let next = self.prev[candidate % WINDOW_SIZE];
+      if next == NONE || next >= candidate {
+        break;
+      }
+      candidate = next;
+      chain -= 1;
+    }
+    if best.1 == 0 {
+      (0, 0)
+    } else {
+      best
+    }
+  }
+}
+
+fn match_token(length: usize, distance: usize) -> Token {
+  Token::Match {
+    length: length as u16,
+    distance: distance as u16,
+  }
+}
+
+fn hash(bytes: &[u8]) -> usize {
+  let value = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+  (value.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+// How many bytes at the start of `a` and `b` are the same
+fn match_length(a: &[u8], b: &[u8]) -> usize {
+  a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  // Expand tokens back into bytes, following `history`
+  fn expand(history: &[u8], tokens: &[Token]) -> Vec<u8> {
+    let mut data = history.to_vec();
+    for token in tokens {
+      match *token {
+        Token::Literal(byte) => data.push(byte),
+        Token::Match { length, distance } => {
+          for _ in 0..length {
+            data.push(data[data.len() - distance as usize]);
+          }
+        }
+      }
+    }
+    data[history.len()..].to_vec()
+  }
+
+  #[test]
+  fn test_repeats() {
+    let data = b"abcabcabcabcxyzabcd";
+    let mut tokens = vec![];
+    Matcher::new(6).tokenize(data, 0, 0, data.len(), &mut tokens);
+    assert_eq!(
+      &tokens[..4],
+      &[
+        Token::Literal(b'a'),
+        Token::Literal(b'b'),
+        Token::Literal(b'c'),
+        Token::Match {
+          length: 9,
+          distance: 3
+        },
+      ]
+    );
+    assert_eq!(expand(&[], &tokens), data.to_vec());
+  }
+
+  #[test]
+  fn test_matches_stop_at_end() {
+    let data = [7; 100];
+    let mut tokens = vec![];
+    let mut matcher = Matcher::new(6);
+    matcher.tokenize(&data, 0, 0, 50, &mut tokens);
+    assert_eq!(
+      tokens,
+      vec![
+        Token::Literal(7),
+        Token::Match {
+          length: 49,
+          distance: 1
+        }
+      ]
+    );
+
+    // The second half can refer back into the first
+    tokens.clear();
+    matcher.tokenize(&data, 0, 50, 100, &mut tokens);
+    assert_eq!(
+      tokens,
+      vec![Token::Match {
+        length: 50,
+        distance: 1
+      }]
+    );
+  }
+
+  #[test]
+  fn test_long_input() {
+    let src = include_bytes!("../../tests/gzip/src/gunzip");
+    let mut tokens = vec![];
+    let mut matcher = Matcher::new(6);
+    // Tokenize in pieces, sliding the start of the buffer along
+    let mut start = 0;
+    while start < src.len() {
+      let end = (start + 1000).min(src.len());
+      let base = start.saturating_sub(WINDOW_SIZE);
+      matcher.tokenize(&src[base..], base, start - base, end - base, &mut tokens);
+      start = end;
+    }
+    assert_eq!(expand(&[], &tokens), src.to_vec());
+    assert!(tokens.len() < src.len() / 2);
+  }
+}
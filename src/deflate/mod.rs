@@ -1,233 +1,570 @@
-mod bit_iterator;
+mod bit_reader;
+mod bit_writer;
+mod encoder;
 mod huffman;
-pub use huffman::HuffmanEncoding;
+mod inflater;
+mod lz77;
+mod window;
+pub use encoder::{encode, encode_with_level, CompressionLevel, Encoder};
+pub use huffman::{DecodeMode, HuffmanEncoding};
+pub use inflater::{Inflater, Status, StreamState};
+pub use window::WINDOW_SIZE;
 
 use crate::crc32;
-use bit_iterator::BitIterator;
-use huffman::HuffmanNode;
+use bit_reader::BitReader;
+use huffman::Decoder;
+use std::error::Error;
+use std::fmt;
+use std::mem;
+use window::Window;
 
-struct Inflate<I: Iterator<Item = u8>> {
+enum State {
+  BlockHeader,
+  Stored {
+    remaining: u32,
+    block: Block,
+  },
+  Codes {
+    literals: Decoder,
+    distances: Option<Decoder>,
+    block: Block,
+  },
+  Done,
+}
+
+// The inflate state machine. Each call to `step` decodes a block header, a
+// single symbol, or a single stored byte, and appends any output to the
+// pending `output` buffer, from which it is `read` out.
+pub struct Inflate<I: Iterator<Item = u8>> {
   result: InflateResult,
-  bits: BitIterator<I>,
+  bits: BitReader<I>,
   cur_block_index: usize,
+  state: State,
+  window: Window,
+  output: Vec<u8>,
+  checksummed: usize, // how much of `output` is in `crc32` so far
+  crc32: u32,
+  total_out: u64,
+  trace: bool, // whether to record a DecodeItem for every symbol
+  mode: DecodeMode,
+  max_distance: usize, // how far back a match may reach
 }
 
 impl<I: Iterator<Item = u8>> Inflate<I> {
-  fn new(bits: BitIterator<I>) -> Inflate<I> {
+  pub fn new(bytes: I) -> Inflate<I> {
+    Inflate::with_mode(bytes, DecodeMode::default())
+  }
+
+  pub fn with_mode(bytes: I, mode: DecodeMode) -> Inflate<I> {
     Inflate {
       result: InflateResult::empty(),
-      bits,
+      bits: BitReader::new(bytes),
       cur_block_index: 0,
+      state: State::BlockHeader,
+      window: Window::new(),
+      output: vec![],
+      checksummed: 0,
+      crc32: crc32::initial_value(),
+      total_out: 0,
+      trace: false,
+      mode,
+      max_distance: WINDOW_SIZE,
     }
   }
 
-  fn inflate(&mut self) {
+  // Decode a stream that was compressed with a preset dictionary (zlib's
+  // FDICT). Matches can reach back into the dictionary, but it is not part
+  // of the output, the CRC32 or `total_out`.
+  pub fn with_dictionary(bytes: I, dictionary: &[u8]) -> Inflate<I> {
+    let mut inflate = Inflate::new(bytes);
+    inflate.window.preset(dictionary);
+    inflate
+  }
+
+  // Reject matches that reach back more than `window_size` bytes, for a
+  // stream whose wrapper declares a smaller window than deflate allows (as
+  // zlib's CINFO does)
+  pub fn limit_window(&mut self, window_size: usize) {
+    self.max_distance = window_size.min(WINDOW_SIZE);
+  }
+
+  // Pick up decoding at a block boundary found earlier (see
+  // `at_block_boundary`), without the data before it. `bytes` starts with
+  // the byte the boundary falls in, `skip_bits` into it, and `window` is the
+  // output that came just before.
+  pub fn resume(bytes: I, skip_bits: u8, window: &[u8]) -> Inflate<I> {
+    let mut inflate = Inflate::with_dictionary(bytes, window);
+    // With no byte to skip into there is nothing to decode either, and the
+    // first step reports the unexpected end
+    let _ = inflate.bits.read_bits_inv(skip_bits);
+    inflate
+  }
+
+  // Start over on another deflate stream that follows this one in the same
+  // input, as the next member of a gzip file does. It begins at the current
+  // byte boundary, which becomes bit 0, and shares nothing with this one.
+  pub fn restart(&mut self) {
+    self.bits.align_to_byte();
+    self.bits.reset_position();
+    self.bits.clear_recorded();
+    self.result = InflateResult::empty();
     self.cur_block_index = 0;
+    self.state = State::BlockHeader;
+    self.window = Window::new();
+    self.output.clear();
+    self.checksummed = 0;
+    self.crc32 = crc32::initial_value();
+    self.total_out = 0;
+  }
+
+  // Record a DecodeItem, with the bits it was read from, for every symbol
+  // from here on, for the visualizer. This costs a Vec push per bit and per
+  // symbol, so it is off unless asked for.
+  pub fn trace(&mut self) {
+    self.trace = true;
+    self.bits.start_recording();
+  }
+
+  // Fill `buf` with decompressed data, returning how many bytes were written.
+  // Returns 0 once the final block has been read out.
+  pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, InflateError> {
+    while self.output.len() < buf.len() && !self.is_finished() {
+      self.step()?;
+    }
+    Ok(self.write_output(buf))
+  }
+
+  // Move as much pending output as fits into `buf`
+  fn write_output(&mut self, buf: &mut [u8]) -> usize {
+    let len = buf.len().min(self.output.len());
+    buf[..len].copy_from_slice(&self.output[..len]);
+    self.checksum_output();
+    self.output.drain(..len);
+    self.checksummed -= len;
+    len
+  }
+
+  // Bring `crc32` up to date with the output decoded since it last was.
+  // This is left until output is handed on, so that the CRC is computed
+  // over long runs rather than a symbol at a time.
+  fn checksum_output(&mut self) {
+    self.crc32 = crc32::update_slice(self.crc32, &self.output[self.checksummed..]);
+    self.checksummed = self.output.len();
+  }
+
+  // Decode the rest of the current block (or the next one, at a boundary),
+  // leaving its output to be `read` or taken
+  pub fn decode_block(&mut self) -> Result<(), InflateError> {
     loop {
-      let block = self.read_block();
-      self.cur_block_index += 1;
-      let is_last = block.is_last;
-      self.result.blocks.push(block);
-      if is_last {
-        break;
+      self.step()?;
+      if self.is_finished() || self.at_block_boundary() {
+        return Ok(());
+      }
+    }
+  }
+
+  // Whether the next step reads a block header, so that decoding could be
+  // picked up from here with `resume`
+  pub fn at_block_boundary(&self) -> bool {
+    matches!(self.state, State::BlockHeader)
+  }
+
+  // The decoded output that has not been read yet
+  pub fn take_output(&mut self) -> Vec<u8> {
+    self.checksum_output();
+    self.checksummed = 0;
+    mem::take(&mut self.output)
+  }
+
+  // The most recent output, up to WINDOW_SIZE bytes of it, oldest first
+  pub fn window(&self) -> Vec<u8> {
+    self.window.to_vec()
+  }
+
+  // Whether the final block has been decoded. There may still be output
+  // waiting to be `read`.
+  pub fn is_finished(&self) -> bool {
+    matches!(self.state, State::Done)
+  }
+
+  // The CRC32 of all of the data decoded so far
+  pub fn crc32(&self) -> u32 {
+    let pending = &self.output[self.checksummed..];
+    crc32::finalize(crc32::update_slice(self.crc32, pending))
+  }
+
+  pub fn total_out(&self) -> u64 {
+    self.total_out
+  }
+
+  pub fn blocks(&self) -> &[Block] {
+    &self.result.blocks
+  }
+
+  // The offset, in bits from the start of the stream, of the next unread bit
+  pub fn bit_position(&self) -> usize {
+    self.bits.position()
+  }
+
+  pub fn get_ref(&self) -> &I {
+    self.bits.get_ref()
+  }
+
+  pub fn get_mut(&mut self) -> &mut I {
+    self.bits.get_mut()
+  }
+
+  // The bytes that follow the deflate stream (such as a gzip trailer),
+  // starting at the next byte boundary
+  pub fn remaining_bytes(&mut self) -> RemainingBytes<'_, I> {
+    self.bits.align_to_byte();
+    RemainingBytes {
+      bits: &mut self.bits,
+    }
+  }
+
+  fn step(&mut self) -> Result<(), InflateError> {
+    // Only the bits of the current symbol are kept for its DecodeItem
+    self.bits.clear_recorded();
+
+    // The state is taken out while stepping so that it can be borrowed
+    // alongside `self`, and put back if the step fails. The bits the step
+    // read are not; only a caller that checkpoints the bit reader, as
+    // `Inflater::feed` does, can retry a failed step.
+    let state = mem::replace(&mut self.state, State::Done);
+    match self.step_from(&state) {
+      Ok(Some(next)) => self.state = next,
+      Ok(None) => self.state = state,
+      Err(e) => {
+        self.state = state;
+        return Err(e);
+      }
+    }
+    Ok(())
+  }
+
+  // Returns the state to move to, or `None` to stay in the current one
+  fn step_from(&mut self, state: &State) -> Result<Option<State>, InflateError> {
+    match state {
+      State::BlockHeader => self.read_block_header().map(Some),
+      State::Stored {
+        remaining: 0,
+        block,
+      } => Ok(Some(self.end_block(*block))),
+      State::Stored { remaining, block } => {
+        // Copied as a run, of as many bytes as the input has to hand
+        let start = self.output.len();
+        let read = self.bits.read_bytes(*remaining as usize, &mut self.output);
+        if read == 0 {
+          return Err(self.error_at(self.bits.position(), InflateErrorKind::UnexpectedEnd));
+        }
+        self.window.extend(&self.output[start..]);
+        self.total_out += read as u64;
+        Ok(Some(State::Stored {
+          remaining: remaining - read as u32,
+          block: *block,
+        }))
       }
+      State::Codes {
+        literals,
+        distances,
+        block,
+      } => {
+        if self.decode_next_symbol(literals, distances)? {
+          Ok(Some(self.end_block(*block)))
+        } else {
+          Ok(None)
+        }
+      }
+      State::Done => Ok(None),
+    }
+  }
+
+  fn end_block(&mut self, block: Block) -> State {
+    self.result.blocks.push(block);
+    self.cur_block_index += 1;
+    if block.is_last {
+      State::Done
+    } else {
+      State::BlockHeader
     }
-    self.result.crc32 = crc32::finalize(self.result.crc32)
   }
 
-  fn read_block(&mut self) -> Block {
-    let is_last = self.bits.read_bits_inv(1) == 1;
-    let encoding = match self.bits.read_bits_inv(2) {
+  fn error_at(&self, bit_offset: usize, kind: InflateErrorKind) -> InflateError {
+    InflateError {
+      block: self.cur_block_index,
+      bit_offset,
+      kind,
+    }
+  }
+
+  fn read_bits_inv(&mut self, count: u8) -> Result<u32, InflateError> {
+    let offset = self.bits.position();
+    match self.bits.read_bits_inv(count) {
+      Some(v) => Ok(v),
+      None => Err(self.error_at(offset, InflateErrorKind::UnexpectedEnd)),
+    }
+  }
+
+  fn read_bits(&mut self, count: u8) -> Result<u32, InflateError> {
+    let offset = self.bits.position();
+    match self.bits.read_bits(count) {
+      Some(v) => Ok(v),
+      None => Err(self.error_at(offset, InflateErrorKind::UnexpectedEnd)),
+    }
+  }
+
+  fn decode_symbol(&mut self, decoder: &Decoder) -> Result<u32, InflateError> {
+    let offset = self.bits.position();
+    decoder
+      .decode(&mut self.bits)
+      .map_err(|kind| self.error_at(offset, kind))
+  }
+
+  fn read_block_header(&mut self) -> Result<State, InflateError> {
+    let offset = self.bits.position();
+    let is_last = self.read_bits_inv(1)? == 1;
+    let encoding = match self.read_bits_inv(2)? {
       0 => BlockEncoding::Stored,
       1 => BlockEncoding::HuffmanFixed,
       2 => BlockEncoding::HuffmanDynamic,
-      v => unreachable!("Unexpected block encoding encountered: {}", v),
+      _ => return Err(self.error_at(offset, InflateErrorKind::ReservedBlockType)),
     };
+    let block = Block { is_last, encoding };
     match encoding {
-      BlockEncoding::HuffmanFixed => {
-        self.decode_block_data(HuffmanNode::fixed(), None);
-      }
+      BlockEncoding::HuffmanFixed => Ok(State::Codes {
+        literals: Decoder::fixed(self.mode),
+        distances: None,
+        block,
+      }),
       BlockEncoding::HuffmanDynamic => {
-        let (literals_root, distances_root) = self.decode_dynamic_data();
-        self.decode_block_data(literals_root, Some(distances_root));
+        let (literals, distances) = self.decode_dynamic_data()?;
+        Ok(State::Codes {
+          literals,
+          distances: Some(distances),
+          block,
+        })
       }
       BlockEncoding::Stored => {
-        self.read_stored_block();
+        let remaining = self.read_stored_block_header()?;
+        Ok(State::Stored { remaining, block })
       }
     }
-    Block { is_last, encoding }
   }
 
-  fn decode_dynamic_data(&mut self) -> (HuffmanNode, HuffmanNode) {
-    let hlit = self.bits.read_bits_inv(5) as usize; // == # of lit/length codes - 257 (257-286)
-    let hdist = self.bits.read_bits_inv(5) as usize; // == # of distance codes - 1 (1-30)
-    let hclen = self.bits.read_bits_inv(4) as usize; // == # of code length codes - 4 (4-19)
+  fn decode_dynamic_data(&mut self) -> Result<(Decoder, Decoder), InflateError> {
+    let offset = self.bits.position();
+    let hlit = self.read_bits_inv(5)? as usize; // == # of lit/length codes - 257 (257-286)
+    let hdist = self.read_bits_inv(5)? as usize; // == # of distance codes - 1 (1-30)
+    let hclen = self.read_bits_inv(4)? as usize; // == # of code length codes - 4 (4-19)
 
     const MAX_LEN_CODES: usize = 286;
-    assert!(hlit < MAX_LEN_CODES);
+    if hlit + 257 > MAX_LEN_CODES {
+      return Err(self.error_at(offset, InflateErrorKind::TooManyLengthCodes(hlit + 257)));
+    }
 
     const MAX_DIST_CODES: usize = 30;
-    assert!(hdist < MAX_DIST_CODES);
-    let mut code_length_code_lengths: Vec<u8> = Vec::with_capacity(3 * (4 + hclen) as usize);
+    if hdist + 1 > MAX_DIST_CODES {
+      return Err(self.error_at(
+        offset + 5,
+        InflateErrorKind::TooManyDistanceCodes(hdist + 1),
+      ));
+    }
+    let mut code_length_code_lengths: Vec<u8> = Vec::with_capacity(3 * (4 + hclen));
     for _ in 0..(hclen + 4) {
-      let code = self.bits.read_bits_inv(3) as u8;
+      let code = self.read_bits_inv(3)? as u8;
       code_length_code_lengths.push(code);
     }
 
-    let code_lengths_tree = HuffmanNode::from_header_code_lengths(code_length_code_lengths);
+    let offset = self.bits.position();
+    huffman::check_code_lengths(&code_length_code_lengths)
+      .map_err(|kind| self.error_at(offset, kind))?;
+    let code_lengths_tree = Decoder::from_header_code_lengths(code_length_code_lengths, self.mode);
 
     let mut alphabet_lens: Vec<u8> = vec![0; hlit + hdist + 258];
     let mut i = 0;
     while i < (hlit + hdist + 258) {
-      if let Some(code) = code_lengths_tree.decode_stream(&mut self.bits) {
-        assert!(code <= 18); // The code length encodings are all in the range 0-18
-        let code = code as u8;
-        match code {
-          0..=15 => {
-            alphabet_lens[i] = code;
-            i += 1;
+      let code_offset = self.bits.position();
+      let code = self.decode_symbol(&code_lengths_tree)?;
+      match code {
+        0..=15 => {
+          alphabet_lens[i] = code as u8;
+          i += 1;
+        }
+        16..=18 => {
+          if code == 16 && i == 0 {
+            return Err(self.error_at(code_offset, InflateErrorKind::RepeatWithoutPrevious));
           }
-          16..=18 => {
-            let mut repeat_len = match code {
-              16 => self.bits.read_bits_inv(2) + 3,
-              17 => self.bits.read_bits_inv(3) + 3,
-              18 => self.bits.read_bits_inv(7) + 11,
-              _ => panic!("Unexpected code for repeat_len {}", code),
-            };
-            while repeat_len > 0 {
-              repeat_len -= 1;
-              alphabet_lens[i] = if code == 16 { alphabet_lens[i - 1] } else { 0 };
-              i += 1;
-            }
+          let repeat_len = match code {
+            16 => self.read_bits_inv(2)? + 3,
+            17 => self.read_bits_inv(3)? + 3,
+            _ => self.read_bits_inv(7)? + 11,
+          } as usize;
+          if i + repeat_len > alphabet_lens.len() {
+            return Err(self.error_at(code_offset, InflateErrorKind::CodeLengthsOverflow));
           }
-          _ => panic!("Unexpected code {} encountered", code),
+          let value = if code == 16 { alphabet_lens[i - 1] } else { 0 };
+          for len in &mut alphabet_lens[i..(i + repeat_len)] {
+            *len = value;
+          }
+          i += repeat_len;
         }
+        // The code length encodings are all in the range 0-18
+        _ => unreachable!("Code length tree has no code {}", code),
       }
     }
 
+    const STOP_CODE: usize = 256;
+    if alphabet_lens[STOP_CODE] == 0 {
+      return Err(self.error_at(offset, InflateErrorKind::MissingEndOfBlock));
+    }
+
+    let (literal_lens, distance_lens) = alphabet_lens.split_at(hlit + 257);
+    huffman::check_code_lengths(literal_lens).map_err(|kind| self.error_at(offset, kind))?;
+    huffman::check_code_lengths(distance_lens).map_err(|kind| self.error_at(offset, kind))?;
+
     // build the literals ranges
-    let literals_tree = HuffmanNode::from_code_lengths(&alphabet_lens[0..(hlit + 257)]);
-    let distance_tree = HuffmanNode::from_code_lengths(&alphabet_lens[(hlit + 257)..]);
-    (literals_tree, distance_tree)
+    let literals_tree = Decoder::from_code_lengths(literal_lens, self.mode);
+    let distance_tree = Decoder::from_code_lengths(distance_lens, self.mode);
+    Ok((literals_tree, distance_tree))
   }
 
   // TODO make the decodeitem output match that from infgen
-  fn read_stored_block(&mut self) -> Vec<DecodeItem> {
+  // Reads LEN and NLEN, returning the number of bytes of stored data that follow
+  fn read_stored_block_header(&mut self) -> Result<u32, InflateError> {
     // skip to the next byte
-    self.bits.discard_extra_bits();
+    self.bits.align_to_byte();
 
+    let offset = self.bits.position();
     // Read 2-byte `len` value as LE
-    let le = self.bits.read_bits_inv(8);
-    let be = self.bits.read_bits_inv(8);
-    let len: u32 = ((be << 8) | le) as u32;
+    let le = self.read_bits_inv(8)?;
+    let be = self.read_bits_inv(8)?;
+    let len: u32 = (be << 8) | le;
 
     // Read 2-byte `nlen` value as LE
     // nlen is one's complement of len, see: https://www.w3.org/Graphics/PNG/RFC-1951#noncompressed
-    let le = self.bits.read_bits_inv(8);
-    let be = self.bits.read_bits_inv(8);
-    let nlen: u32 = ((be << 8) | le) as u32;
-
-    assert!(len == (!nlen & 0xFFFF));
+    let le = self.read_bits_inv(8)?;
+    let be = self.read_bits_inv(8)?;
+    let nlen: u32 = (be << 8) | le;
 
-    for _ in 0..len {
-      let byte = self.bits.read_bits_inv(8) as u8;
-      self.append_data(byte);
+    if len != (!nlen & 0xFFFF) {
+      return Err(self.error_at(
+        offset,
+        InflateErrorKind::StoredLengthMismatch {
+          len: len as u16,
+          nlen: nlen as u16,
+        },
+      ));
     }
-    vec![]
+    Ok(len)
   }
 
   fn push_literal(&mut self, byte: u8) {
-    let data = DecodeData {
-      bits: self.bits.flush_buffer(),
-      block_id: self.cur_block_index,
-    };
-    self
-      .result
-      .decode_items
-      .push(DecodeItem::Literal { value: byte, data });
+    if self.trace {
+      let data = DecodeData {
+        bits: self.bits.take_recorded(),
+        block_id: self.cur_block_index,
+      };
+      self
+        .result
+        .decode_items
+        .push(DecodeItem::Literal { value: byte, data });
+    }
     self.append_data(byte);
   }
 
   fn push_match(&mut self, length: u32, distance: u32) {
-    assert!(self.result.data.len() > distance as usize);
-
     // Copy match to data
-    let v_idx = self.result.data.len() - distance as usize;
-    for i in 0..length {
-      let val = self.result.data[v_idx + i as usize];
+    for _ in 0..length {
+      let val = self.window.get(distance as usize);
       self.append_data(val);
     }
 
     // push DecodeItem
-    let data = DecodeData {
-      bits: self.bits.flush_buffer(),
-      block_id: self.cur_block_index,
-    };
-    self.result.decode_items.push(DecodeItem::Match {
-      length,
-      distance,
-      data,
-    });
+    if self.trace {
+      let data = DecodeData {
+        bits: self.bits.take_recorded(),
+        block_id: self.cur_block_index,
+      };
+      self.result.decode_items.push(DecodeItem::Match {
+        length,
+        distance,
+        data,
+      });
+    }
   }
 
   fn append_data(&mut self, byte: u8) {
-    self.result.data.push(byte);
-    self.update_crc32(byte);
+    self.window.push(byte);
+    self.output.push(byte);
+    self.total_out += 1;
   }
 
-  fn update_crc32(&mut self, byte: u8) {
-    self.result.crc32 = crc32::update(self.result.crc32, byte);
-  }
-
-  fn decode_block_data(&mut self, literals_root: HuffmanNode, distances_root: Option<HuffmanNode>) {
+  // Decode a single literal, match or end-of-block code. Returns whether the
+  // end of the block was reached.
+  fn decode_next_symbol(
+    &mut self,
+    literals_root: &Decoder,
+    distances_root: &Option<Decoder>,
+  ) -> Result<bool, InflateError> {
     const MAX_LITERAL_CODE: u32 = 255;
     const STOP_CODE: u32 = 256;
     const MIN_DISTANCE_CODE: u32 = STOP_CODE + 1;
     const MAX_DISTANCE_CODE: u32 = 285;
 
-    loop {
-      self.bits.flush_buffer();
-      match literals_root.decode_stream(&mut self.bits) {
-        None => unreachable!("Failed to decode from stream"),
-        Some(x) => match x {
-          0..=MAX_LITERAL_CODE => {
-            self.push_literal(x as u8);
-          }
-          STOP_CODE => {
-            break;
-          }
-          MIN_DISTANCE_CODE..=MAX_DISTANCE_CODE => {
-            let length = self.decode_length(x);
-            let distance = self.decode_distance(&distances_root);
+    let offset = self.bits.position();
+    match self.decode_symbol(literals_root)? {
+      x @ 0..=MAX_LITERAL_CODE => {
+        self.push_literal(x as u8);
+      }
+      STOP_CODE => {
+        return Ok(true);
+      }
+      x @ MIN_DISTANCE_CODE..=MAX_DISTANCE_CODE => {
+        let length = self.decode_length(x)?;
+        let distance = self.decode_distance(distances_root)?;
 
-            self.push_match(length, distance);
-          }
-          _ => unreachable!("Unexpected decoded value {}", x),
-        },
+        let available = self.window.len().min(self.max_distance);
+        if distance as usize > available {
+          return Err(self.error_at(
+            offset,
+            InflateErrorKind::DistanceTooFar {
+              distance,
+              available,
+            },
+          ));
+        }
+        self.push_match(length, distance);
       }
+      x => return Err(self.error_at(offset, InflateErrorKind::InvalidLengthSymbol(x))),
     }
+    Ok(false)
   }
 
-  fn decode_distance(&mut self, distances_root: &Option<HuffmanNode>) -> u32 {
+  fn decode_distance(&mut self, distances_root: &Option<Decoder>) -> Result<u32, InflateError> {
     const EXTRA_DIST_ADDEND: [u32; 26] = [
       5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073,
       4097, 6145, 8193, 12289, 16385, 24577,
     ];
+    const MAX_DISTANCE_CODE: u32 = 29;
+
+    let offset = self.bits.position();
     let code = match distances_root {
-      Some(node) => node.decode_stream(&mut self.bits).unwrap(),
-      None => self.bits.read_bits(5),
+      Some(node) => self.decode_symbol(node)?,
+      None => self.read_bits(5)?,
     };
     if code <= 3 {
-      code + 1 // minimum distance is 1, so code 0 => distance 1
-    } else {
+      Ok(code + 1) // minimum distance is 1, so code 0 => distance 1
+    } else if code <= MAX_DISTANCE_CODE {
       let extra_bits_to_read = (code as u8 - 2) / 2;
-      let extra_dist = self.bits.read_bits_inv(extra_bits_to_read);
-      extra_dist + EXTRA_DIST_ADDEND[code as usize - 4]
+      let extra_dist = self.read_bits_inv(extra_bits_to_read)?;
+      Ok(extra_dist + EXTRA_DIST_ADDEND[code as usize - 4])
+    } else {
+      Err(self.error_at(offset, InflateErrorKind::InvalidDistanceSymbol(code)))
     }
   }
 
-  fn decode_length(&mut self, code: u32) -> u32 {
-    assert!(code > 256);
+  fn decode_length(&mut self, code: u32) -> Result<u32, InflateError> {
     const EXTRA_LENGTH_ADDEND: [u32; 20] = [
       11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227,
     ];
@@ -235,14 +572,14 @@ impl<I: Iterator<Item = u8>> Inflate<I> {
     const MAX_LENGTH: u32 = 258;
 
     match code {
-      257..=264 => code - 257 + 3,
+      257..=264 => Ok(code - 257 + 3),
       265..=284 => {
         let extra_bits = ((code - 261) / 4) as u8;
-        let length = self.bits.read_bits_inv(extra_bits);
-        length + EXTRA_LENGTH_ADDEND[code as usize - 265]
+        let length = self.read_bits_inv(extra_bits)?;
+        Ok(length + EXTRA_LENGTH_ADDEND[code as usize - 265])
       }
-      285 => MAX_LENGTH,
-      _ => panic!("Unexpected code for length {}", code),
+      285 => Ok(MAX_LENGTH),
+      _ => unreachable!("decode_length called with non-length code {}", code),
     }
   }
 }
@@ -252,6 +589,7 @@ pub struct InflateResult {
   pub decode_items: Vec<DecodeItem>,
   pub data: Vec<u8>,
   pub crc32: u32,
+  pub compressed_len: usize, // bytes of input the deflate stream took up
 }
 
 impl InflateResult {
@@ -261,25 +599,223 @@ impl InflateResult {
       data: vec![],
       decode_items: vec![],
       crc32: crc32::initial_value(),
+      compressed_len: 0,
     }
   }
 }
 
-pub fn inflate(bytes: &mut impl Iterator<Item = u8>) -> InflateResult {
-  let bits = BitIterator::new(bytes);
-  let mut inflator = Inflate::new(bits);
-  inflator.inflate();
-  inflator.result
+pub fn inflate(bytes: &mut impl Iterator<Item = u8>) -> Result<InflateResult, InflateError> {
+  inflate_with_mode(bytes, DecodeMode::default())
+}
+
+pub fn inflate_with_mode(
+  bytes: &mut impl Iterator<Item = u8>,
+  mode: DecodeMode,
+) -> Result<InflateResult, InflateError> {
+  inflate_to_end(Inflate::with_mode(bytes, mode))
+}
+
+// `inflate`, for a stream compressed with a preset dictionary
+pub fn inflate_with_dictionary(
+  bytes: &mut impl Iterator<Item = u8>,
+  dictionary: &[u8],
+) -> Result<InflateResult, InflateError> {
+  inflate_to_end(Inflate::with_dictionary(bytes, dictionary))
+}
+
+// `inflate_with_dictionary`, for a stream whose matches may only reach
+// `window_size` bytes back
+pub fn inflate_with_window(
+  bytes: &mut impl Iterator<Item = u8>,
+  dictionary: &[u8],
+  window_size: usize,
+) -> Result<InflateResult, InflateError> {
+  let mut inflate = Inflate::with_dictionary(bytes, dictionary);
+  inflate.limit_window(window_size);
+  inflate_to_end(inflate)
+}
+
+// Decode the whole of a stream set up with one of the `Inflate`
+// constructors, as the functions above do. This is the way in for a stream
+// that should be traced: call `trace` on it first.
+pub fn inflate_to_end<I: Iterator<Item = u8>>(
+  mut inflator: Inflate<I>,
+) -> Result<InflateResult, InflateError> {
+  while !inflator.is_finished() {
+    inflator.step()?;
+  }
+  inflator.result.crc32 = inflator.crc32();
+  inflator.result.data = mem::take(&mut inflator.output);
+  inflator.result.compressed_len = inflator.bit_position().div_ceil(8);
+  Ok(inflator.result)
+}
+
+// The result of decoding a raw deflate stream (one with no zlib or gzip
+// wrapper) from the start of a buffer, and where in the buffer it ended
+#[derive(Debug)]
+pub struct RawInflate<'a> {
+  pub data: Vec<u8>,
+  pub blocks: Vec<Block>,
+  pub crc32: u32,
+  pub bytes_consumed: usize, // including the partly used final byte
+  pub unused_bits: u8,       // high bits of the final byte after the end of the stream
+  pub remainder: &'a [u8],   // the bytes after the stream, such as a trailer
+}
+
+// Decode the deflate stream at the start of `bytes`, without recording
+// DecodeItems
+pub fn inflate_raw(bytes: &[u8]) -> Result<RawInflate<'_>, InflateError> {
+  let mut inflator = Inflate::new(bytes.iter().cloned());
+  while !inflator.is_finished() {
+    inflator.step()?;
+  }
+  Ok(raw_result(inflator, bytes))
+}
+
+// `inflate_raw`, giving up with `None` as soon as there is more than `limit`
+// bytes of data. A few bytes of deflate can expand a thousandfold, so this
+// is for streams read from files that cannot be trusted to stay small.
+pub fn inflate_raw_limited(
+  bytes: &[u8],
+  limit: usize,
+) -> Result<Option<RawInflate<'_>>, InflateError> {
+  let mut inflator = Inflate::new(bytes.iter().cloned());
+  while !inflator.is_finished() {
+    inflator.step()?;
+    if inflator.output.len() > limit {
+      return Ok(None);
+    }
+  }
+  Ok(Some(raw_result(inflator, bytes)))
+}
+
+fn raw_result<'a, I: Iterator<Item = u8>>(
+  mut inflator: Inflate<I>,
+  bytes: &'a [u8],
+) -> RawInflate<'a> {
+  let bit_position = inflator.bit_position();
+  let bytes_consumed = bit_position.div_ceil(8);
+  RawInflate {
+    crc32: inflator.crc32(),
+    data: mem::take(&mut inflator.output),
+    blocks: mem::take(&mut inflator.result.blocks),
+    bytes_consumed,
+    unused_bits: (bytes_consumed * 8 - bit_position) as u8,
+    remainder: &bytes[bytes_consumed..],
+  }
+}
+
+// Iterates over the whole bytes that follow the end of a deflate stream
+pub struct RemainingBytes<'a, I: Iterator<Item = u8>> {
+  bits: &'a mut BitReader<I>,
+}
+
+impl<'a, I: Iterator<Item = u8>> Iterator for RemainingBytes<'a, I> {
+  type Item = u8;
+  fn next(&mut self) -> Option<u8> {
+    self.bits.read_bits_inv(8).map(|byte| byte as u8)
+  }
+}
+
+// Where in the stream decoding failed, and why
+#[derive(Debug, PartialEq)]
+pub struct InflateError {
+  pub block: usize,      // index of the block being decoded
+  pub bit_offset: usize, // from the start of the deflate stream
+  pub kind: InflateErrorKind,
 }
 
 #[derive(Debug, PartialEq)]
+pub enum InflateErrorKind {
+  UnexpectedEnd,
+  ReservedBlockType,
+  StoredLengthMismatch { len: u16, nlen: u16 },
+  TooManyLengthCodes(usize),
+  TooManyDistanceCodes(usize),
+  RepeatWithoutPrevious,
+  CodeLengthsOverflow,
+  MissingEndOfBlock,
+  OversubscribedCodeLengths,
+  InvalidCode,
+  InvalidLengthSymbol(u32),
+  InvalidDistanceSymbol(u32),
+  DistanceTooFar { distance: u32, available: usize },
+}
+
+impl InflateErrorKind {
+  // The section of RFC 1951 (https://tools.ietf.org/html/rfc1951) that the
+  // stream violated
+  pub fn rfc_section(&self) -> &'static str {
+    use InflateErrorKind::*;
+    match self {
+      UnexpectedEnd | ReservedBlockType => "3.2.3",
+      StoredLengthMismatch { .. } => "3.2.4",
+      InvalidLengthSymbol(_) | InvalidDistanceSymbol(_) | DistanceTooFar { .. } => "3.2.5",
+      OversubscribedCodeLengths | InvalidCode => "3.2.2",
+      TooManyLengthCodes(_)
+      | TooManyDistanceCodes(_)
+      | RepeatWithoutPrevious
+      | CodeLengthsOverflow
+      | MissingEndOfBlock => "3.2.7",
+    }
+  }
+}
+
+impl fmt::Display for InflateErrorKind {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    use InflateErrorKind::*;
+    match self {
+      UnexpectedEnd => write!(f, "stream ended before the final block was complete"),
+      ReservedBlockType => write!(f, "block type 11 is reserved"),
+      StoredLengthMismatch { len, nlen } => write!(
+        f,
+        "stored block LEN {:#06x} is not the one's complement of NLEN {:#06x}",
+        len, nlen
+      ),
+      TooManyLengthCodes(n) => write!(f, "{} literal/length codes (at most 286 allowed)", n),
+      TooManyDistanceCodes(n) => write!(f, "{} distance codes (at most 30 allowed)", n),
+      RepeatWithoutPrevious => write!(f, "code length 16 used with no previous length"),
+      CodeLengthsOverflow => write!(f, "code length repeat runs past HLIT + HDIST"),
+      MissingEndOfBlock => write!(f, "end-of-block code 256 has no code length"),
+      OversubscribedCodeLengths => write!(f, "code lengths are over-subscribed"),
+      InvalidCode => write!(f, "bits do not match any code in an incomplete tree"),
+      InvalidLengthSymbol(code) => write!(f, "literal/length symbol {} is not used", code),
+      InvalidDistanceSymbol(code) => write!(f, "distance symbol {} is not used", code),
+      DistanceTooFar {
+        distance,
+        available,
+      } => write!(
+        f,
+        "distance {} reaches back further than the {} bytes of window available",
+        distance, available
+      ),
+    }
+  }
+}
+
+impl fmt::Display for InflateError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "block {}, bit {}: {} (RFC 1951 section {})",
+      self.block,
+      self.bit_offset,
+      self.kind,
+      self.kind.rfc_section()
+    )
+  }
+}
+
+impl Error for InflateError {}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum BlockEncoding {
   HuffmanFixed,
   HuffmanDynamic,
   Stored,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct Block {
   pub is_last: bool,
   pub encoding: BlockEncoding,
@@ -305,7 +841,6 @@ pub struct DecodeData {
   pub block_id: usize,
 }
 
-use std::fmt;
 impl fmt::Display for DecodeItem {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     match self {
@@ -324,3 +859,201 @@ impl fmt::Display for DecodeItem {
     }
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  // Pack bits into bytes, least-significant bit first, the way deflate does
+  fn pack_bits(bits: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0; bits.len().div_ceil(8)];
+    for (i, &bit) in bits.iter().enumerate() {
+      bytes[i / 8] |= bit << (i % 8);
+    }
+    bytes
+  }
+
+  fn inflate_err(bytes: Vec<u8>) -> InflateError {
+    match inflate(&mut bytes.into_iter()) {
+      Ok(_) => panic!("Expected inflate to fail"),
+      Err(e) => e,
+    }
+  }
+
+  #[test]
+  fn test_tree_and_table_agree() {
+    use std::fs;
+    for entry in fs::read_dir("tests/gzip/compressed/").expect("failed to read dir") {
+      let gzip = fs::read(entry.expect("failed entry").path()).expect("failed to read file");
+      // Skip the 10-byte header and the file name that follows it
+      let name_len = gzip[10..].iter().position(|&b| b == 0).unwrap();
+      let stream = &gzip[(10 + name_len + 1)..];
+
+      let traced = |mode| {
+        let mut inflate = Inflate::with_mode(stream.iter().cloned(), mode);
+        inflate.trace();
+        inflate_to_end(inflate).unwrap()
+      };
+      let (tree, table) = (traced(DecodeMode::Tree), traced(DecodeMode::Table));
+      assert!(!tree.decode_items.is_empty());
+      let untraced = inflate(&mut stream.iter().cloned()).unwrap();
+      assert!(untraced.decode_items.is_empty());
+      assert_eq!(untraced.data, table.data);
+      assert_eq!(tree.data, table.data);
+      assert_eq!(tree.crc32, table.crc32);
+      assert_eq!(
+        format!("{:?}", tree.decode_items),
+        format!("{:?}", table.decode_items)
+      );
+    }
+  }
+
+  #[test]
+  fn test_inflate_raw() {
+    // An empty fixed block takes ten bits, leaving six in the second byte
+    let bytes = [0x03, 0x00, 0xaa, 0xbb];
+    let raw = inflate_raw(&bytes).unwrap();
+    assert_eq!(raw.data, vec![]);
+    assert_eq!(raw.bytes_consumed, 2);
+    assert_eq!(raw.unused_bits, 6);
+    assert_eq!(raw.remainder, &[0xaa, 0xbb]);
+
+    // A stored block ends on a byte boundary
+    let bytes = [0x01, 0x02, 0x00, 0xfd, 0xff, b'h', b'i', 0xcc];
+    let raw = inflate_raw(&bytes).unwrap();
+    assert_eq!(raw.data, b"hi");
+    assert_eq!(raw.blocks[0].encoding, BlockEncoding::Stored);
+    assert_eq!(raw.bytes_consumed, 7);
+    assert_eq!(raw.unused_bits, 0);
+    assert_eq!(raw.remainder, &[0xcc]);
+  }
+
+  #[test]
+  fn test_inflate_raw_matches_gzip_trailer() {
+    let gzip = include_bytes!("../../tests/gzip/compressed/raven");
+    let name_len = gzip[10..].iter().position(|&b| b == 0).unwrap();
+    let stream = &gzip[(10 + name_len + 1)..];
+    let raw = inflate_raw(stream).unwrap();
+    assert_eq!(raw.data, &include_bytes!("../../tests/gzip/src/raven")[..]);
+    assert_eq!(raw.remainder.len(), 8);
+    assert_eq!(raw.remainder[..4], raw.crc32.to_le_bytes());
+    assert!(raw.unused_bits < 8);
+  }
+
+  #[test]
+  fn test_inflate_raw_limited() {
+    let src = include_bytes!("../../tests/gzip/src/raven");
+    let stream = encode(src);
+    let raw = inflate_raw_limited(&stream, src.len()).unwrap().unwrap();
+    assert_eq!(raw.data, &src[..]);
+    assert!(inflate_raw_limited(&stream, src.len() - 1)
+      .unwrap()
+      .is_none());
+  }
+
+  #[test]
+  fn test_resume_at_block_boundaries() {
+    let src = include_bytes!("../../tests/gzip/src/testpng");
+    let compressed = encode(src);
+
+    // Note the bit position, output offset and window at every boundary
+    let mut inflate = Inflate::new(compressed.iter().cloned());
+    let mut output = 0;
+    let mut boundaries = vec![];
+    while !inflate.is_finished() {
+      inflate.decode_block().unwrap();
+      output += inflate.take_output().len();
+      if inflate.at_block_boundary() {
+        boundaries.push((inflate.bit_position(), output, inflate.window()));
+      }
+    }
+    assert!(boundaries.len() > 1);
+
+    for (bit_position, output, window) in boundaries {
+      // Boundaries fall anywhere in a byte
+      let bytes = compressed[bit_position / 8..].iter().cloned();
+      let mut inflate = Inflate::resume(bytes, (bit_position % 8) as u8, &window);
+      let mut data = vec![];
+      while !inflate.is_finished() {
+        inflate.decode_block().unwrap();
+        data.extend(inflate.take_output());
+      }
+      assert_eq!(&data[..], &src[output..]);
+    }
+  }
+
+  #[test]
+  fn test_reserved_block_type() {
+    let err = inflate_err(vec![0b111]);
+    assert_eq!(err.kind, InflateErrorKind::ReservedBlockType);
+    assert_eq!(err.block, 0);
+    assert_eq!(err.bit_offset, 0);
+  }
+
+  #[test]
+  fn test_stored_length_mismatch() {
+    let err = inflate_err(vec![0b001, 0x05, 0x00, 0x00, 0x00]);
+    assert_eq!(
+      err.kind,
+      InflateErrorKind::StoredLengthMismatch { len: 5, nlen: 0 }
+    );
+    assert_eq!(err.bit_offset, 8);
+  }
+
+  #[test]
+  fn test_unexpected_end() {
+    // A stored block of 5 bytes that stops after 2, in the second block
+    let err = inflate_err(vec![
+      0b000, 0, 0, 0xff, 0xff, 0b001, 5, 0, 0xfa, 0xff, b'a', b'b',
+    ]);
+    assert_eq!(err.kind, InflateErrorKind::UnexpectedEnd);
+    assert_eq!(err.block, 1);
+    assert_eq!(err.bit_offset, 12 * 8);
+  }
+
+  #[test]
+  fn test_distance_too_far() {
+    // Fixed block whose first symbol is a length 3, distance 1 match
+    let bits = [
+      1, 1, 0, // BFINAL, BTYPE = fixed
+      0, 0, 0, 0, 0, 0, 1, // length code 257
+      0, 0, 0, 0, 0, // distance code 0
+    ];
+    let err = inflate_err(pack_bits(&bits));
+    assert_eq!(
+      err.kind,
+      InflateErrorKind::DistanceTooFar {
+        distance: 1,
+        available: 0
+      }
+    );
+    assert_eq!(err.bit_offset, 3);
+  }
+
+  #[test]
+  fn test_invalid_distance_symbol() {
+    // Fixed block: literal 'a', then a match using distance code 30
+    let bits = [
+      1, 1, 0, // BFINAL, BTYPE = fixed
+      1, 0, 0, 1, 0, 0, 0, 1, // literal 0x61 ('a')
+      0, 0, 0, 0, 0, 0, 1, // length code 257
+      1, 1, 1, 1, 0, // distance code 30
+    ];
+    let err = inflate_err(pack_bits(&bits));
+    assert_eq!(err.kind, InflateErrorKind::InvalidDistanceSymbol(30));
+    assert_eq!(err.bit_offset, 18);
+  }
+
+  #[test]
+  fn test_too_many_length_codes() {
+    let bits = [
+      1, 0, 1, // BFINAL, BTYPE = dynamic
+      1, 1, 1, 1, 1, // HLIT = 31
+      0, 0, 0, 0, 0, // HDIST
+      0, 0, 0, 0, // HCLEN
+    ];
+    let err = inflate_err(pack_bits(&bits));
+    assert_eq!(err.kind, InflateErrorKind::TooManyLengthCodes(288));
+    assert_eq!(err.kind.rfc_section(), "3.2.7");
+  }
+}
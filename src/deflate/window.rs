@@ -0,0 +1,135 @@
+// A match can reach at most 32 KiB back, see https://tools.ietf.org/html/rfc1951#section-2
+pub const WINDOW_SIZE: usize = 32 * 1024;
+
+// A ring buffer of the most recent output, which is all that a decoder
+// needs to remember in order to resolve matches
+pub struct Window {
+  buf: Vec<u8>,
+  pos: usize, // where the next byte will be written
+  len: usize, // how many bytes are valid, up to WINDOW_SIZE
+}
+
+impl Window {
+  pub fn new() -> Window {
+    Window {
+      buf: vec![0; WINDOW_SIZE],
+      pos: 0,
+      len: 0,
+    }
+  }
+
+  pub fn push(&mut self, byte: u8) {
+    self.buf[self.pos] = byte;
+    self.pos = (self.pos + 1) & (WINDOW_SIZE - 1);
+    if self.len < WINDOW_SIZE {
+      self.len += 1;
+    }
+  }
+
+  // Fill the window with a preset dictionary, as if it had been output. Only
+  // its last WINDOW_SIZE bytes can be reached.
+  pub fn preset(&mut self, dictionary: &[u8]) {
+    self.extend(dictionary);
+  }
+
+  // `push` every byte of `bytes`, a slice at a time. Only the last
+  // WINDOW_SIZE of them are kept.
+  pub fn extend(&mut self, bytes: &[u8]) {
+    let bytes = &bytes[bytes.len().saturating_sub(WINDOW_SIZE)..];
+    let first = bytes.len().min(WINDOW_SIZE - self.pos);
+    self.buf[self.pos..self.pos + first].copy_from_slice(&bytes[..first]);
+    self.buf[..bytes.len() - first].copy_from_slice(&bytes[first..]);
+    self.pos = (self.pos + bytes.len()) & (WINDOW_SIZE - 1);
+    self.len = (self.len + bytes.len()).min(WINDOW_SIZE);
+  }
+
+  // The byte `distance` positions back from the most recent one (which is at
+  // distance 1). Callers must check `distance <= len()` first.
+  pub fn get(&self, distance: usize) -> u8 {
+    self.buf[(self.pos + WINDOW_SIZE - distance) & (WINDOW_SIZE - 1)]
+  }
+
+  pub fn len(&self) -> usize {
+    self.len
+  }
+
+  // The valid bytes, oldest first
+  pub fn to_vec(&self) -> Vec<u8> {
+    let start = (self.pos + WINDOW_SIZE - self.len) & (WINDOW_SIZE - 1);
+    if start + self.len <= WINDOW_SIZE {
+      self.buf[start..start + self.len].to_vec()
+    } else {
+      [&self.buf[start..], &self.buf[..self.pos]].concat()
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_get() {
+    let mut window = Window::new();
+    assert_eq!(window.len(), 0);
+    for byte in b"abc" {
+      window.push(*byte);
+    }
+    assert_eq!(window.len(), 3);
+    assert_eq!(window.get(1), b'c');
+    assert_eq!(window.get(3), b'a');
+  }
+
+  #[test]
+  fn test_wraps_around() {
+    let mut window = Window::new();
+    for i in 0..(WINDOW_SIZE + 10) {
+      window.push(i as u8);
+    }
+    assert_eq!(window.len(), WINDOW_SIZE);
+    assert_eq!(window.get(1), (WINDOW_SIZE + 9) as u8);
+    assert_eq!(window.get(WINDOW_SIZE), 10);
+  }
+
+  #[test]
+  fn test_to_vec() {
+    let mut window = Window::new();
+    assert_eq!(window.to_vec(), b"");
+    window.preset(b"abc");
+    assert_eq!(window.to_vec(), b"abc");
+
+    let data: Vec<u8> = (0..(WINDOW_SIZE + 10)).map(|i| (i % 251) as u8).collect();
+    window.preset(&data);
+    assert_eq!(window.to_vec(), &data[10..]);
+  }
+
+  #[test]
+  fn test_extend_matches_push() {
+    let data: Vec<u8> = (0..(3 * WINDOW_SIZE)).map(|i| (i % 253) as u8).collect();
+    let (mut pushed, mut extended) = (Window::new(), Window::new());
+    let mut start = 0;
+    for &len in [0, 5, WINDOW_SIZE - 3, 10, WINDOW_SIZE + 7, 1].iter().cycle().take(12) {
+      let end = (start + len).min(data.len());
+      for &byte in &data[start..end] {
+        pushed.push(byte);
+      }
+      extended.extend(&data[start..end]);
+      assert_eq!(extended.to_vec(), pushed.to_vec());
+      start = end;
+    }
+  }
+
+  #[test]
+  fn test_preset() {
+    let mut window = Window::new();
+    window.preset(b"xyz");
+    assert_eq!(window.len(), 3);
+    assert_eq!(window.get(3), b'x');
+
+    let mut window = Window::new();
+    let dictionary: Vec<u8> = (0..(WINDOW_SIZE + 10)).map(|i| i as u8).collect();
+    window.preset(&dictionary);
+    assert_eq!(window.len(), WINDOW_SIZE);
+    assert_eq!(window.get(WINDOW_SIZE), 10);
+  }
+}
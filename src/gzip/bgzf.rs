@@ -0,0 +1,639 @@
+// BGZF, the blocked gzip of the SAM/BAM specification (section 4.1): a
+// series of gzip members of at most 64K each, every one with a "BC" extra
+// subfield giving its size, so that a reader can find the start of any
+// block without decompressing those before it. A position in the data is a
+// 64-bit "virtual offset": the file offset of a block's first byte, shifted
+// up 16 bits, plus the offset in that block's data. The file ends with an
+// empty block, the EOF marker, whose absence means it was cut short.
+use super::extra::{bgzf_block_size, split_fields};
+use super::{check_trailer, ExtraField, GzipError, Headers, HeadersBuilder, MAGIC, TRAILER_LEN};
+use crate::crc32;
+use crate::deflate::{encode_with_level, inflate_raw_limited, CompressionLevel};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+// The most a block can take up, compressed or not
+pub const MAX_BLOCK_SIZE: usize = 64 * 1024;
+
+// The most data the writer puts in a block, as htslib does, so that even
+// data that does not compress fits with room for the header and trailer
+const MAX_BLOCK_DATA: usize = 0xff00;
+
+pub const EOF_MARKER: [u8; 28] = [
+  0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+  0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+// The gzip header up to and including XLEN
+const FIXED_HEADER_LEN: usize = 12;
+
+const FEXTRA: u8 = 0b100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VirtualOffset(u64);
+
+impl VirtualOffset {
+  // `block_offset` is from the start of the file, and must fit in 48 bits
+  pub fn new(block_offset: u64, within_block: u16) -> VirtualOffset {
+    assert!(
+      block_offset < 1 << 48,
+      "block offset {} too large",
+      block_offset
+    );
+    VirtualOffset(block_offset << 16 | u64::from(within_block))
+  }
+
+  pub fn block_offset(self) -> u64 {
+    self.0 >> 16
+  }
+
+  pub fn within_block(self) -> u16 {
+    self.0 as u16
+  }
+}
+
+impl From<u64> for VirtualOffset {
+  fn from(offset: u64) -> VirtualOffset {
+    VirtualOffset(offset)
+  }
+}
+
+impl From<VirtualOffset> for u64 {
+  fn from(offset: VirtualOffset) -> u64 {
+    offset.0
+  }
+}
+
+impl fmt::Display for VirtualOffset {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}:{}", self.block_offset(), self.within_block())
+  }
+}
+
+#[derive(Debug)]
+pub struct Block {
+  pub offset: u64, // of the block's first byte in the file
+  pub len: usize,  // bytes of the file the block takes up
+  pub data: Vec<u8>,
+  is_eof_marker: bool,
+}
+
+impl Block {
+  // Parse the block at the start of `bytes`, which must hold all of it (and
+  // may hold more). Offsets in errors are from the start of `bytes`.
+  pub fn parse(bytes: &[u8], offset: u64) -> Result<Block, BgzfError> {
+    let member = |error: GzipError| BgzfError::Member { offset, error };
+    let mut iter = bytes.iter().cloned();
+    let headers = Headers::new(&mut iter).map_err(member)?;
+    let header_len = bytes.len() - iter.len();
+    headers.check_reserved_flags().map_err(member)?;
+    headers.check_crc16(header_len - 2).map_err(member)?;
+
+    let block_size =
+      bgzf_block_size(&headers.extra_fields).ok_or(BgzfError::MissingBlockSize { offset })?;
+    let len = usize::from(block_size) + 1;
+    if len < header_len + 8 {
+      return Err(BgzfError::BadBlockSize {
+        offset,
+        size: block_size,
+      });
+    }
+    if bytes.len() < len {
+      return Err(member(GzipError::TruncatedTrailer {
+        offset: bytes.len(),
+      }));
+    }
+
+    // However small the block, its data cannot be more than 64K either
+    let raw = inflate_raw_limited(&bytes[header_len..len], MAX_BLOCK_SIZE)
+      .map_err(|error| member(GzipError::inflate(header_len, error)))?
+      .ok_or(BgzfError::BadBlockSize {
+        offset,
+        size: block_size,
+      })?;
+    let trailer_start = header_len + raw.bytes_consumed;
+    if raw.remainder.len() != TRAILER_LEN {
+      // The deflate stream should end exactly where the trailer starts
+      return Err(BgzfError::BadBlockSize {
+        offset,
+        size: block_size,
+      });
+    }
+    check_trailer(
+      raw.remainder,
+      trailer_start,
+      raw.crc32,
+      raw.data.len() as u64,
+    )
+    .map_err(member)?;
+    Ok(Block {
+      offset,
+      len,
+      data: raw.data,
+      is_eof_marker: bytes[..len] == EOF_MARKER,
+    })
+  }
+
+  pub fn is_eof_marker(&self) -> bool {
+    self.is_eof_marker
+  }
+}
+
+// Reads the data of a BGZF file, a block at a time. With a reader that can
+// seek, it can jump straight to any virtual offset.
+pub struct BgzfReader<R: Read> {
+  inner: R,
+  block: Option<Block>, // the block being read from
+  pos: usize,           // how much of its data has been read
+  next_offset: u64,     // of the block after it
+  saw_eof_marker: bool,
+}
+
+impl<R: Read> BgzfReader<R> {
+  pub fn new(inner: R) -> BgzfReader<R> {
+    BgzfReader {
+      inner,
+      block: None,
+      pos: 0,
+      next_offset: 0,
+      saw_eof_marker: false,
+    }
+  }
+
+  // Move on to the next block, returning it, or `None` at the end of the
+  // file. Any data left unread in the current block is skipped.
+  pub fn read_block(&mut self) -> io::Result<Option<&Block>> {
+    let offset = self.next_offset;
+    let mut header = [0; FIXED_HEADER_LEN];
+    let read = read_fully(&mut self.inner, &mut header)?;
+    if read == 0 {
+      self.block = None;
+      self.pos = 0;
+      return Ok(None);
+    }
+    let member = |error: GzipError| BgzfError::Member { offset, error };
+    if read < FIXED_HEADER_LEN {
+      return Err(member(GzipError::TruncatedHeader { offset: read }).into());
+    }
+    if header[..2] != MAGIC {
+      return Err(member(GzipError::BadMagic { offset: 0 }).into());
+    }
+    if header[3] & FEXTRA == 0 {
+      return Err(BgzfError::MissingBlockSize { offset }.into());
+    }
+
+    // The BC subfield gives the size of the whole block
+    let xlen = usize::from(u16::from_le_bytes([header[10], header[11]]));
+    let mut bytes = header.to_vec();
+    bytes.resize(FIXED_HEADER_LEN + xlen, 0);
+    self.read_exact_at(&mut bytes[FIXED_HEADER_LEN..], offset, FIXED_HEADER_LEN)?;
+    let block_size = split_fields(&bytes[FIXED_HEADER_LEN..])
+      .and_then(|fields| bgzf_block_size(&fields))
+      .ok_or(BgzfError::MissingBlockSize { offset })?;
+    let len = usize::from(block_size) + 1;
+    if len < bytes.len() {
+      return Err(
+        BgzfError::BadBlockSize {
+          offset,
+          size: block_size,
+        }
+        .into(),
+      );
+    }
+    let start = bytes.len();
+    bytes.resize(len, 0);
+    self.read_exact_at(&mut bytes[start..], offset, start)?;
+
+    let block = Block::parse(&bytes, offset)?;
+    self.saw_eof_marker = block.is_eof_marker();
+    self.next_offset = offset + len as u64;
+    self.pos = 0;
+    Ok(Some(self.block.insert(block)))
+  }
+
+  // Where the next byte of data will be read from
+  pub fn virtual_offset(&self) -> VirtualOffset {
+    match &self.block {
+      // The end of a full 64K of data is past the last offset a virtual
+      // offset can give, so it can only be given as the start of the next
+      // block
+      Some(_) if self.pos > usize::from(u16::MAX) => VirtualOffset::new(self.next_offset, 0),
+      Some(block) => VirtualOffset::new(block.offset, self.pos as u16),
+      None => VirtualOffset::new(self.next_offset, 0),
+    }
+  }
+
+  // Once the data has been read to the end, whether the file ended with the
+  // EOF marker block. Without it the file may have been cut short.
+  pub fn has_eof_marker(&self) -> bool {
+    self.saw_eof_marker
+  }
+
+  pub fn get_ref(&self) -> &R {
+    &self.inner
+  }
+
+  // Fill `buf` with the bytes from `at` on in the block at `offset`, which
+  // must all be there
+  fn read_exact_at(&mut self, buf: &mut [u8], offset: u64, at: usize) -> io::Result<()> {
+    let read = read_fully(&mut self.inner, buf)?;
+    if read < buf.len() {
+      let error = GzipError::TruncatedTrailer { offset: at + read };
+      return Err(BgzfError::Member { offset, error }.into());
+    }
+    Ok(())
+  }
+}
+
+impl<R: Read + Seek> BgzfReader<R> {
+  // Go to a virtual offset, such as one from a BAI or tabix index
+  pub fn seek(&mut self, offset: VirtualOffset) -> io::Result<()> {
+    self.inner.seek(SeekFrom::Start(offset.block_offset()))?;
+    self.next_offset = offset.block_offset();
+    let within = usize::from(offset.within_block());
+    let len = self.read_block()?.map_or(0, |block| block.data.len());
+    // The end of a block's data is allowed, as the same place as the start
+    // of the next
+    if within > len {
+      return Err(BgzfError::BadVirtualOffset { offset }.into());
+    }
+    self.pos = within;
+    Ok(())
+  }
+}
+
+impl<R: Read> Read for BgzfReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+      if let Some(block) = &self.block {
+        if self.pos < block.data.len() {
+          let len = buf.len().min(block.data.len() - self.pos);
+          buf[..len].copy_from_slice(&block.data[self.pos..self.pos + len]);
+          self.pos += len;
+          return Ok(len);
+        }
+      }
+      // Empty blocks, the EOF marker among them, are passed over
+      if self.read_block()?.is_none() {
+        return Ok(0);
+      }
+    }
+  }
+}
+
+// Writes data as BGZF, in blocks of up to 0xff00 bytes of data, each
+// compressed on its own. `finish` writes the last block and the EOF marker.
+pub struct BgzfWriter<W: Write> {
+  inner: W,
+  level: CompressionLevel,
+  buf: Vec<u8>,
+  offset: u64, // of the next block
+}
+
+impl<W: Write> BgzfWriter<W> {
+  pub fn new(inner: W) -> BgzfWriter<W> {
+    BgzfWriter::with_level(inner, CompressionLevel::default())
+  }
+
+  pub fn with_level(inner: W, level: CompressionLevel) -> BgzfWriter<W> {
+    BgzfWriter {
+      inner,
+      level,
+      buf: Vec::with_capacity(MAX_BLOCK_DATA),
+      offset: 0,
+    }
+  }
+
+  // Where the next byte written will be, for building an index as the file
+  // is written
+  pub fn virtual_offset(&self) -> VirtualOffset {
+    VirtualOffset::new(self.offset, self.buf.len() as u16)
+  }
+
+  pub fn get_ref(&self) -> &W {
+    &self.inner
+  }
+
+  pub fn finish(mut self) -> io::Result<W> {
+    if !self.buf.is_empty() {
+      self.write_block()?;
+    }
+    self.inner.write_all(&EOF_MARKER)?;
+    self.inner.flush()?;
+    Ok(self.inner)
+  }
+
+  fn write_block(&mut self) -> io::Result<()> {
+    let block = encode_block(&self.buf, self.level)?;
+    self.inner.write_all(&block)?;
+    self.offset += block.len() as u64;
+    self.buf.clear();
+    Ok(())
+  }
+}
+
+// Compress `data` into a single block. Data that does not compress is
+// stored, so up to MAX_BLOCK_DATA always fits.
+fn encode_block(data: &[u8], level: CompressionLevel) -> io::Result<Vec<u8>> {
+  let compressed = encode_with_level(data, level);
+  // Header (with the BC subfield), compressed data and trailer, less one
+  let block_size = 18 + compressed.len() + 8 - 1;
+  assert!(block_size < MAX_BLOCK_SIZE);
+  let mut block = Vec::with_capacity(block_size + 1);
+  HeadersBuilder::new()
+    .extra_field(ExtraField {
+      id: *b"BC",
+      data: (block_size as u16).to_le_bytes().to_vec(),
+    })
+    .build()
+    .write_to(&mut block)?;
+  block.extend_from_slice(&compressed);
+  block.extend_from_slice(&crc32::crc32(data).to_le_bytes());
+  block.extend_from_slice(&(data.len() as u32).to_le_bytes());
+  Ok(block)
+}
+
+impl<W: Write> Write for BgzfWriter<W> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let len = buf.len().min(MAX_BLOCK_DATA - self.buf.len());
+    self.buf.extend_from_slice(&buf[..len]);
+    if self.buf.len() == MAX_BLOCK_DATA {
+      self.write_block()?;
+    }
+    Ok(len)
+  }
+
+  // Ends the current block early, so that everything written so far can be
+  // read back
+  fn flush(&mut self) -> io::Result<()> {
+    if !self.buf.is_empty() {
+      self.write_block()?;
+    }
+    self.inner.flush()
+  }
+}
+
+/// The ways a BGZF file can fail to read. Every variant carries the offset
+/// in the file of the block with the problem.
+#[derive(Debug, PartialEq)]
+pub enum BgzfError {
+  /// The block is not a valid gzip member; the error's own offset is from
+  /// the start of the block
+  Member { offset: u64, error: GzipError },
+  /// The block has no BC extra subfield giving its size
+  MissingBlockSize { offset: u64 },
+  /// The BC subfield gives a size that does not fit the block
+  BadBlockSize { offset: u64, size: u16 },
+  /// A virtual offset points past the end of its block's data
+  BadVirtualOffset { offset: VirtualOffset },
+}
+
+impl BgzfError {
+  pub fn offset(&self) -> u64 {
+    use BgzfError::*;
+    match *self {
+      Member { offset, .. } | MissingBlockSize { offset } | BadBlockSize { offset, .. } => offset,
+      BadVirtualOffset { offset } => offset.block_offset(),
+    }
+  }
+}
+
+impl fmt::Display for BgzfError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    use BgzfError::*;
+    match self {
+      Member { offset, error } => write!(f, "in the block at byte {}: {}", offset, error),
+      MissingBlockSize { offset } => {
+        write!(f, "block at byte {} has no BC subfield (not BGZF?)", offset)
+      }
+      BadBlockSize { offset, size } => write!(
+        f,
+        "block at byte {} has BC size {}, which does not fit it",
+        offset, size
+      ),
+      BadVirtualOffset { offset } => {
+        write!(f, "virtual offset {} is past the end of its block", offset)
+      }
+    }
+  }
+}
+
+impl Error for BgzfError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      BgzfError::Member { error, .. } => Some(error),
+      _ => None,
+    }
+  }
+}
+
+impl From<BgzfError> for io::Error {
+  fn from(error: BgzfError) -> io::Error {
+    let kind = match &error {
+      BgzfError::Member {
+        error: GzipError::TruncatedHeader { .. },
+        ..
+      }
+      | BgzfError::Member {
+        error: GzipError::TruncatedTrailer { .. },
+        ..
+      } => io::ErrorKind::UnexpectedEof,
+      _ => io::ErrorKind::InvalidData,
+    };
+    io::Error::new(kind, error)
+  }
+}
+
+// Like `read_exact`, but stopping quietly at the end of the input, and
+// returning how much was read
+fn read_fully(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+  let mut read = 0;
+  while read < buf.len() {
+    match reader.read(&mut buf[read..]) {
+      Ok(0) => break,
+      Ok(len) => read += len,
+      Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+      Err(e) => return Err(e),
+    }
+  }
+  Ok(read)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::gzip::Gzip;
+  use std::io::Cursor;
+
+  fn fixture() -> Vec<u8> {
+    ["testpng", "gunzip", "raven"]
+      .iter()
+      .flat_map(|name| std::fs::read(format!("tests/gzip/src/{}", name)).unwrap())
+      .collect()
+  }
+
+  // Full blocks and any partial one, leaving out the EOF marker
+  fn blocks_of(data: &[u8]) -> usize {
+    data.len().div_ceil(MAX_BLOCK_DATA)
+  }
+
+  fn write(data: &[u8]) -> Vec<u8> {
+    let mut writer = BgzfWriter::new(vec![]);
+    writer.write_all(data).unwrap();
+    writer.finish().unwrap()
+  }
+
+  #[test]
+  fn test_eof_marker() {
+    assert_eq!(write(b""), EOF_MARKER);
+    let block = Block::parse(&EOF_MARKER, 0).unwrap();
+    assert!(block.is_eof_marker());
+    assert_eq!(block.len, EOF_MARKER.len());
+    assert!(block.data.is_empty());
+  }
+
+  #[test]
+  fn test_round_trip() {
+    let data = fixture();
+    let bgzf = write(&data);
+
+    let mut reader = BgzfReader::new(&bgzf[..]);
+    let mut read = vec![];
+    reader.read_to_end(&mut read).unwrap();
+    assert_eq!(read, data);
+    assert!(reader.has_eof_marker());
+
+    // It is also plain multi-member gzip
+    let gzip = Gzip::new(bgzf.clone()).unwrap();
+    assert_eq!(gzip.data, data);
+    assert_eq!(gzip.members.len(), blocks_of(&data) + 1);
+
+    let mut reader = BgzfReader::new(&bgzf[..]);
+    let mut offset = 0;
+    let mut blocks = 0;
+    while let Some(block) = reader.read_block().unwrap() {
+      assert_eq!(block.offset, offset);
+      assert!(block.len <= MAX_BLOCK_SIZE);
+      assert!(block.data.len() <= MAX_BLOCK_DATA);
+      offset += block.len as u64;
+      blocks += 1;
+    }
+    assert_eq!(offset, bgzf.len() as u64);
+    assert_eq!(blocks, blocks_of(&data) + 1);
+  }
+
+  #[test]
+  fn test_seek() {
+    let data = fixture();
+    let mut writer = BgzfWriter::new(vec![]);
+    let mut offsets = vec![];
+    for (i, chunk) in data.chunks(10_000).enumerate() {
+      offsets.push((writer.virtual_offset(), i * 10_000));
+      writer.write_all(chunk).unwrap();
+    }
+    let bgzf = writer.finish().unwrap();
+
+    let mut reader = BgzfReader::new(Cursor::new(&bgzf));
+    for &(offset, start) in offsets.iter().rev() {
+      reader.seek(offset).unwrap();
+      assert_eq!(reader.virtual_offset(), offset);
+      let mut read = vec![0; 500.min(data.len() - start)];
+      reader.read_exact(&mut read).unwrap();
+      assert_eq!(read, &data[start..start + read.len()]);
+    }
+
+    let last = offsets.last().unwrap().0;
+    let past = VirtualOffset::new(last.block_offset(), u16::MAX);
+    let error = reader.seek(past).unwrap_err();
+    assert_eq!(
+      error.to_string(),
+      format!(
+        "virtual offset {}:65535 is past the end of its block",
+        last.block_offset()
+      )
+    );
+  }
+
+  #[test]
+  fn test_virtual_offset() {
+    let offset = VirtualOffset::new(0x1234_5678, 0x9abc);
+    assert_eq!(u64::from(offset), 0x1234_5678_9abc);
+    assert_eq!(VirtualOffset::from(0x1234_5678_9abc), offset);
+    assert_eq!(offset.block_offset(), 0x1234_5678);
+    assert_eq!(offset.within_block(), 0x9abc);
+  }
+
+  #[test]
+  fn test_full_block() {
+    // Another writer could fill a block with all 64K of data
+    let full = encode_block(&[7; MAX_BLOCK_SIZE], CompressionLevel::default()).unwrap();
+    let bgzf = [&full[..], &write(b"next")[..]].concat();
+    let mut reader = BgzfReader::new(&bgzf[..]);
+    let mut read = vec![0; MAX_BLOCK_SIZE];
+    reader.read_exact(&mut read).unwrap();
+    assert_eq!(read, [7; MAX_BLOCK_SIZE]);
+    assert_eq!(reader.virtual_offset(), VirtualOffset::new(full.len() as u64, 0));
+
+    // But no more
+    let over = encode_block(&[7; MAX_BLOCK_SIZE + 1], CompressionLevel::default()).unwrap();
+    let error = Block::parse(&over, 0).map(|_| ()).unwrap_err();
+    assert!(error.to_string().starts_with("block at byte 0 has BC size"));
+  }
+
+  #[test]
+  fn test_missing_eof_marker() {
+    let data = fixture();
+    let bgzf = write(&data);
+    let mut reader = BgzfReader::new(&bgzf[..bgzf.len() - EOF_MARKER.len()]);
+    let mut read = vec![];
+    reader.read_to_end(&mut read).unwrap();
+    assert_eq!(read, data);
+    assert!(!reader.has_eof_marker());
+
+    // Cut short in the middle of a block
+    let mut reader = BgzfReader::new(&bgzf[..1000]);
+    let error = reader.read_to_end(&mut vec![]).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+  }
+
+  #[test]
+  fn test_not_bgzf() {
+    let mut encoder = crate::gzip::GzEncoder::new(vec![]);
+    encoder.write_all(b"plain gzip").unwrap();
+    let gzip = encoder.finish().unwrap();
+    let error = BgzfReader::new(&gzip[..])
+      .read_block()
+      .map(|_| ())
+      .unwrap_err();
+    assert_eq!(
+      error.to_string(),
+      "block at byte 0 has no BC subfield (not BGZF?)"
+    );
+
+    // A wrong BSIZE
+    let mut bgzf = write(b"some data");
+    bgzf[16] += 1;
+    let error = BgzfReader::new(&bgzf[..])
+      .read_block()
+      .map(|_| ())
+      .unwrap_err();
+    assert!(error.to_string().starts_with("block at byte 0 has BC size"));
+
+    // Reserved flags are an error, as for any other member
+    let mut bgzf = write(b"some data");
+    bgzf[3] |= 0x80;
+    let error = Block::parse(&bgzf, 0).map(|_| ()).unwrap_err();
+    assert_eq!(
+      error.to_string(),
+      BgzfError::Member {
+        offset: 0,
+        error: GzipError::ReservedFlags {
+          offset: 3,
+          flags: bgzf[3]
+        }
+      }
+      .to_string()
+    );
+  }
+}
@@ -0,0 +1,334 @@
+use super::{latin1, Compression, CompressionInfo, ExtraField, Flags, Headers, Os, MAGIC};
+use crate::crc32;
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Builds a `Headers` to write out, for a new member or in place of the
+// header of an existing one
+#[derive(Debug)]
+pub struct HeadersBuilder {
+  mtime: u32,
+  os: Os,
+  is_text: bool,
+  filename: Option<Vec<u8>>,
+  comment: Option<Vec<u8>>,
+  extra_fields: Vec<ExtraField>,
+  compression_info: Option<CompressionInfo>,
+  header_crc: bool,
+}
+
+impl HeadersBuilder {
+  pub fn new() -> HeadersBuilder {
+    HeadersBuilder {
+      mtime: 0,
+      os: Os::Unknown,
+      is_text: false,
+      filename: None,
+      comment: None,
+      extra_fields: vec![],
+      compression_info: None,
+      header_crc: false,
+    }
+  }
+
+  // Seconds since the Unix epoch; 0 (the default) means no time is recorded
+  pub fn mtime(mut self, mtime: u32) -> HeadersBuilder {
+    self.mtime = mtime;
+    self
+  }
+
+  // Times before 1970 or after 2106 cannot be recorded, and are left out
+  pub fn mtime_from(self, time: SystemTime) -> HeadersBuilder {
+    let mtime = time
+      .duration_since(UNIX_EPOCH)
+      .ok()
+      .and_then(|since| u32::try_from(since.as_secs()).ok())
+      .unwrap_or(0);
+    self.mtime(mtime)
+  }
+
+  pub fn os(mut self, os: Os) -> HeadersBuilder {
+    self.os = os;
+    self
+  }
+
+  pub fn text(mut self, is_text: bool) -> HeadersBuilder {
+    self.is_text = is_text;
+    self
+  }
+
+  // Characters outside ISO-8859-1 become '?'
+  pub fn filename(self, filename: &str) -> HeadersBuilder {
+    self.filename_bytes(&latin1::encode(filename))
+  }
+
+  pub fn filename_bytes(mut self, filename: &[u8]) -> HeadersBuilder {
+    self.filename = Some(filename.to_vec());
+    self
+  }
+
+  pub fn comment(self, comment: &str) -> HeadersBuilder {
+    self.comment_bytes(&latin1::encode(comment))
+  }
+
+  pub fn comment_bytes(mut self, comment: &[u8]) -> HeadersBuilder {
+    self.comment = Some(comment.to_vec());
+    self
+  }
+
+  pub fn extra_field(mut self, field: ExtraField) -> HeadersBuilder {
+    self.extra_fields.push(field);
+    self
+  }
+
+  pub fn compression_info(mut self, info: Option<CompressionInfo>) -> HeadersBuilder {
+    self.compression_info = info;
+    self
+  }
+
+  // Whether to write an FHCRC, which is computed as the header is written
+  pub fn header_crc(mut self, header_crc: bool) -> HeadersBuilder {
+    self.header_crc = header_crc;
+    self
+  }
+
+  pub fn build(self) -> Headers {
+    let mut headers = Headers {
+      compression: Compression::Deflate,
+      mtime: self.mtime,
+      os: self.os,
+      filename: self.filename,
+      comment: self.comment,
+      crc16: if self.header_crc { Some(0) } else { None },
+      compression_info: self.compression_info,
+      is_text: self.is_text,
+      extra_fields: self.extra_fields,
+      flags: 0,
+      extra_flags: 0,
+      calculated_crc16: 0,
+    };
+    // Fill in what a parsed header would have, from the bytes it becomes
+    headers.extra_flags = headers.xfl();
+    let (flags, crc16) = headers.flags_and_crc16();
+    headers.flags = flags;
+    headers.calculated_crc16 = crc16;
+    if headers.crc16.is_some() {
+      headers.crc16 = Some(u32::from(crc16));
+    }
+    headers
+  }
+}
+
+impl Default for HeadersBuilder {
+  fn default() -> HeadersBuilder {
+    HeadersBuilder::new()
+  }
+}
+
+impl Headers {
+  // Write the header as it would appear at the start of a member. The FLG
+  // bits come from the fields that are set (reserved bits are never
+  // written), and an FHCRC is written, freshly computed, if `crc16` is set.
+  pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+    writer.write_all(&self.to_bytes()?)
+  }
+
+  fn to_bytes(&self) -> io::Result<Vec<u8>> {
+    let mut flags = 0;
+    let mut optional = vec![];
+    if self.is_text {
+      flags |= Flags::Text as u8;
+    }
+    if !self.extra_fields.is_empty() {
+      flags |= Flags::Extra as u8;
+      let mut fields = vec![];
+      for field in &self.extra_fields {
+        fields.extend_from_slice(&field.id);
+        fields.extend_from_slice(&length_u16(field.data.len(), "extra field")?.to_le_bytes());
+        fields.extend_from_slice(&field.data);
+      }
+      optional.extend_from_slice(&length_u16(fields.len(), "extra fields")?.to_le_bytes());
+      optional.extend(fields);
+    }
+    if let Some(filename) = &self.filename {
+      flags |= Flags::FileName as u8;
+      optional.extend(zero_terminated(filename, "filename")?);
+    }
+    if let Some(comment) = &self.comment {
+      flags |= Flags::Comment as u8;
+      optional.extend(zero_terminated(comment, "comment")?);
+    }
+    if self.crc16.is_some() {
+      flags |= Flags::CRC16 as u8;
+    }
+
+    // Magic, compression method (deflate), flags, mtime, extra flags, OS
+    let mut bytes = vec![MAGIC[0], MAGIC[1], 8, flags];
+    bytes.extend_from_slice(&self.mtime.to_le_bytes());
+    bytes.push(self.xfl());
+    bytes.push(self.os.to_byte());
+    bytes.extend(optional);
+    if self.crc16.is_some() {
+      let crc16 = crc32::crc32(&bytes) as u16;
+      bytes.extend_from_slice(&crc16.to_le_bytes());
+    }
+    Ok(bytes)
+  }
+
+  // XFL tells readers how hard the compressor tried. A parsed value is
+  // written back as it was, even one that `compression_info` has no name
+  // for, unless `compression_info` has since been changed.
+  fn xfl(&self) -> u8 {
+    if CompressionInfo::parse(self.extra_flags) == self.compression_info {
+      return self.extra_flags;
+    }
+    match self.compression_info {
+      Some(CompressionInfo::MaximumCompressionSlowestAlgorithm) => 2,
+      Some(CompressionInfo::FastestAlgorithm) => 4,
+      None => 0,
+    }
+  }
+
+  // The FLG byte and header CRC16 that these headers are written with
+  fn flags_and_crc16(&self) -> (u8, u16) {
+    match self.to_bytes() {
+      Ok(bytes) => {
+        let crc16 = if self.crc16.is_some() {
+          let len = bytes.len();
+          u16::from_le_bytes([bytes[len - 2], bytes[len - 1]])
+        } else {
+          crc32::crc32(&bytes) as u16
+        };
+        (bytes[3], crc16)
+      }
+      // Headers that cannot be written have nothing to check against
+      Err(_) => (0, 0),
+    }
+  }
+}
+
+fn length_u16(len: usize, what: &str) -> io::Result<u16> {
+  u16::try_from(len).map_err(|_| {
+    io::Error::new(
+      io::ErrorKind::InvalidInput,
+      format!("{} longer than 65535 bytes", what),
+    )
+  })
+}
+
+fn zero_terminated(bytes: &[u8], what: &str) -> io::Result<Vec<u8>> {
+  if bytes.contains(&0) {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidInput,
+      format!("{} contains a NUL byte", what),
+    ));
+  }
+  let mut result = bytes.to_vec();
+  result.push(0);
+  Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::gzip::Gzip;
+  use std::time::Duration;
+
+  // A whole member around `headers`, with an empty deflate stream
+  fn member(headers: &Headers) -> Vec<u8> {
+    let mut bytes = vec![];
+    headers.write_to(&mut bytes).unwrap();
+    bytes.extend_from_slice(&[0x03, 0x00, 0, 0, 0, 0, 0, 0, 0, 0]);
+    bytes
+  }
+
+  #[test]
+  fn test_round_trip() {
+    let headers = HeadersBuilder::new()
+      .mtime_from(UNIX_EPOCH + Duration::from_secs(1_553_116_298))
+      .os(Os::Unix)
+      .text(true)
+      .filename("café.txt")
+      .comment_bytes(b"\xff\x01")
+      .extra_field(ExtraField {
+        id: *b"AP",
+        data: vec![0, 1, 2],
+      })
+      .compression_info(Some(CompressionInfo::FastestAlgorithm))
+      .header_crc(true)
+      .build();
+    let bytes = member(&headers);
+
+    let gzip = Gzip::new(bytes.clone()).unwrap();
+    let parsed = &gzip.members[0].headers;
+    assert_eq!(parsed.mtime, 1_553_116_298);
+    assert_eq!(parsed.os, Os::Unix);
+    assert!(parsed.is_text);
+    assert_eq!(parsed.filename_latin1(), Some(String::from("café.txt")));
+    assert_eq!(parsed.comment, Some(vec![0xff, 0x01]));
+    assert_eq!(parsed.extra_fields, headers.extra_fields);
+    assert_eq!(
+      parsed.compression_info,
+      Some(CompressionInfo::FastestAlgorithm)
+    );
+    assert_eq!(parsed.crc16, headers.crc16);
+    assert!(parsed.crc16_is_valid());
+    assert!(headers.crc16_is_valid());
+
+    // Writing the parsed headers gives back the same bytes
+    assert_eq!(member(parsed), bytes);
+  }
+
+  #[test]
+  fn test_rewrites_fixture_headers() {
+    let bytes = include_bytes!("../../tests/gzip/compressed/raven").to_vec();
+    let gzip = Gzip::new(bytes.clone()).unwrap();
+    let mut header = vec![];
+    gzip.members[0].headers.write_to(&mut header).unwrap();
+    assert_eq!(header, bytes[..header.len()]);
+  }
+
+  #[test]
+  fn test_unnamed_xfl() {
+    // XFL 6 has no meaning, but should survive a rewrite
+    let mut bytes = member(&HeadersBuilder::new().build());
+    bytes[8] = 6;
+    let gzip = Gzip::new(bytes.clone()).unwrap();
+    let headers = &gzip.members[0].headers;
+    assert_eq!(headers.compression_info, None);
+    assert_eq!(member(headers), bytes);
+
+    // Unless the compression info is set to something else
+    let mut headers = Gzip::new(bytes).unwrap().members.remove(0).headers;
+    headers.compression_info = Some(CompressionInfo::FastestAlgorithm);
+    assert_eq!(member(&headers)[8], 4);
+  }
+
+  #[test]
+  fn test_defaults() {
+    let mut bytes = vec![];
+    HeadersBuilder::new().build().write_to(&mut bytes).unwrap();
+    assert_eq!(bytes, vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255]);
+
+    let headers = HeadersBuilder::new()
+      .mtime_from(UNIX_EPOCH - Duration::from_secs(1))
+      .build();
+    assert_eq!(headers.mtime, 0);
+  }
+
+  #[test]
+  fn test_invalid() {
+    let headers = HeadersBuilder::new().filename_bytes(b"a\0b").build();
+    let error = headers.write_to(&mut vec![]).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+    let headers = HeadersBuilder::new()
+      .extra_field(ExtraField {
+        id: *b"xx",
+        data: vec![0; 70_000],
+      })
+      .build();
+    assert!(headers.write_to(&mut vec![]).is_err());
+  }
+}
@@ -0,0 +1,353 @@
+use super::{check_trailer, GzipError, Headers, TRAILER_LEN};
+use crate::deflate::Inflate;
+use std::io::{self, Read};
+use std::mem;
+
+// Decompresses a gzip stream as it is read, holding on to no more than the
+// deflate window and whatever the inner reader has buffered. Each member's
+// CRC32 and ISIZE trailer is checked once its data has been read out, and a
+// mismatch is returned as an `InvalidData` error from that `read`. As with
+// `Gzip`, members follow one another until the input ends, and anything else
+// after a member is a `TrailingData` error.
+pub struct GzDecoder<R: Read> {
+  state: State<R>,
+  headers: Option<Headers>,
+  start: usize, // the offset of the current member's deflate stream
+}
+
+enum State<R: Read> {
+  // Between deflate streams, at the start of the member that begins at the
+  // given offset
+  Header(Box<Inflate<ReadBytes<R>>>, usize),
+  Body(Box<Inflate<ReadBytes<R>>>),
+  Done,
+}
+
+impl<R: Read> GzDecoder<R> {
+  pub fn new(inner: R) -> GzDecoder<R> {
+    GzDecoder {
+      state: State::Header(Box::new(Inflate::new(ReadBytes::new(inner))), 0),
+      headers: None,
+      start: 0,
+    }
+  }
+
+  // The headers of the member being read. They are parsed on the first call
+  // to `read`, so this is `None` until then.
+  pub fn headers(&self) -> Option<&Headers> {
+    self.headers.as_ref()
+  }
+
+  fn read_headers(&mut self) -> io::Result<()> {
+    let (mut inflate, member) = match mem::replace(&mut self.state, State::Done) {
+      State::Header(inflate, member) => (inflate, member),
+      state => {
+        self.state = state;
+        return Ok(());
+      }
+    };
+    let before = inflate.bit_position();
+    let headers = {
+      let mut bytes = inflate.remaining_bytes().peekable();
+      // There is always a first member, but the input may end after any
+      // other
+      if member > 0 && bytes.peek().is_none() {
+        None
+      } else {
+        Some(Headers::new(&mut bytes))
+      }
+    };
+    if let Some(error) = inflate.get_mut().error.take() {
+      return Err(error);
+    }
+    let headers = match headers {
+      None => return Ok(()),
+      // Anything that does not look like another member ends the file
+      Some(Err(GzipError::BadMagic { .. })) if member > 0 => {
+        return Err(GzipError::TrailingData { offset: member }.into());
+      }
+      Some(headers) => headers.map_err(|error| error.shifted(member))?,
+    };
+    let header_len = (inflate.bit_position() - before) / 8;
+    // Unlike `Gzip`, the decoder has no options, and is always strict
+    headers
+      .check_reserved_flags()
+      .and_then(|_| headers.check_crc16(header_len - 2))
+      .map_err(|error| error.shifted(member))?;
+    self.headers = Some(headers);
+    self.start = member + header_len;
+    inflate.restart();
+    self.state = State::Body(inflate);
+    Ok(())
+  }
+}
+
+// Read the trailer after a member's deflate stream, which started at byte
+// `header_len`, and check it against the data decoded
+pub(super) fn read_trailer<R: Read>(
+  header_len: usize,
+  inflate: &mut Inflate<ReadBytes<R>>,
+) -> io::Result<()> {
+  let offset = header_len + inflate.bit_position().div_ceil(8);
+  let trailer: Vec<u8> = inflate.remaining_bytes().take(TRAILER_LEN).collect();
+  if trailer.len() < TRAILER_LEN {
+    // Running short may be the inner reader's fault
+    if let Some(error) = inflate.get_mut().error.take() {
+      return Err(error);
+    }
+  }
+  Ok(check_trailer(
+    &trailer,
+    offset,
+    inflate.crc32(),
+    inflate.total_out(),
+  )?)
+}
+
+impl<R: Read> Read for GzDecoder<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+      self.read_headers()?;
+      let inflate = match &mut self.state {
+        State::Body(inflate) => inflate,
+        _ => return Ok(0),
+      };
+
+      let len = match inflate.read(buf) {
+        Ok(len) => len,
+        Err(error) => {
+          if let Some(error) = inflate.get_mut().error.take() {
+            return Err(error);
+          }
+          return Err(GzipError::inflate(self.start, error).into());
+        }
+      };
+      if len > 0 || buf.is_empty() {
+        return Ok(len);
+      }
+      // This member is done; look for another after its trailer
+      read_trailer(self.start, inflate)?;
+      let next = self.start + inflate.bit_position() / 8;
+      if let State::Body(inflate) = mem::replace(&mut self.state, State::Done) {
+        self.state = State::Header(inflate, next);
+      }
+    }
+  }
+}
+
+// Adapts a reader into the byte iterator that `Headers` and `Inflate` read
+// from. An iterator cannot fail, so an I/O error ends the iteration and is
+// kept here to be reported instead of the "unexpected end" it leads to.
+pub(crate) struct ReadBytes<R: Read> {
+  inner: R,
+  buf: Vec<u8>,
+  pos: usize,
+  len: usize,
+  pub(super) position: usize, // the number of bytes returned so far
+  pub(super) error: Option<io::Error>,
+}
+
+impl<R: Read> ReadBytes<R> {
+  pub(super) fn new(inner: R) -> ReadBytes<R> {
+    ReadBytes {
+      inner,
+      buf: vec![0; 32 * 1024],
+      pos: 0,
+      len: 0,
+      position: 0,
+      error: None,
+    }
+  }
+
+  fn fill_buf(&mut self) -> bool {
+    loop {
+      match self.inner.read(&mut self.buf) {
+        Ok(len) => {
+          self.pos = 0;
+          self.len = len;
+          return len > 0;
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+        Err(e) => {
+          self.error = Some(e);
+          return false;
+        }
+      }
+    }
+  }
+}
+
+impl<R: Read> Iterator for ReadBytes<R> {
+  type Item = u8;
+  fn next(&mut self) -> Option<u8> {
+    if self.error.is_some() || (self.pos == self.len && !self.fill_buf()) {
+      return None;
+    }
+    let byte = self.buf[self.pos];
+    self.pos += 1;
+    self.position += 1;
+    Some(byte)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::gzip::test::multi_member;
+  use std::fs;
+
+  // Hands out data a few bytes at a time, like a slow socket
+  struct Trickle<'a> {
+    data: &'a [u8],
+    chunk: usize,
+  }
+
+  impl<'a> Read for Trickle<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+      let len = self.chunk.min(buf.len()).min(self.data.len());
+      buf[..len].copy_from_slice(&self.data[..len]);
+      self.data = &self.data[len..];
+      Ok(len)
+    }
+  }
+
+  #[test]
+  fn test_decodes_fixtures() {
+    for entry in fs::read_dir("tests/gzip/src/").expect("failed to read src dir") {
+      let path = entry.expect("failed entry").path();
+      let src = fs::read(&path).expect("Failed to read file");
+      let compressed_path = path.to_str().unwrap().replace("/src/", "/compressed/");
+      let compressed = fs::read(compressed_path).expect("Failed to read file");
+
+      let mut decoder = GzDecoder::new(Trickle {
+        data: &compressed,
+        chunk: 7,
+      });
+      let mut data = vec![];
+      let mut buf = [0; 100];
+      loop {
+        let len = decoder.read(&mut buf).expect("Failed to decode");
+        if len == 0 {
+          break;
+        }
+        data.extend_from_slice(&buf[..len]);
+      }
+      assert_eq!(data, src);
+    }
+  }
+
+  #[test]
+  fn test_headers() {
+    let bytes = include_bytes!("../../tests/gzip/dynamic_encoding/gunzip.c.gz");
+    let mut decoder = GzDecoder::new(&bytes[..]);
+    assert!(decoder.headers().is_none());
+    decoder.read_exact(&mut [0; 10]).unwrap();
+    let headers = decoder.headers().unwrap();
+    assert_eq!(headers.filename, Some(b"gunzip.c".to_vec()));
+  }
+
+  #[test]
+  fn test_stored_blocks() {
+    let bytes = include_bytes!("../../tests/gzip/no_compression/png.png.gz");
+    let src = include_bytes!("../../tests/gzip/no_compression/png.png");
+    let mut data = vec![];
+    GzDecoder::new(&bytes[..]).read_to_end(&mut data).unwrap();
+    assert_eq!(&data[..], &src[..]);
+  }
+
+  #[test]
+  fn test_checksum_mismatch() {
+    let mut bytes = include_bytes!("../../tests/gzip/dynamic_encoding/gunzip.c.gz").to_vec();
+    let crc_offset = bytes.len() - 8;
+    bytes[crc_offset] ^= 1;
+    let error = GzDecoder::new(&bytes[..])
+      .read_to_end(&mut vec![])
+      .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    match error.into_inner().unwrap().downcast::<GzipError>() {
+      Ok(error) => match *error {
+        GzipError::ChecksumMismatch { offset, .. } => assert_eq!(offset, crc_offset),
+        e => panic!("Unexpected error {:?}", e),
+      },
+      Err(e) => panic!("Unexpected error {:?}", e),
+    }
+  }
+
+  #[test]
+  fn test_header_checksum_mismatch() {
+    // FHCRC set, over an empty deflate stream and its trailer
+    let mut bytes = vec![0x1f, 0x8b, 8, 0b10, 0, 0, 0, 0, 0, 3];
+    let crc16 = crate::crc32::crc32(&bytes) as u16;
+    bytes.extend_from_slice(&(crc16 ^ 1).to_le_bytes());
+    bytes.extend_from_slice(&[0x03, 0x00, 0, 0, 0, 0, 0, 0, 0, 0]);
+    let error = GzDecoder::new(&bytes[..])
+      .read_to_end(&mut vec![])
+      .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    match error.into_inner().unwrap().downcast::<GzipError>() {
+      Ok(error) => assert_eq!(
+        *error,
+        GzipError::HeaderChecksumMismatch {
+          offset: 10,
+          expected: crc16 ^ 1,
+          found: crc16
+        }
+      ),
+      Err(e) => panic!("Unexpected error {:?}", e),
+    }
+  }
+
+  #[test]
+  fn test_multi_member() {
+    let (gzip, expected) = multi_member();
+    let mut data = vec![];
+    GzDecoder::new(Trickle {
+      data: &gzip,
+      chunk: 1000,
+    })
+    .read_to_end(&mut data)
+    .unwrap();
+    assert_eq!(data, expected);
+  }
+
+  #[test]
+  fn test_trailing_data() {
+    let (mut gzip, _) = multi_member();
+    let offset = gzip.len();
+    gzip.extend_from_slice(b"GARBAGE");
+    let error = GzDecoder::new(&gzip[..])
+      .read_to_end(&mut vec![])
+      .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    match error.into_inner().unwrap().downcast::<GzipError>() {
+      Ok(error) => assert_eq!(*error, GzipError::TrailingData { offset }),
+      Err(e) => panic!("Unexpected error {:?}", e),
+    }
+  }
+
+  #[test]
+  fn test_truncated() {
+    let bytes = include_bytes!("../../tests/gzip/dynamic_encoding/gunzip.c.gz");
+    for &len in &[5, 2000, bytes.len() - 3] {
+      let error = GzDecoder::new(&bytes[..len])
+        .read_to_end(&mut vec![])
+        .unwrap_err();
+      assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+  }
+
+  #[test]
+  fn test_read_error() {
+    struct Failing;
+    impl Read for Failing {
+      fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::new(
+          io::ErrorKind::PermissionDenied,
+          "disk on fire",
+        ))
+      }
+    }
+    let error = GzDecoder::new(Failing).read(&mut [0; 10]).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+  }
+}
@@ -0,0 +1,221 @@
+use super::{CompressionInfo, HeadersBuilder};
+use crate::crc32;
+use crate::deflate::{CompressionLevel, Encoder};
+use std::io::{self, Write};
+
+// Compresses everything written to it into a gzip file on `inner`. The
+// header goes out with the first write (or with `finish`, for empty input).
+// `finish` must be called once all the data has been written, to write the
+// final block and the trailer.
+pub struct GzEncoder<W: Write> {
+  inner: W,
+  encoder: Encoder,
+  level: CompressionLevel,
+  header: Option<HeadersBuilder>, // until it is written
+  crc32: u32,
+  size: u32, // mod 2^32, as ISIZE is
+}
+
+impl<W: Write> GzEncoder<W> {
+  pub fn new(inner: W) -> GzEncoder<W> {
+    GzEncoder::with_level(inner, CompressionLevel::default())
+  }
+
+  pub fn with_level(inner: W, level: CompressionLevel) -> GzEncoder<W> {
+    GzEncoder::with_headers(inner, level, HeadersBuilder::new())
+  }
+
+  // The header fields are fixed here, since the header goes out with the
+  // first write. XFL is always set from the compression level.
+  pub fn with_headers(inner: W, level: CompressionLevel, headers: HeadersBuilder) -> GzEncoder<W> {
+    GzEncoder {
+      inner,
+      encoder: Encoder::with_level(level),
+      level,
+      header: Some(headers),
+      crc32: crc32::initial_value(),
+      size: 0,
+    }
+  }
+
+  pub fn get_ref(&self) -> &W {
+    &self.inner
+  }
+
+  // Write the last of the data and the trailer, returning the inner writer
+  pub fn finish(mut self) -> io::Result<W> {
+    self.write_header()?;
+    self.encoder.finish();
+    self.inner.write_all(&self.encoder.take_output())?;
+    let crc32 = crc32::finalize(self.crc32);
+    self.inner.write_all(&crc32.to_le_bytes())?;
+    self.inner.write_all(&self.size.to_le_bytes())?;
+    self.inner.flush()?;
+    Ok(self.inner)
+  }
+
+  fn write_header(&mut self) -> io::Result<()> {
+    let header = match self.header.take() {
+      Some(header) => header,
+      None => return Ok(()),
+    };
+
+    header
+      .compression_info(compression_info(self.level))
+      .build()
+      .write_to(&mut self.inner)
+  }
+}
+
+// The XFL for a compression level. zlib only records how hard it tried for
+// its slowest and fastest settings.
+pub(super) fn compression_info(level: CompressionLevel) -> Option<CompressionInfo> {
+  match level.level() {
+    9 => Some(CompressionInfo::MaximumCompressionSlowestAlgorithm),
+    0 | 1 => Some(CompressionInfo::FastestAlgorithm),
+    _ => None,
+  }
+}
+
+impl<W: Write> Write for GzEncoder<W> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.write_header()?;
+    self.crc32 = crc32::update_slice(self.crc32, buf);
+    self.size = self.size.wrapping_add(buf.len() as u32);
+    self.encoder.write(buf);
+    self.inner.write_all(&self.encoder.take_output())?;
+    Ok(buf.len())
+  }
+
+  // Passes on what has been compressed so far. Input that has not yet made
+  // up a whole block stays buffered.
+  fn flush(&mut self) -> io::Result<()> {
+    self.write_header()?;
+    self.inner.write_all(&self.encoder.take_output())?;
+    self.inner.flush()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::gzip::{CompressionInfo, ExtraField, GzDecoder, Gzip, Os};
+  use std::fs;
+  use std::io::Read;
+
+  fn compress(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(vec![]);
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+  }
+
+  #[test]
+  fn test_round_trip_fixtures() {
+    for entry in fs::read_dir("tests/gzip/src/").expect("failed to read src dir") {
+      let src = fs::read(entry.expect("failed entry").path()).expect("Failed to read file");
+      let gzip = Gzip::new(compress(&src)).expect("Failed to parse gzip");
+      assert_eq!(gzip.data, src);
+      assert!(gzip.crc_is_valid());
+      assert!(gzip.size_is_valid());
+
+      let mut data = vec![];
+      GzDecoder::new(&compress(&src)[..])
+        .read_to_end(&mut data)
+        .unwrap();
+      assert_eq!(data, src);
+    }
+  }
+
+  #[test]
+  fn test_empty() {
+    let gzip = Gzip::new(compress(&[])).unwrap();
+    assert_eq!(gzip.data, vec![]);
+    assert!(gzip.crc_is_valid());
+  }
+
+  #[test]
+  fn test_headers() {
+    let headers = HeadersBuilder::new()
+      .mtime(1_553_116_298)
+      .os(Os::Unix)
+      .filename("café.txt")
+      .comment("日本")
+      .extra_field(ExtraField {
+        id: *b"AP",
+        data: vec![0, 0xff, b'x'],
+      });
+    let mut encoder = GzEncoder::with_headers(vec![], CompressionLevel::default(), headers);
+    encoder.write_all(b"hello").unwrap();
+    let gzip = Gzip::new(encoder.finish().unwrap()).unwrap();
+
+    let headers = &gzip.members[0].headers;
+    assert_eq!(headers.mtime, 1_553_116_298);
+    assert_eq!(headers.os, Os::Unix);
+    assert_eq!(headers.filename, Some(b"caf\xe9.txt".to_vec()));
+    assert_eq!(headers.filename_latin1(), Some(String::from("café.txt")));
+    assert_eq!(headers.comment_latin1(), Some(String::from("??")));
+    assert_eq!(
+      headers.extra_fields,
+      vec![ExtraField {
+        id: *b"AP",
+        data: vec![0, 0xff, b'x'],
+      }]
+    );
+    assert_eq!(gzip.data, b"hello");
+  }
+
+  #[test]
+  fn test_level_in_extra_flags() {
+    let src = include_bytes!("../../tests/gzip/src/raven");
+    for (level, info) in [
+      (0, Some(CompressionInfo::FastestAlgorithm)),
+      (1, Some(CompressionInfo::FastestAlgorithm)),
+      (6, None),
+      (9, Some(CompressionInfo::MaximumCompressionSlowestAlgorithm)),
+    ] {
+      let mut encoder = GzEncoder::with_level(vec![], CompressionLevel::new(level).unwrap());
+      encoder.write_all(src).unwrap();
+      let gzip = Gzip::new(encoder.finish().unwrap()).unwrap();
+      assert_eq!(gzip.members[0].headers.compression_info, info);
+      assert_eq!(&gzip.data[..], &src[..]);
+    }
+  }
+
+  #[test]
+  fn test_headers_builder() {
+    let headers = HeadersBuilder::new()
+      .text(true)
+      .filename("notes.txt")
+      .mtime(42)
+      .header_crc(true)
+      .compression_info(Some(CompressionInfo::FastestAlgorithm));
+    let mut encoder = GzEncoder::with_headers(vec![], CompressionLevel::best(), headers);
+    encoder.write_all(b"hello").unwrap();
+    let gzip = Gzip::new(encoder.finish().unwrap()).unwrap();
+
+    let headers = &gzip.members[0].headers;
+    // XFL comes from the level, whatever the builder had
+    assert_eq!(
+      headers.compression_info,
+      Some(CompressionInfo::MaximumCompressionSlowestAlgorithm)
+    );
+    assert!(headers.is_text);
+    assert_eq!(headers.mtime, 42);
+    assert_eq!(headers.filename, Some(b"notes.txt".to_vec()));
+    assert!(headers.crc16.is_some());
+    assert!(headers.crc16_is_valid());
+    assert_eq!(gzip.data, b"hello");
+  }
+
+  #[test]
+  fn test_small_writes() {
+    let src = include_bytes!("../../tests/gzip/src/raven");
+    let mut encoder = GzEncoder::new(vec![]);
+    for chunk in src.chunks(7) {
+      encoder.write_all(chunk).unwrap();
+      encoder.flush().unwrap();
+    }
+    let compressed = encoder.finish().unwrap();
+    assert_eq!(compressed, compress(src));
+  }
+}
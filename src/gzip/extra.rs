@@ -0,0 +1,164 @@
+// FEXTRA subfields. Each has a two-byte id (SI1, SI2) and up to 65535 bytes
+// of data, which is usually binary.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtraField {
+  pub id: [u8; 2],
+  pub data: Vec<u8>,
+}
+
+// The contents of the subfields we know the layout of
+#[derive(Debug, PartialEq)]
+pub enum Subfield {
+  // BGZF ("BC"): the size of the whole member, less one
+  Bgzf {
+    block_size: u16,
+  },
+  // dictzip ("RA"): the compressed size of each fixed-size chunk
+  RandomAccess {
+    version: u16,
+    chunk_len: u16,
+    chunk_sizes: Vec<u16>,
+  },
+  // Macintosh ("Mc"): the file's four-character type and creator codes
+  Macintosh {
+    file_type: [u8; 4],
+    creator: [u8; 4],
+  },
+}
+
+// The ids registered with the maintainer of gzip, as listed in RFC 1952,
+// and the ones in wide use since
+const KNOWN_IDS: [([u8; 2], &str); 9] = [
+  (*b"AC", "Acorn RISC OS/BBC MOS file type information"),
+  (*b"Ap", "Apollo file type information"),
+  (*b"BC", "BGZF block size"),
+  (*b"cp", "file compressed by cpio"),
+  (*b"GS", "gzsig signature"),
+  (*b"KN", "KeyNote assertion"),
+  (*b"Mc", "Macintosh type and creator"),
+  (*b"RA", "dictzip random access table"),
+  (*b"RO", "Acorn RISC OS file type information"),
+];
+
+impl ExtraField {
+  pub fn description(&self) -> Option<&'static str> {
+    KNOWN_IDS
+      .iter()
+      .find(|(id, _)| *id == self.id)
+      .map(|&(_, description)| description)
+  }
+
+  // `None` for an unknown id, or data that does not fit its layout
+  pub fn decode(&self) -> Option<Subfield> {
+    let data = &self.data[..];
+    match &self.id {
+      b"BC" if data.len() == 2 => Some(Subfield::Bgzf {
+        block_size: u16_at(data, 0),
+      }),
+      b"RA" if data.len() >= 6 => {
+        let count = u16_at(data, 4) as usize;
+        if data.len() != 6 + 2 * count {
+          return None;
+        }
+        Some(Subfield::RandomAccess {
+          version: u16_at(data, 0),
+          chunk_len: u16_at(data, 2),
+          chunk_sizes: (0..count).map(|i| u16_at(data, 6 + 2 * i)).collect(),
+        })
+      }
+      b"Mc" if data.len() == 8 => {
+        let mut file_type = [0; 4];
+        let mut creator = [0; 4];
+        file_type.copy_from_slice(&data[..4]);
+        creator.copy_from_slice(&data[4..]);
+        Some(Subfield::Macintosh { file_type, creator })
+      }
+      _ => None,
+    }
+  }
+}
+
+// Split the FEXTRA bytes of a header (after XLEN) into subfields, or `None`
+// if they do not divide up exactly
+pub(super) fn split_fields(mut extra: &[u8]) -> Option<Vec<ExtraField>> {
+  let mut fields = vec![];
+  while !extra.is_empty() {
+    let len = usize::from(u16_at(extra.get(..4)?, 2));
+    let data = extra.get(4..4 + len)?;
+    fields.push(ExtraField {
+      id: [extra[0], extra[1]],
+      data: data.to_vec(),
+    });
+    extra = &extra[4 + len..];
+  }
+  Some(fields)
+}
+
+// The BSIZE in the BC subfield, if there is one
+pub(super) fn bgzf_block_size(fields: &[ExtraField]) -> Option<u16> {
+  fields.iter().find_map(|field| match field.decode() {
+    Some(Subfield::Bgzf { block_size }) => Some(block_size),
+    _ => None,
+  })
+}
+
+fn u16_at(data: &[u8], at: usize) -> u16 {
+  u16::from_le_bytes([data[at], data[at + 1]])
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn field(id: &[u8; 2], data: &[u8]) -> ExtraField {
+    ExtraField {
+      id: *id,
+      data: data.to_vec(),
+    }
+  }
+
+  #[test]
+  fn test_decode() {
+    assert_eq!(
+      field(b"BC", &[0x1b, 0x00]).decode(),
+      Some(Subfield::Bgzf { block_size: 27 })
+    );
+    assert_eq!(
+      field(b"RA", &[1, 0, 0xff, 0xff, 2, 0, 0x10, 0x20, 0x30, 0x40]).decode(),
+      Some(Subfield::RandomAccess {
+        version: 1,
+        chunk_len: 0xffff,
+        chunk_sizes: vec![0x2010, 0x4030],
+      })
+    );
+    assert_eq!(
+      field(b"Mc", b"TEXTttxt").decode(),
+      Some(Subfield::Macintosh {
+        file_type: *b"TEXT",
+        creator: *b"ttxt",
+      })
+    );
+    // Known ids with the wrong layout, and unknown ids
+    assert_eq!(field(b"BC", &[1, 2, 3]).decode(), None);
+    assert_eq!(field(b"RA", &[1, 0, 0, 1, 2, 0, 0]).decode(), None);
+    assert_eq!(field(b"zz", &[]).decode(), None);
+  }
+
+  #[test]
+  fn test_split_fields() {
+    let extra = [b'z', b'z', 1, 0, 9, b'B', b'C', 2, 0, 0x1b, 0x00];
+    let fields = split_fields(&extra).unwrap();
+    assert_eq!(fields, vec![field(b"zz", &[9]), field(b"BC", &[0x1b, 0])]);
+    assert_eq!(bgzf_block_size(&fields), Some(27));
+    assert_eq!(bgzf_block_size(&fields[..1]), None);
+    // A subfield that runs past the end, and one cut off in its header
+    assert_eq!(split_fields(&extra[..10]), None);
+    assert_eq!(split_fields(&extra[..7]), None);
+  }
+
+  #[test]
+  fn test_description() {
+    assert_eq!(field(b"BC", &[]).description(), Some("BGZF block size"));
+    assert_eq!(field(b"zz", &[]).description(), None);
+  }
+}
@@ -0,0 +1,427 @@
+use super::decoder::{read_trailer, ReadBytes};
+use super::{GzipError, Headers};
+use crate::deflate::{encode, inflate_raw_limited, Inflate, InflateError, WINDOW_SIZE};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+// A random-access index into a gzip file, like the one zlib's
+// examples/zran.c builds. Each checkpoint holds what `Inflate::resume`
+// needs to start decoding part-way through: where the next block starts in
+// the file, how much data comes before it, and the window of data just
+// before it. There is one at the start of every member, and then one at the
+// first block boundary after every `span` bytes of data.
+#[derive(Debug, PartialEq)]
+pub struct Index {
+  span: u64,
+  checkpoints: Vec<Checkpoint>,
+  len: u64, // of all of the decompressed data
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Checkpoint {
+  pub input_bit: u64, // the offset in the file, in bits, of the next block
+  pub output: u64,    // the offset in the decompressed data
+  pub window: Vec<u8>,
+}
+
+// The start of a sidecar index file, and the version of its layout
+const INDEX_MAGIC: [u8; 4] = *b"GZIX";
+const INDEX_VERSION: u8 = 1;
+
+// The most a compressed window can take up: `encode` stores a window that
+// does not compress in one block, with a few bytes of framing
+const MAX_ENCODED_WINDOW: u32 = WINDOW_SIZE as u32 + 1024;
+
+impl Index {
+  // Decompress the whole file, checking each member's trailer, and note a
+  // checkpoint every `span` bytes of data or so
+  pub fn build<R: Read + Seek>(reader: &mut R, span: u64) -> io::Result<Index> {
+    if span == 0 {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "checkpoints must be a positive span apart",
+      ));
+    }
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    let mut checkpoints = vec![];
+    let mut output = 0;
+    let mut offset = 0; // of the next member
+    while offset < file_len || checkpoints.is_empty() {
+      reader.seek(SeekFrom::Start(offset))?;
+      let mut bytes = ReadBytes::new(&mut *reader);
+      let headers = Headers::new(&mut bytes);
+      if let Some(error) = bytes.error.take() {
+        return Err(error);
+      }
+      let member = offset as usize;
+      let headers = match headers {
+        // Anything that does not look like another member ends the file
+        Err(GzipError::BadMagic { .. }) if member > 0 => {
+          return Err(GzipError::TrailingData { offset: member }.into());
+        }
+        headers => headers.map_err(|error| error.shifted(member))?,
+      };
+      // As `GzDecoder` does, hold the headers to the letter
+      headers
+        .check_reserved_flags()
+        .and_then(|_| headers.check_crc16(bytes.position - 2))
+        .map_err(|error| error.shifted(member))?;
+
+      let start = member + bytes.position; // of the deflate stream
+      checkpoints.push(Checkpoint {
+        input_bit: start as u64 * 8,
+        output,
+        window: vec![],
+      });
+      let mut inflate = Inflate::new(bytes);
+      let mut last = output;
+      while !inflate.is_finished() {
+        decode_block(&mut inflate, start)?;
+        output += inflate.take_output().len() as u64;
+        if inflate.at_block_boundary() && output - last >= span {
+          checkpoints.push(Checkpoint {
+            input_bit: start as u64 * 8 + inflate.bit_position() as u64,
+            output,
+            window: inflate.window(),
+          });
+          last = output;
+        }
+      }
+      let end = start + inflate.bit_position().div_ceil(8);
+      read_trailer(start, &mut inflate)?;
+      offset = end as u64 + 8;
+    }
+    Ok(Index {
+      span,
+      checkpoints,
+      len: output,
+    })
+  }
+
+  pub fn span(&self) -> u64 {
+    self.span
+  }
+
+  pub fn checkpoints(&self) -> &[Checkpoint] {
+    &self.checkpoints
+  }
+
+  // The length of the data in the whole file
+  pub fn uncompressed_len(&self) -> u64 {
+    self.len
+  }
+
+  // Read up to `len` bytes of data, starting `offset` bytes in, decoding
+  // only from the last checkpoint before `offset`. Less comes back if the
+  // data ends first. Trailers are not checked, as only part of each member
+  // is decoded.
+  pub fn seek_read<R: Read + Seek>(
+    &self,
+    reader: &mut R,
+    offset: u64,
+    len: usize,
+  ) -> io::Result<Vec<u8>> {
+    if offset >= self.len || len == 0 {
+      return Ok(vec![]);
+    }
+    // Whatever is asked for, no more than the rest of the data comes back
+    let len = (self.len - offset).min(len as u64) as usize;
+    let mut data = Vec::with_capacity(len);
+    // The first checkpoint is at 0, so there is always one
+    let mut i = self.checkpoints.partition_point(|c| c.output <= offset) - 1;
+    let mut skip = offset - self.checkpoints[i].output;
+    loop {
+      let checkpoint = &self.checkpoints[i];
+      let start = checkpoint.input_bit / 8;
+      reader.seek(SeekFrom::Start(start))?;
+      let bytes = ReadBytes::new(&mut *reader);
+      let skip_bits = (checkpoint.input_bit % 8) as u8;
+      let mut inflate = Inflate::resume(bytes, skip_bits, &checkpoint.window);
+      while !inflate.is_finished() && data.len() < len {
+        decode_block(&mut inflate, start as usize)?;
+        let output = inflate.take_output();
+        let from = skip.min(output.len() as u64) as usize;
+        skip -= from as u64;
+        let to = output.len().min(from + (len - data.len()));
+        data.extend_from_slice(&output[from..to]);
+      }
+      if data.len() == len {
+        return Ok(data);
+      }
+
+      // Carry on into the next member, from its checkpoint
+      let end = start + inflate.bit_position().div_ceil(8) as u64;
+      match self.checkpoints[i + 1..]
+        .iter()
+        .position(|c| c.input_bit / 8 > end)
+      {
+        Some(next) => i += 1 + next,
+        None => return Ok(data),
+      }
+    }
+  }
+
+  // Save the index, for example to a sidecar file next to the gzip file.
+  // The windows are compressed, as they would otherwise take up 32K apiece.
+  pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+    writer.write_all(&INDEX_MAGIC)?;
+    writer.write_all(&[INDEX_VERSION])?;
+    writer.write_all(&self.span.to_le_bytes())?;
+    writer.write_all(&self.len.to_le_bytes())?;
+    writer.write_all(&(self.checkpoints.len() as u64).to_le_bytes())?;
+    for checkpoint in &self.checkpoints {
+      writer.write_all(&checkpoint.input_bit.to_le_bytes())?;
+      writer.write_all(&checkpoint.output.to_le_bytes())?;
+      let window = encode(&checkpoint.window);
+      writer.write_all(&(window.len() as u32).to_le_bytes())?;
+      writer.write_all(&window)?;
+    }
+    Ok(())
+  }
+
+  pub fn read_from(reader: &mut impl Read) -> io::Result<Index> {
+    let mut magic = [0; 5];
+    reader.read_exact(&mut magic)?;
+    if magic[..4] != INDEX_MAGIC || magic[4] != INDEX_VERSION {
+      return Err(invalid_index("not a gzip index"));
+    }
+    let span = read_u64(reader)?;
+    let len = read_u64(reader)?;
+    let count = read_u64(reader)?;
+    let mut checkpoints = vec![];
+    for _ in 0..count {
+      let input_bit = read_u64(reader)?;
+      let output = read_u64(reader)?;
+      let window_len = read_u32(reader)?;
+      if window_len > MAX_ENCODED_WINDOW {
+        return Err(invalid_index("window larger than 32K"));
+      }
+      let mut window = vec![0; window_len as usize];
+      reader.read_exact(&mut window)?;
+      // However few bytes it is stored in, a window holds no more than 32K
+      let window = inflate_raw_limited(&window, WINDOW_SIZE)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?
+        .ok_or_else(|| invalid_index("window larger than 32K"))?
+        .data;
+      checkpoints.push(Checkpoint {
+        input_bit,
+        output,
+        window,
+      });
+    }
+    // `seek_read` relies on there being a checkpoint at the start of the
+    // data, and on the checkpoints being in order
+    match checkpoints.first() {
+      None => return Err(invalid_index("no checkpoints")),
+      Some(first) if first.output != 0 || first.input_bit % 8 != 0 => {
+        return Err(invalid_index("no checkpoint at the start"));
+      }
+      _ => (),
+    }
+    if checkpoints
+      .windows(2)
+      .any(|pair| pair[0].output > pair[1].output)
+    {
+      return Err(invalid_index("checkpoints out of order"));
+    }
+    if checkpoints.iter().any(|checkpoint| checkpoint.output > len) {
+      return Err(invalid_index("checkpoint past the end of the data"));
+    }
+    Ok(Index {
+      span,
+      checkpoints,
+      len,
+    })
+  }
+}
+
+// Decode a block of a member whose deflate stream (or the part of it being
+// decoded) starts at byte `start`, reporting the I/O error behind any
+// unexpected end
+fn decode_block<R: Read>(inflate: &mut Inflate<ReadBytes<R>>, start: usize) -> io::Result<()> {
+  inflate
+    .decode_block()
+    .map_err(|error: InflateError| match inflate.get_mut().error.take() {
+      Some(error) => error,
+      None => GzipError::inflate(start, error).into(),
+    })
+}
+
+fn invalid_index(message: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+  let mut bytes = [0; 8];
+  reader.read_exact(&mut bytes)?;
+  Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+  let mut bytes = [0; 4];
+  reader.read_exact(&mut bytes)?;
+  Ok(u32::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::gzip::test::multi_member;
+  use std::io::Cursor;
+
+  #[test]
+  fn test_seek_read() {
+    let (gzip, data) = multi_member();
+    let mut reader = Cursor::new(&gzip);
+    let index = Index::build(&mut reader, 16 * 1024).unwrap();
+    assert_eq!(index.uncompressed_len(), data.len() as u64);
+    // More than the one at the start of each member
+    assert!(index.checkpoints().len() > 4);
+    assert!(index
+      .checkpoints()
+      .windows(2)
+      .all(|pair| pair[0].output <= pair[1].output));
+
+    let len = data.len();
+    for &(offset, read_len) in &[
+      (0, 10),
+      (0, len),
+      (1000, 50_000),
+      (70_000, 1),
+      (150_000, 30_000), // across members
+      (len - 5, 100),
+      (len, 10),
+      (10, usize::MAX), // more than there is
+      (len - 5, usize::MAX),
+    ] {
+      let read = index
+        .seek_read(&mut reader, offset as u64, read_len)
+        .unwrap();
+      let end = len.min(offset.saturating_add(read_len));
+      assert_eq!(&read[..], &data[offset..end], "at {}", offset);
+    }
+  }
+
+  #[test]
+  fn test_every_checkpoint() {
+    let (gzip, data) = multi_member();
+    let mut reader = Cursor::new(&gzip);
+    let index = Index::build(&mut reader, 1).unwrap();
+    for checkpoint in index.checkpoints() {
+      let offset = checkpoint.output;
+      let read = index.seek_read(&mut reader, offset, 300).unwrap();
+      let start = offset as usize;
+      assert_eq!(&read[..], &data[start..data.len().min(start + 300)]);
+    }
+  }
+
+  #[test]
+  fn test_write_and_read() {
+    let (gzip, data) = multi_member();
+    let mut reader = Cursor::new(&gzip);
+    let index = Index::build(&mut reader, 64 * 1024).unwrap();
+    let mut sidecar = vec![];
+    index.write_to(&mut sidecar).unwrap();
+    // Much smaller than the windows it holds
+    assert!(sidecar.len() < index.checkpoints().len() * WINDOW_SIZE / 2);
+
+    let read = Index::read_from(&mut &sidecar[..]).unwrap();
+    assert_eq!(read, index);
+    let from_read = read.seek_read(&mut reader, 200_000, 1000).unwrap();
+    assert_eq!(&from_read[..], &data[200_000..201_000]);
+
+    assert_eq!(
+      Index::read_from(&mut &b"GZIP"[..]).unwrap_err().kind(),
+      io::ErrorKind::UnexpectedEof
+    );
+    assert_eq!(
+      Index::read_from(&mut &gzip[..]).unwrap_err().kind(),
+      io::ErrorKind::InvalidData
+    );
+  }
+
+  #[test]
+  fn test_bad_sidecar() {
+    let checkpoint = |input_bit, output| Checkpoint {
+      input_bit,
+      output,
+      window: vec![],
+    };
+    let read_back = |checkpoints| {
+      let index = Index {
+        span: 1,
+        checkpoints,
+        len: 10,
+      };
+      let mut sidecar = vec![];
+      index.write_to(&mut sidecar).unwrap();
+      Index::read_from(&mut &sidecar[..]).map(|_| ())
+    };
+    read_back(vec![checkpoint(80, 0), checkpoint(96, 10)]).unwrap();
+    for checkpoints in [
+      vec![],
+      vec![checkpoint(80, 5)],
+      vec![checkpoint(81, 0)],
+      vec![checkpoint(80, 0), checkpoint(96, 6), checkpoint(112, 4)],
+      vec![checkpoint(80, 0), checkpoint(96, 11)],
+    ] {
+      let error = read_back(checkpoints).unwrap_err();
+      assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    // A window length too long for any window is not trusted
+    let mut sidecar = vec![];
+    Index {
+      span: 1,
+      checkpoints: vec![checkpoint(80, 0)],
+      len: 10,
+    }
+    .write_to(&mut sidecar)
+    .unwrap();
+    let at = 5 + 3 * 8 + 2 * 8;
+    sidecar[at..at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    let error = Index::read_from(&mut &sidecar[..]).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+    // Nor is a short one that decompresses to more than a window
+    let mut sidecar = vec![];
+    Index {
+      span: 1,
+      checkpoints: vec![Checkpoint {
+        input_bit: 80,
+        output: 0,
+        window: vec![0; 100 * WINDOW_SIZE],
+      }],
+      len: 10,
+    }
+    .write_to(&mut sidecar)
+    .unwrap();
+    assert!(sidecar.len() < WINDOW_SIZE);
+    let error = Index::read_from(&mut &sidecar[..]).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+  }
+
+  #[test]
+  fn test_build_errors() {
+    let (mut gzip, _) = multi_member();
+    let len = gzip.len();
+    gzip.push(0);
+    assert_eq!(
+      Index::build(&mut Cursor::new(&gzip), 1024)
+        .unwrap_err()
+        .to_string(),
+      GzipError::TrailingData { offset: len }.to_string()
+    );
+
+    // The trailers are checked as the index is built
+    gzip.truncate(len);
+    gzip[len - 8] ^= 1;
+    let error = Index::build(&mut Cursor::new(&gzip), 1024).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+    let error = Index::build(&mut Cursor::new(&gzip[..1000]), 1024).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+    let error = Index::build(&mut Cursor::new(&gzip), 0).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+  }
+}
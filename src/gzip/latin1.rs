@@ -0,0 +1,25 @@
+// RFC 1952 says FNAME and FCOMMENT are ISO-8859-1, whose 256 characters are
+// the first 256 code points of Unicode, so each byte maps straight to a char
+use std::convert::TryFrom;
+
+pub fn decode(bytes: &[u8]) -> String {
+  bytes.iter().map(|&byte| char::from(byte)).collect()
+}
+
+// Characters outside ISO-8859-1 become '?'
+pub fn encode(s: &str) -> Vec<u8> {
+  s.chars().map(|c| u8::try_from(c).unwrap_or(b'?')).collect()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_round_trip() {
+    let bytes: Vec<u8> = (0..=255).collect();
+    assert_eq!(encode(&decode(&bytes)), bytes);
+    assert_eq!(decode(b"caf\xe9"), "café");
+    assert_eq!(encode("café 日本"), b"caf\xe9 ??");
+  }
+}
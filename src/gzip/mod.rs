@@ -0,0 +1,1269 @@
+pub mod bgzf;
+mod builder;
+mod decoder;
+mod encoder;
+mod extra;
+mod index;
+pub mod latin1;
+mod par_encoder;
+mod parallel;
+mod rewrite;
+mod workers;
+pub use builder::HeadersBuilder;
+pub use decoder::GzDecoder;
+pub use encoder::GzEncoder;
+pub use extra::{ExtraField, Subfield};
+pub use index::{Checkpoint, Index};
+pub use par_encoder::ParEncoder;
+pub use parallel::par_decompress;
+pub use rewrite::{may_have_later_members, rewrite_all_headers, rewrite_headers, verify};
+
+use crate::crc32;
+use crate::deflate::{inflate_to_end, Block, DecodeItem, Inflate, InflateError, InflateErrorKind};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::ops::Range;
+
+// A parsed gzip file. RFC 1952 allows any number of members one after
+// another (as `cat a.gz b.gz` produces), each with its own header and
+// trailer; their data is concatenated, as gunzip does.
+#[derive(Debug)]
+pub struct Gzip {
+  pub members: Vec<Member>,
+  pub data: Vec<u8>,
+  pub trailing_data: Option<Range<usize>>, // bytes after the last member
+  pub warnings: Vec<GzipError>,            // problems let through by a `Policy::Warn`
+}
+
+#[derive(Debug)]
+pub struct Member {
+  pub offset: usize, // of the member's first byte in the file
+  pub len: usize,    // bytes of the file the member takes up, header to trailer
+  pub headers: Headers,
+  pub blocks: Vec<Block>,
+  pub decode_items: Vec<DecodeItem>, // only with `ParseOptions::trace`
+  pub data_range: Range<usize>, // the member's part of `Gzip::data`
+  pub crc32: u32,
+  pub size: u32,
+  calculated_crc32: u32,
+}
+
+// The CRC32 and ISIZE trailer
+const TRAILER_LEN: usize = 8;
+
+// How `Gzip::with_options` treats problems that gunzip would tolerate
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ParseOptions {
+  // Make a header CRC16, trailer CRC32 or ISIZE that does not match the data
+  // an error, rather than leaving it to `crc16_is_valid` and the like
+  pub strict: bool,
+  // FLG bits 5-7, which RFC 1952 says a decoder must reject
+  pub reserved_flags: Policy,
+  // Bytes after the last member that are not the start of another, which
+  // gunzip warns about (or, with --quiet, ignores)
+  pub trailing_data: Policy,
+  // Fill in `Member::decode_items`, as --json and --debug show them. This
+  // records every bit of every symbol, so it is much slower.
+  pub trace: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Policy {
+  #[default]
+  Error,
+  Warn, // carry on, and add the error to `Gzip::warnings`
+  Ignore,
+}
+
+impl std::str::FromStr for Policy {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Policy, String> {
+    match s {
+      "error" => Ok(Policy::Error),
+      "warn" => Ok(Policy::Warn),
+      "ignore" => Ok(Policy::Ignore),
+      _ => Err(format!("expected error, warn or ignore, not {}", s)),
+    }
+  }
+}
+
+const MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+impl Gzip {
+  pub fn new(bytes: Vec<u8>) -> Result<Gzip, GzipError> {
+    Gzip::with_options(bytes, ParseOptions::default())
+  }
+
+  pub fn with_options(bytes: Vec<u8>, options: ParseOptions) -> Result<Gzip, GzipError> {
+    let mut members = vec![];
+    let mut data = vec![];
+    let mut trailing_data = None;
+    let mut warnings = vec![];
+    let mut offset = 0;
+    while offset < bytes.len() || members.is_empty() {
+      // Anything that does not look like another member ends the file. The
+      // first member has no such leeway.
+      if !members.is_empty() && !bytes[offset..].starts_with(&MAGIC) {
+        let error = GzipError::TrailingData { offset };
+        match options.trailing_data {
+          Policy::Error => return Err(error),
+          Policy::Warn => warnings.push(error),
+          Policy::Ignore => (),
+        }
+        trailing_data = Some(offset..bytes.len());
+        break;
+      }
+      let (mut member, member_data) = Member::parse(&bytes, offset, options, &mut warnings)
+        .map_err(|error| error.shifted(offset))?;
+      offset += member.len;
+      let start = data.len();
+      data.extend_from_slice(&member_data);
+      member.data_range = start..data.len();
+      members.push(member);
+    }
+    Ok(Gzip {
+      members,
+      data,
+      trailing_data,
+      warnings,
+    })
+  }
+
+  pub fn size_is_valid(&self) -> bool {
+    self.members.iter().all(Member::size_is_valid)
+  }
+
+  pub fn crc_is_valid(&self) -> bool {
+    self.members.iter().all(Member::crc_is_valid)
+  }
+
+  pub fn crc16_is_valid(&self) -> bool {
+    self
+      .members
+      .iter()
+      .all(|member| member.headers.crc16_is_valid())
+  }
+
+  pub fn as_string(&self) -> String {
+    match String::from_utf8(self.data.clone()) {
+      Ok(s) => s,
+      _ => String::from("<binary data>"),
+    }
+  }
+}
+
+impl Member {
+  // Parse the member that starts at `bytes[start]`, returning it and its
+  // decompressed data. Error offsets are relative to `start`, but those of
+  // any warnings are from the start of the file.
+  fn parse(
+    bytes: &[u8],
+    start: usize,
+    options: ParseOptions,
+    warnings: &mut Vec<GzipError>,
+  ) -> Result<(Member, Vec<u8>), GzipError> {
+    let bytes = &bytes[start..];
+    let mut iter = bytes.iter().cloned();
+    let headers = Headers::new(&mut iter)?;
+    let header_len = bytes.len() - iter.len();
+    if let Err(error) = headers.check_reserved_flags() {
+      match options.reserved_flags {
+        Policy::Error => return Err(error),
+        Policy::Warn => warnings.push(error.shifted(start)),
+        Policy::Ignore => (),
+      }
+    }
+    if options.strict {
+      // The CRC16 is the last field of the header
+      headers.check_crc16(header_len - 2)?;
+    }
+
+    let mut inflate = Inflate::new(bytes[header_len..].iter().cloned());
+    if options.trace {
+      inflate.trace();
+    }
+    let inflate_result = inflate_to_end(inflate).map_err(|error| GzipError::inflate(header_len, error))?;
+
+    let trailer_start = header_len + inflate_result.compressed_len;
+    let trailer = &bytes[trailer_start..];
+    // Unless strict, a mismatch only shows in `crc_is_valid`/`size_is_valid`
+    match check_trailer(
+      trailer,
+      trailer_start,
+      inflate_result.crc32,
+      inflate_result.data.len() as u64,
+    ) {
+      Err(GzipError::ChecksumMismatch { .. }) | Err(GzipError::SizeMismatch { .. })
+        if !options.strict => {}
+      result => result?,
+    }
+    let mut trailer = trailer.iter().cloned();
+    // There is a full trailer, so these reads cannot run out
+    let crc32 = read_int(&mut trailer, 4).unwrap_or(0);
+    let size = read_int(&mut trailer, 4).unwrap_or(0);
+
+    let member = Member {
+      offset: start,
+      len: trailer_start + TRAILER_LEN,
+      headers,
+      blocks: inflate_result.blocks,
+      decode_items: inflate_result.decode_items,
+      data_range: 0..inflate_result.data.len(),
+      crc32,
+      size,
+      calculated_crc32: inflate_result.crc32,
+    };
+    Ok((member, inflate_result.data))
+  }
+
+  pub fn size_is_valid(&self) -> bool {
+    self.data_range.len() as u64 % 2_u64.pow(32) == u64::from(self.size)
+  }
+
+  pub fn crc_is_valid(&self) -> bool {
+    self.crc32 == self.calculated_crc32
+  }
+}
+
+/// The ways a gzip file can fail to parse. Every variant carries the byte
+/// offset (from the start of the file) at which the problem was found.
+#[derive(Debug, PartialEq)]
+pub enum GzipError {
+  /// The first two bytes were not 0x1f 0x8b
+  BadMagic { offset: usize },
+  /// The CM byte named a compression method other than deflate (8)
+  UnsupportedMethod { offset: usize, method: u8 },
+  /// The input ended before the header was complete
+  TruncatedHeader { offset: usize },
+  /// The input ended before the 8-byte CRC32/ISIZE trailer
+  TruncatedTrailer { offset: usize },
+  /// One of FLG bits 5-7, which RFC 1952 reserves, was set
+  ReservedFlags { offset: usize, flags: u8 },
+  /// The bytes after a member are not another member
+  TrailingData { offset: usize },
+  /// An FEXTRA subfield claimed more bytes than XLEN left for it
+  BadExtraField { offset: usize },
+  /// The FHCRC header CRC16 does not match the bytes before it
+  HeaderChecksumMismatch {
+    offset: usize,
+    expected: u16,
+    found: u16,
+  },
+  /// The compressed data is not a valid deflate stream
+  Inflate { offset: usize, error: InflateError },
+  /// The CRC32 in the trailer does not match the decompressed data
+  ChecksumMismatch {
+    offset: usize,
+    expected: u32,
+    found: u32,
+  },
+  /// ISIZE in the trailer does not match the decompressed length (mod 2^32)
+  SizeMismatch {
+    offset: usize,
+    expected: u32,
+    found: u32,
+  },
+}
+
+impl GzipError {
+  // Wraps an error from a deflate stream that started at byte `start`
+  fn inflate(start: usize, error: InflateError) -> GzipError {
+    GzipError::Inflate {
+      offset: start + error.bit_offset / 8,
+      error,
+    }
+  }
+
+  // Moves the offset on by `by` bytes, for errors found in a member that
+  // does not start at the beginning of the file
+  fn shifted(mut self, by: usize) -> GzipError {
+    use GzipError::*;
+    match &mut self {
+      BadMagic { offset }
+      | UnsupportedMethod { offset, .. }
+      | TruncatedHeader { offset }
+      | TruncatedTrailer { offset }
+      | ReservedFlags { offset, .. }
+      | TrailingData { offset }
+      | BadExtraField { offset }
+      | HeaderChecksumMismatch { offset, .. }
+      | Inflate { offset, .. }
+      | ChecksumMismatch { offset, .. }
+      | SizeMismatch { offset, .. } => *offset += by,
+    }
+    self
+  }
+
+  pub fn offset(&self) -> usize {
+    use GzipError::*;
+    match *self {
+      BadMagic { offset }
+      | UnsupportedMethod { offset, .. }
+      | TruncatedHeader { offset }
+      | TruncatedTrailer { offset }
+      | ReservedFlags { offset, .. }
+      | TrailingData { offset }
+      | BadExtraField { offset }
+      | HeaderChecksumMismatch { offset, .. }
+      | Inflate { offset, .. }
+      | ChecksumMismatch { offset, .. }
+      | SizeMismatch { offset, .. } => offset,
+    }
+  }
+}
+
+impl fmt::Display for GzipError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    use GzipError::*;
+    match self {
+      BadMagic { offset } => write!(f, "not a gzip file (bad magic bytes at {})", offset),
+      UnsupportedMethod { offset, method } => write!(
+        f,
+        "unsupported compression method {} at byte {}",
+        method, offset
+      ),
+      TruncatedHeader { offset } => write!(f, "header truncated at byte {}", offset),
+      TruncatedTrailer { offset } => write!(f, "trailer truncated at byte {}", offset),
+      ReservedFlags { offset, flags } => write!(
+        f,
+        "reserved flag bits set ({:#010b}) at byte {}",
+        flags, offset
+      ),
+      TrailingData { offset } => write!(f, "trailing garbage at byte {}", offset),
+      BadExtraField { offset } => write!(f, "malformed extra field at byte {}", offset),
+      HeaderChecksumMismatch {
+        offset,
+        expected,
+        found,
+      } => write!(
+        f,
+        "header CRC16 at byte {} is {:x}, but the header has CRC16 {:x}",
+        offset, expected, found
+      ),
+      Inflate { offset, error } => write!(f, "corrupt data at byte {}: {}", offset, error),
+      ChecksumMismatch {
+        offset,
+        expected,
+        found,
+      } => write!(
+        f,
+        "CRC32 at byte {} is {:x}, but the data has CRC32 {:x}",
+        offset, expected, found
+      ),
+      SizeMismatch {
+        offset,
+        expected,
+        found,
+      } => write!(
+        f,
+        "ISIZE at byte {} is {}, but {} bytes were decompressed",
+        offset, expected, found
+      ),
+    }
+  }
+}
+
+impl Error for GzipError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      GzipError::Inflate { error, .. } => Some(error),
+      _ => None,
+    }
+  }
+}
+
+impl From<GzipError> for io::Error {
+  fn from(error: GzipError) -> io::Error {
+    let kind = match &error {
+      GzipError::TruncatedHeader { .. } | GzipError::TruncatedTrailer { .. } => {
+        io::ErrorKind::UnexpectedEof
+      }
+      GzipError::Inflate { error, .. } if error.kind == InflateErrorKind::UnexpectedEnd => {
+        io::ErrorKind::UnexpectedEof
+      }
+      _ => io::ErrorKind::InvalidData,
+    };
+    io::Error::new(kind, error)
+  }
+}
+
+// Compression
+// Flags
+// MTIME
+// "Extra Info" Flag
+// OS
+
+// Optional Extra Info
+// Optional filename
+// Optional comment
+// Optional crc16
+#[derive(Debug)]
+pub struct Headers {
+  pub compression: Compression,
+  pub mtime: u32,
+  pub os: Os,
+  pub filename: Option<Vec<u8>>, // indicated by flags bit 3, ISO-8859-1
+  pub comment: Option<Vec<u8>>,  // indicated by flags bit 4, ISO-8859-1
+  pub crc16: Option<u32>,        // indicated by flags bit 1
+  pub compression_info: Option<CompressionInfo>, // via "extra flags"
+  pub is_text: bool,             // == Flags bit 0
+  pub extra_fields: Vec<ExtraField>,
+  flags: u8,             // FLG as read, including any reserved bits
+  extra_flags: u8,       // XFL as read, even a value `compression_info` has no name for
+  calculated_crc16: u16, // the low half of the CRC32 of the bytes before `crc16`
+}
+
+enum Flags {
+  Text = 0b1,
+  CRC16 = 0b10,
+  Extra = 0b100,
+  FileName = 0b1000,
+  Comment = 0b10000,
+}
+
+// FLG bits 5-7 must be zero, see https://tools.ietf.org/html/rfc1952#page-5
+const RESERVED_FLAGS: u8 = 0b1110_0000;
+
+// Wraps the byte iterator the header is read from, counting the bytes read
+// so far so that errors can report where they occurred, and keeping their
+// CRC32 for FHCRC
+struct HeaderBytes<'a, I: Iterator<Item = u8>> {
+  bytes: &'a mut I,
+  offset: usize,
+  crc32: u32,
+}
+
+impl<'a, I: Iterator<Item = u8>> HeaderBytes<'a, I> {
+  fn next_byte(&mut self) -> Result<u8, GzipError> {
+    self.next().ok_or(GzipError::TruncatedHeader {
+      offset: self.offset,
+    })
+  }
+
+  fn read_int(&mut self, size: usize) -> Result<u32, GzipError> {
+    read_int(self, size).ok_or(GzipError::TruncatedHeader {
+      offset: self.offset,
+    })
+  }
+}
+
+impl<'a, I: Iterator<Item = u8>> Iterator for HeaderBytes<'a, I> {
+  type Item = u8;
+  fn next(&mut self) -> Option<u8> {
+    let byte = self.bytes.next();
+    if let Some(byte) = byte {
+      self.offset += 1;
+      self.crc32 = crc32::update(self.crc32, byte);
+    }
+    byte
+  }
+}
+
+impl Headers {
+  fn new(bytes: &mut impl Iterator<Item = u8>) -> Result<Headers, GzipError> {
+    // TODO -- I cannot figure out how to use
+    // this in the match below. `MAGIC_BYTES[0]` does not seem to be syntactically valid
+    // const MAGIC_BYTES: [u8; 2] = [0x1f, 0x8b];
+    const MAGIC_BYTE_1: u8 = 0x1f;
+    const MAGIC_BYTE_2: u8 = 0x8b;
+
+    let mut bytes = HeaderBytes {
+      bytes,
+      offset: 0,
+      crc32: crc32::initial_value(),
+    };
+
+    match bytes.next_byte()? {
+      MAGIC_BYTE_1 => (),
+      _ => return Err(GzipError::BadMagic { offset: 0 }),
+    }
+    match bytes.next_byte()? {
+      MAGIC_BYTE_2 => (),
+      _ => return Err(GzipError::BadMagic { offset: 1 }),
+    }
+
+    let offset = bytes.offset;
+    let byte = bytes.next_byte()?;
+    let compression = Compression::parse(byte).ok_or(GzipError::UnsupportedMethod {
+      offset,
+      method: byte,
+    })?;
+
+    let flags = bytes.next_byte()?;
+
+    let mtime = bytes.read_int(4)?;
+
+    let extra_flags = bytes.next_byte()?;
+    let compression_info = CompressionInfo::parse(extra_flags);
+
+    let os = Os::parse(bytes.next_byte()?);
+
+    let extra_fields = if flags & Flags::Extra as u8 != 0 {
+      // parse extra fields
+      let mut len = bytes.read_int(2)?;
+      let mut result = vec![];
+
+      while len > 0 {
+        let offset = bytes.offset;
+        let (bytes_read, field) = read_extra_data_field(&mut bytes)?;
+        if bytes_read > len {
+          return Err(GzipError::BadExtraField { offset });
+        }
+        len -= bytes_read;
+        result.push(field);
+      }
+      result
+    } else {
+      vec![]
+    };
+
+    let filename = if flags & Flags::FileName as u8 != 0 {
+      Some(read_zero_terminated(&mut bytes)?)
+    } else {
+      None
+    };
+
+    let comment = if flags & Flags::Comment as u8 != 0 {
+      Some(read_zero_terminated(&mut bytes)?)
+    } else {
+      None
+    };
+
+    let is_text = flags & Flags::Text as u8 != 0;
+
+    let calculated_crc16 = crc32::finalize(bytes.crc32) as u16;
+    let crc16 = if flags & Flags::CRC16 as u8 != 0 {
+      Some(bytes.read_int(2)?)
+    } else {
+      None
+    };
+
+    Ok(Headers {
+      compression,
+      mtime,
+      os,
+      filename,
+      comment,
+      crc16,
+      compression_info,
+      is_text,
+      extra_fields,
+      flags,
+      extra_flags,
+      calculated_crc16,
+    })
+  }
+
+  pub fn filename_latin1(&self) -> Option<String> {
+    self.filename.as_deref().map(latin1::decode)
+  }
+
+  pub fn comment_latin1(&self) -> Option<String> {
+    self.comment.as_deref().map(latin1::decode)
+  }
+
+  // FLG bits 5-7, which should all be zero
+  pub fn reserved_flags(&self) -> u8 {
+    self.flags & RESERVED_FLAGS
+  }
+
+  fn check_reserved_flags(&self) -> Result<(), GzipError> {
+    if self.reserved_flags() != 0 {
+      // FLG is the fourth byte
+      return Err(GzipError::ReservedFlags {
+        offset: 3,
+        flags: self.flags,
+      });
+    }
+    Ok(())
+  }
+
+  // Whether the header CRC16 matches the header, if there is one
+  pub fn crc16_is_valid(&self) -> bool {
+    self.check_crc16(0).is_ok()
+  }
+
+  // `offset` is where the CRC16 was read from, for the error
+  fn check_crc16(&self, offset: usize) -> Result<(), GzipError> {
+    match self.crc16 {
+      Some(crc16) if crc16 as u16 != self.calculated_crc16 => {
+        Err(GzipError::HeaderChecksumMismatch {
+          offset,
+          expected: crc16 as u16,
+          found: self.calculated_crc16,
+        })
+      }
+      _ => Ok(()),
+    }
+  }
+}
+
+fn read_extra_data_field<I: Iterator<Item = u8>>(
+  bytes: &mut HeaderBytes<I>,
+) -> Result<(u32, ExtraField), GzipError> {
+  let id = [bytes.next_byte()?, bytes.next_byte()?];
+
+  let len = bytes.read_int(2)?;
+  let mut data = Vec::with_capacity(len as usize);
+  for _ in 0..len {
+    data.push(bytes.next_byte()?);
+  }
+
+  Ok((len + 4, ExtraField { id, data }))
+}
+
+// Check the trailer at `offset`, which should hold the CRC32 and length (mod
+// 2^32) of the member's data. `trailer` may run on past it.
+fn check_trailer(trailer: &[u8], offset: usize, crc32: u32, len: u64) -> Result<(), GzipError> {
+  if trailer.len() < TRAILER_LEN {
+    return Err(GzipError::TruncatedTrailer {
+      offset: offset + trailer.len(),
+    });
+  }
+  let mut trailer = trailer.iter().cloned();
+  let expected_crc32 = read_int(&mut trailer, 4).unwrap_or(0);
+  let size = read_int(&mut trailer, 4).unwrap_or(0);
+  if expected_crc32 != crc32 {
+    return Err(GzipError::ChecksumMismatch {
+      offset,
+      expected: expected_crc32,
+      found: crc32,
+    });
+  }
+  if size != len as u32 {
+    return Err(GzipError::SizeMismatch {
+      offset: offset + 4,
+      expected: size,
+      found: len as u32,
+    });
+  }
+  Ok(())
+}
+
+// Read little-endian int of `size` bytes, or `None` if the bytes run out first
+fn read_int<I: Iterator<Item = u8>>(bytes: &mut I, size: usize) -> Option<u32> {
+  let mut values = vec![];
+  while values.len() < size {
+    let byte = bytes.next()?;
+    values.push(byte);
+  }
+  Some(
+    values
+      .iter()
+      .map(|&v| u32::from(v))
+      .enumerate()
+      .fold(0, |acc, (idx, val)| acc + (val << (8 * idx))),
+  )
+}
+
+// Read null-terminated string, leaving off the null
+fn read_zero_terminated<I: Iterator<Item = u8>>(
+  bytes: &mut HeaderBytes<I>,
+) -> Result<Vec<u8>, GzipError> {
+  let mut result = vec![];
+  loop {
+    match bytes.next_byte()? {
+      b'\0' => break,
+      v => result.push(v),
+    }
+  }
+  Ok(result)
+}
+
+#[derive(Debug)]
+pub enum Compression {
+  Deflate,
+}
+
+impl Compression {
+  fn parse(byte: u8) -> Option<Compression> {
+    match byte {
+      8 => Some(Compression::Deflate),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CompressionInfo {
+  MaximumCompressionSlowestAlgorithm,
+  FastestAlgorithm,
+}
+
+impl CompressionInfo {
+  fn parse(byte: u8) -> Option<CompressionInfo> {
+    match byte {
+      2 => Some(CompressionInfo::MaximumCompressionSlowestAlgorithm),
+      4 => Some(CompressionInfo::FastestAlgorithm),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Os {
+  FATFilesystem,
+  Amiga,
+  VMS,
+  Unix,
+  VMcMS,
+  AtaritOS,
+  HPFS,
+  Macintosh,
+  Zsystem,
+  CPm,
+  TOPS20,
+  NTFS,
+  QDOS,
+  Acorn,
+  Unknown,
+  Other(u8), // not assigned by RFC 1952
+}
+
+impl Os {
+  fn parse(byte: u8) -> Os {
+    use Os::*;
+    match byte {
+      0 => FATFilesystem,
+      1 => Amiga,
+      2 => VMS,
+      3 => Unix,
+      4 => VMcMS,
+      5 => AtaritOS,
+      6 => HPFS,
+      7 => Macintosh,
+      8 => Zsystem,
+      9 => CPm,
+      10 => TOPS20,
+      11 => NTFS,
+      12 => QDOS,
+      13 => Acorn,
+      255 => Unknown,
+      _ => Other(byte),
+    }
+  }
+
+  fn to_byte(self) -> u8 {
+    use Os::*;
+    match self {
+      FATFilesystem => 0,
+      Amiga => 1,
+      VMS => 2,
+      Unix => 3,
+      VMcMS => 4,
+      AtaritOS => 5,
+      HPFS => 6,
+      Macintosh => 7,
+      Zsystem => 8,
+      CPm => 9,
+      TOPS20 => 10,
+      NTFS => 11,
+      QDOS => 12,
+      Acorn => 13,
+      Unknown => 255,
+      Other(byte) => byte,
+    }
+  }
+}
+
+// For OS bytes given as numbers, as on the command line
+impl From<u8> for Os {
+  fn from(byte: u8) -> Os {
+    Os::parse(byte)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::deflate::{BlockEncoding, InflateErrorKind};
+
+  // Several hundred kilobytes of data, compressed into a few members at
+  // different levels, for the modules that work member by member
+  pub(super) fn multi_member() -> (Vec<u8>, Vec<u8>) {
+    use std::io::Write;
+    let mut data = vec![];
+    let mut gzip = vec![];
+    for (i, name) in ["testpng", "gunzip", "raven", "testpng"].iter().enumerate() {
+      let src = std::fs::read(format!("tests/gzip/src/{}", name)).unwrap();
+      let level = crate::deflate::CompressionLevel::new(i as u8 * 3).unwrap();
+      let mut encoder = GzEncoder::with_level(vec![], level);
+      encoder.write_all(&src).unwrap();
+      gzip.extend(encoder.finish().unwrap());
+      data.extend(src);
+    }
+    (gzip, data)
+  }
+
+  // A single member, for the modules that corrupt or extend one
+  pub(super) fn gunzip_c() -> Vec<u8> {
+    include_bytes!("../../tests/gzip/dynamic_encoding/gunzip.c.gz").to_vec()
+  }
+
+  #[test]
+  fn test_src_vs_compressed_in_dirs() {
+    use std::fs;
+    use std::fs::File;
+    use std::io::Read;
+
+    let src_dir = "tests/gzip/src/";
+    let compressed_dir = "tests/gzip/compressed/";
+
+    for entry in fs::read_dir(src_dir).expect("failed to read src dir") {
+      let path = entry
+        .expect("failed entry")
+        .path()
+        .into_os_string()
+        .into_string()
+        .expect("failed to convert path to string");
+      let compressed_path = path.replace(src_dir, compressed_dir);
+
+      let mut src = vec![];
+      let mut file = File::open(path).expect("Failed to open file");
+      file.read_to_end(&mut src).expect("Failed to read file");
+
+      let mut compressed = vec![];
+      let mut file = File::open(compressed_path).expect("Failed to open file");
+      file
+        .read_to_end(&mut compressed)
+        .expect("Failed to read file");
+
+      let gzip = Gzip::new(compressed).expect("Failed to parse gzip");
+      assert_eq!(gzip.data, src);
+    }
+  }
+
+  #[test]
+  fn test_trace() {
+    let bytes = include_bytes!("../../tests/gzip/compressed/raven").to_vec();
+    let gzip = Gzip::new(bytes.clone()).unwrap();
+    assert!(gzip.members[0].decode_items.is_empty());
+    let options = ParseOptions {
+      trace: true,
+      ..ParseOptions::default()
+    };
+    let traced = Gzip::with_options(bytes, options).unwrap();
+    assert!(!traced.members[0].decode_items.is_empty());
+    assert_eq!(traced.data, gzip.data);
+  }
+
+  #[test]
+  fn test_crc32_in_dirs() {
+    use std::fs;
+    use std::fs::File;
+    use std::io::Read;
+
+    let src_dir = "tests/gzip/src/";
+    let compressed_dir = "tests/gzip/compressed/";
+
+    for entry in fs::read_dir(src_dir).expect("failed to read src dir") {
+      let path = entry
+        .expect("failed entry")
+        .path()
+        .into_os_string()
+        .into_string()
+        .expect("failed to convert path to string");
+      let compressed_path = path.replace(src_dir, compressed_dir);
+
+      let mut compressed = vec![];
+      let mut file = File::open(compressed_path).expect("Failed to open file");
+      file
+        .read_to_end(&mut compressed)
+        .expect("Failed to read file");
+
+      let gzip = Gzip::new(compressed).expect("Failed to parse gzip");
+      assert!(gzip.crc_is_valid());
+    }
+  }
+
+  mod dynamic_encoding {
+    use super::*;
+
+    #[test]
+    fn gunzip_c_file_structure() {
+      let bytes = include_bytes!("../../tests/gzip/dynamic_encoding/gunzip.c.gz");
+      let gzip = Gzip::new(bytes.to_vec()).expect("Failed to parse gzip");
+
+      assert_eq!(gzip.members[0].blocks.len(), 1);
+      assert_eq!(
+        gzip.members[0].blocks[0].encoding,
+        BlockEncoding::HuffmanDynamic
+      );
+    }
+  }
+
+  mod fixed_encoding {
+    use super::*;
+
+    #[test]
+    fn gzip_distance_with_extra_bits() {
+      // This file has fixed encoding, and a single match with a distance with an extra bit
+      // The match is len 6, dist 7
+      let bytes = include_bytes!("../../tests/gzip/fixed_encoding/deflate-1flate.txt.gz");
+      let gzip = Gzip::new(bytes.to_vec()).expect("Failed to parse gzip");
+
+      assert_eq!(gzip.members[0].blocks.len(), 1);
+      assert!(gzip.members[0].blocks[0].is_last);
+      assert_eq!(
+        gzip.members[0].blocks[0].encoding,
+        BlockEncoding::HuffmanFixed
+      );
+    }
+  }
+
+  mod multi_member {
+    use super::*;
+
+    fn png() -> Vec<u8> {
+      include_bytes!("../../tests/gzip/no_compression/png.png.gz").to_vec()
+    }
+
+    #[test]
+    fn concatenated_members() {
+      let first = gunzip_c();
+      let second = png();
+      let bytes = [&first[..], &second[..]].concat();
+      let gzip = Gzip::new(bytes).expect("Failed to parse gzip");
+
+      let expected = [
+        &Gzip::new(first.clone()).unwrap().data[..],
+        &include_bytes!("../../tests/gzip/no_compression/png.png")[..],
+      ]
+      .concat();
+      assert_eq!(gzip.data, expected);
+      assert!(gzip.crc_is_valid());
+      assert!(gzip.size_is_valid());
+
+      assert_eq!(gzip.members.len(), 2);
+      let (a, b) = (&gzip.members[0], &gzip.members[1]);
+      assert_eq!(a.headers.filename, Some(b"gunzip.c".to_vec()));
+      assert_eq!(b.headers.filename, Some(b"png.png".to_vec()));
+      assert_eq!((a.offset, a.len), (0, first.len()));
+      assert_eq!((b.offset, b.len), (first.len(), second.len()));
+      assert_eq!(a.data_range.end, b.data_range.start);
+      assert_eq!(b.data_range.end, gzip.data.len());
+      assert!(b
+        .blocks
+        .iter()
+        .any(|block| block.encoding == BlockEncoding::Stored));
+    }
+
+    #[test]
+    fn checksums_are_per_member() {
+      let first = gunzip_c();
+      let mut second = gunzip_c();
+      let crc_offset = second.len() - 8;
+      second[crc_offset] ^= 1;
+      let gzip = Gzip::new([&first[..], &second[..]].concat()).unwrap();
+      assert!(gzip.members[0].crc_is_valid());
+      assert!(!gzip.members[1].crc_is_valid());
+      assert!(!gzip.crc_is_valid());
+    }
+
+    #[test]
+    fn errors_in_later_members() {
+      let first = gunzip_c();
+      let mut second = gunzip_c();
+      second[2] = 7;
+      assert_eq!(
+        Gzip::new([&first[..], &second[..]].concat()).unwrap_err(),
+        GzipError::UnsupportedMethod {
+          offset: first.len() + 2,
+          method: 7
+        }
+      );
+
+      // A second member with a bad header is still an error, whatever the
+      // trailing data policy
+      let options = ParseOptions {
+        trailing_data: Policy::Ignore,
+        ..ParseOptions::default()
+      };
+      assert_eq!(
+        Gzip::with_options([&first[..], &second[..]].concat(), options).unwrap_err(),
+        GzipError::UnsupportedMethod {
+          offset: first.len() + 2,
+          method: 7
+        }
+      );
+    }
+  }
+
+  #[test]
+  fn test_read_int() {
+    let mut bytes = vec![0b0, 0b0, 0b0, 0b0].into_iter();
+    assert_eq!(read_int(&mut bytes, 4), Some(0));
+
+    let mut bytes = vec![0b1, 0b0, 0b0, 0b0].into_iter();
+    assert_eq!(read_int(&mut bytes, 4), Some(1));
+
+    let mut bytes = vec![0b0, 0b1, 0b0, 0b0].into_iter();
+    assert_eq!(read_int(&mut bytes, 4), Some(256));
+
+    let mut bytes = vec![0b0, 0b0, 0b1, 0b0].into_iter();
+    assert_eq!(read_int(&mut bytes, 4), Some(0x0001_0000));
+
+    let mut bytes = vec![0b0, 0b0, 0b0, 0b1].into_iter();
+    assert_eq!(read_int(&mut bytes, 4), Some(0x0100_0000));
+
+    let mut bytes = vec![0b0000_0000, 0b1111_1111, 0b0000_0000, 0b0000_1000].into_iter();
+    assert_eq!(read_int(&mut bytes, 4), Some(0x0800_ff00));
+
+    let mut bytes = vec![0b1, 0b0, 0b0].into_iter();
+    assert_eq!(read_int(&mut bytes, 4), None);
+  }
+
+  mod header_crc {
+    use super::*;
+
+    fn strict() -> ParseOptions {
+      ParseOptions {
+        strict: true,
+        ..ParseOptions::default()
+      }
+    }
+
+    // A member with FHCRC set and a correct CRC16, around an empty deflate
+    // stream. Returns it and the offset of the CRC16.
+    fn with_crc16() -> (Vec<u8>, usize) {
+      let mut bytes = vec![0x1f, 0x8b, 8, 0b10, 0, 0, 0, 0, 0, 3];
+      let crc16 = crc32::crc32(&bytes) as u16;
+      let offset = bytes.len();
+      bytes.extend_from_slice(&crc16.to_le_bytes());
+      bytes.extend_from_slice(&[0x03, 0x00, 0, 0, 0, 0, 0, 0, 0, 0]);
+      (bytes, offset)
+    }
+
+    #[test]
+    fn valid() {
+      let (bytes, _) = with_crc16();
+      let gzip = Gzip::with_options(bytes, strict()).unwrap();
+      assert!(gzip.members[0].headers.crc16.is_some());
+      assert!(gzip.crc16_is_valid());
+
+      // No CRC16 is not a bad one
+      let gzip = Gzip::new(include_bytes!("../../tests/gzip/compressed/raven").to_vec()).unwrap();
+      assert!(gzip.members[0].headers.crc16.is_none());
+      assert!(gzip.crc16_is_valid());
+    }
+
+    #[test]
+    fn mismatch() {
+      let (mut bytes, offset) = with_crc16();
+      bytes[offset] ^= 1;
+      let expected = u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+      let gzip = Gzip::new(bytes.clone()).unwrap();
+      assert!(!gzip.members[0].headers.crc16_is_valid());
+      assert!(!gzip.crc16_is_valid());
+
+      assert_eq!(
+        Gzip::with_options(bytes, strict()).unwrap_err(),
+        GzipError::HeaderChecksumMismatch {
+          offset,
+          expected,
+          found: expected ^ 1
+        }
+      );
+    }
+
+    #[test]
+    fn strict_trailer() {
+      let mut bytes = include_bytes!("../../tests/gzip/compressed/raven").to_vec();
+      let crc_offset = bytes.len() - 8;
+      bytes[crc_offset] ^= 1;
+      assert!(Gzip::new(bytes.clone()).is_ok());
+      match Gzip::with_options(bytes, strict()).unwrap_err() {
+        GzipError::ChecksumMismatch { offset, .. } => assert_eq!(offset, crc_offset),
+        e => panic!("Unexpected error {:?}", e),
+      }
+
+      let mut bytes = include_bytes!("../../tests/gzip/compressed/raven").to_vec();
+      let size_offset = bytes.len() - 4;
+      bytes[size_offset] ^= 1;
+      match Gzip::with_options(bytes, strict()).unwrap_err() {
+        GzipError::SizeMismatch { offset, .. } => assert_eq!(offset, size_offset),
+        e => panic!("Unexpected error {:?}", e),
+      }
+    }
+  }
+
+  mod policies {
+    use super::*;
+
+    fn with_policies(bytes: Vec<u8>, policy: Policy) -> Result<Gzip, GzipError> {
+      let options = ParseOptions {
+        reserved_flags: policy,
+        trailing_data: policy,
+        ..ParseOptions::default()
+      };
+      Gzip::with_options(bytes, options)
+    }
+
+    #[test]
+    fn trailing_data() {
+      let first = gunzip_c();
+      let bytes = [&first[..], &[0, 0, 0x1f][..]].concat();
+      let len = bytes.len();
+      let trailing = GzipError::TrailingData {
+        offset: first.len(),
+      };
+      assert_eq!(Gzip::new(bytes.clone()).unwrap_err(), trailing);
+
+      let gzip = with_policies(bytes.clone(), Policy::Warn).unwrap();
+      assert_eq!(gzip.members.len(), 1);
+      assert_eq!(gzip.trailing_data, Some(first.len()..len));
+      assert_eq!(gzip.warnings, vec![trailing]);
+
+      let gzip = with_policies(bytes, Policy::Ignore).unwrap();
+      assert_eq!(gzip.trailing_data, Some(first.len()..len));
+      assert_eq!(gzip.warnings, vec![]);
+
+      let gzip = Gzip::new(first).unwrap();
+      assert_eq!(gzip.trailing_data, None);
+    }
+
+    #[test]
+    fn reserved_flags() {
+      let first = gunzip_c();
+      let mut second = gunzip_c();
+      second[3] |= 0b0100_0000;
+      let flags = second[3];
+      let bytes = [&first[..], &second[..]].concat();
+      let reserved = GzipError::ReservedFlags {
+        offset: first.len() + 3,
+        flags,
+      };
+      assert_eq!(Gzip::new(bytes.clone()).unwrap_err(), reserved);
+
+      let gzip = with_policies(bytes.clone(), Policy::Warn).unwrap();
+      assert_eq!(gzip.members.len(), 2);
+      assert_eq!(gzip.members[1].headers.reserved_flags(), 0b0100_0000);
+      assert_eq!(gzip.warnings, vec![reserved]);
+      assert_eq!(
+        gzip.data,
+        with_policies(bytes, Policy::Ignore).unwrap().data
+      );
+    }
+  }
+
+  #[test]
+  fn test_raw_header_bytes() {
+    // FEXTRA with a binary BGZF subfield, and a Latin-1 FNAME
+    let mut bytes = vec![0x1f, 0x8b, 8, 0b1100, 0, 0, 0, 0, 0, 3, 6, 0];
+    bytes.extend_from_slice(&[b'B', b'C', 2, 0, 0xff, 0x00]);
+    bytes.extend_from_slice(b"na\xefve.txt\0");
+    bytes.extend_from_slice(&[0x03, 0x00, 0, 0, 0, 0, 0, 0, 0, 0]);
+    let gzip = Gzip::new(bytes).unwrap();
+
+    let headers = &gzip.members[0].headers;
+    assert_eq!(headers.filename, Some(b"na\xefve.txt".to_vec()));
+    assert_eq!(headers.filename_latin1(), Some(String::from("naïve.txt")));
+    assert_eq!(headers.comment_latin1(), None);
+    assert_eq!(
+      headers.extra_fields,
+      vec![ExtraField {
+        id: *b"BC",
+        data: vec![0xff, 0x00],
+      }]
+    );
+    assert_eq!(
+      headers.extra_fields[0].decode(),
+      Some(Subfield::Bgzf { block_size: 0xff })
+    );
+  }
+
+  mod errors {
+    use super::*;
+
+    #[test]
+    fn bad_magic() {
+      let mut bytes = gunzip_c();
+      bytes[1] = 0x8c;
+      assert_eq!(
+        Gzip::new(bytes).unwrap_err(),
+        GzipError::BadMagic { offset: 1 }
+      );
+      assert_eq!(
+        Gzip::new(b"PK\x03\x04".to_vec()).unwrap_err(),
+        GzipError::BadMagic { offset: 0 }
+      );
+    }
+
+    #[test]
+    fn unsupported_method() {
+      let mut bytes = gunzip_c();
+      bytes[2] = 7;
+      assert_eq!(
+        Gzip::new(bytes).unwrap_err(),
+        GzipError::UnsupportedMethod {
+          offset: 2,
+          method: 7
+        }
+      );
+    }
+
+    #[test]
+    fn reserved_flags() {
+      let mut bytes = gunzip_c();
+      bytes[3] |= 0b1000_0000;
+      let flags = bytes[3];
+      assert_eq!(
+        Gzip::new(bytes).unwrap_err(),
+        GzipError::ReservedFlags { offset: 3, flags }
+      );
+    }
+
+    #[test]
+    fn truncated_header() {
+      let bytes = gunzip_c();
+      assert_eq!(
+        Gzip::new(bytes[..0].to_vec()).unwrap_err(),
+        GzipError::TruncatedHeader { offset: 0 }
+      );
+      assert_eq!(
+        Gzip::new(bytes[..6].to_vec()).unwrap_err(),
+        GzipError::TruncatedHeader { offset: 6 }
+      );
+      // gunzip.c.gz has a filename, which is cut off before its terminating NUL
+      assert_eq!(
+        Gzip::new(bytes[..12].to_vec()).unwrap_err(),
+        GzipError::TruncatedHeader { offset: 12 }
+      );
+    }
+
+    #[test]
+    fn truncated_trailer() {
+      // A header with no filename, followed by too few bytes for the trailer
+      let bytes = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 3, 0x03, 0x00, 0, 0, 0];
+      assert_eq!(
+        Gzip::new(bytes).unwrap_err(),
+        GzipError::TruncatedTrailer { offset: 15 }
+      );
+    }
+
+    #[test]
+    fn truncated_data() {
+      let bytes = gunzip_c();
+      let truncated = bytes[..2000].to_vec();
+      match Gzip::new(truncated).unwrap_err() {
+        GzipError::Inflate { error, .. } => {
+          assert_eq!(error.kind, InflateErrorKind::UnexpectedEnd)
+        }
+        e => panic!("Unexpected error {:?}", e),
+      }
+    }
+
+    #[test]
+    fn bad_extra_field() {
+      // XLEN says 4 bytes, but the single subfield claims 1 byte of data on top of its 4-byte header
+      let bytes = vec![
+        0x1f, 0x8b, 8, 0b100, 0, 0, 0, 0, 0, 3, 4, 0, b'A', b'B', 1, 0, b'x',
+      ];
+      assert_eq!(
+        Gzip::new(bytes).unwrap_err(),
+        GzipError::BadExtraField { offset: 12 }
+      );
+    }
+  }
+}
@@ -0,0 +1,251 @@
+// Compresses on several threads at once, as pigz does. The input is cut into
+// chunks, and each is compressed on its own, with the 32K of input before it
+// as a preset dictionary so that matches can still reach back across the
+// cut. Each chunk but the last ends with an empty stored block, which
+// leaves it on a byte boundary, so the chunks can simply be laid end to end
+// to make one deflate stream, in one gzip member. The CRC32s of the chunks
+// are combined into the one for the trailer.
+use super::encoder::compression_info;
+use super::workers::run_ordered;
+use super::HeadersBuilder;
+use crate::crc32;
+use crate::deflate::{CompressionLevel, Encoder, WINDOW_SIZE};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::thread;
+
+// How much input goes into each chunk, as in pigz
+const CHUNK_SIZE: usize = 128 * 1024;
+
+pub struct ParEncoder {
+  level: CompressionLevel,
+  threads: usize,
+  header: HeadersBuilder,
+}
+
+struct Job {
+  dictionary: Vec<u8>,
+  data: Vec<u8>,
+  is_last: bool,
+}
+
+// The input, cut into jobs. It is read one chunk ahead, to know which job is
+// the last. A read error ends the jobs early, and is kept for `compress`.
+struct Jobs<R: Read> {
+  input: R,
+  next: Option<Vec<u8>>,
+  dictionary: Vec<u8>,
+  error: Option<io::Error>,
+}
+
+struct Chunk {
+  len: usize,
+  crc32: u32,
+  compressed: Vec<u8>,
+}
+
+impl ParEncoder {
+  pub fn new() -> ParEncoder {
+    ParEncoder::with_level(CompressionLevel::default())
+  }
+
+  // Uses a thread for each CPU, until told otherwise
+  pub fn with_level(level: CompressionLevel) -> ParEncoder {
+    ParEncoder {
+      level,
+      threads: thread::available_parallelism().map_or(1, |n| n.get()),
+      header: HeadersBuilder::new(),
+    }
+  }
+
+  pub fn threads(mut self, threads: usize) -> ParEncoder {
+    self.threads = threads.max(1);
+    self
+  }
+
+  // The header fields. XFL is always set from the compression level.
+  pub fn headers(mut self, headers: HeadersBuilder) -> ParEncoder {
+    self.header = headers;
+    self
+  }
+
+  // Compress all of `input` into a gzip file on `output`, returning how
+  // much input there was
+  pub fn compress(self, mut input: impl Read, output: &mut impl Write) -> io::Result<u64> {
+    self
+      .header
+      .compression_info(compression_info(self.level))
+      .build()
+      .write_to(output)?;
+
+    let level = self.level;
+    let mut jobs = Jobs {
+      next: Some(read_chunk(&mut input)?),
+      input,
+      dictionary: vec![],
+      error: None,
+    };
+    let mut crc = crc32::crc32(&[]);
+    let mut len = 0;
+    run_ordered(
+      self.threads,
+      &mut jobs,
+      |job| compress_chunk(&job, level),
+      |chunk: Chunk| -> io::Result<()> {
+        output.write_all(&chunk.compressed)?;
+        crc = crc32::combine(crc, chunk.crc32, chunk.len as u64);
+        len += chunk.len as u64;
+        Ok(())
+      },
+    )?;
+    if let Some(error) = jobs.error {
+      return Err(error);
+    }
+
+    output.write_all(&crc.to_le_bytes())?;
+    output.write_all(&(len as u32).to_le_bytes())?;
+    output.flush()?;
+    Ok(len)
+  }
+}
+
+impl Default for ParEncoder {
+  fn default() -> ParEncoder {
+    ParEncoder::new()
+  }
+}
+
+impl<R: Read> Iterator for Jobs<R> {
+  type Item = Job;
+
+  fn next(&mut self) -> Option<Job> {
+    let data = self.next.take()?;
+    match read_chunk(&mut self.input) {
+      Ok(next) => self.next = Some(next).filter(|next| !next.is_empty()),
+      Err(error) => {
+        self.error = Some(error);
+        return None;
+      }
+    }
+    let tail = data[data.len().saturating_sub(WINDOW_SIZE)..].to_vec();
+    Some(Job {
+      dictionary: mem::replace(&mut self.dictionary, tail),
+      data,
+      is_last: self.next.is_none(),
+    })
+  }
+}
+
+fn compress_chunk(job: &Job, level: CompressionLevel) -> Chunk {
+  let mut encoder = Encoder::with_dictionary(level, &job.dictionary);
+  encoder.write(&job.data);
+  if job.is_last {
+    encoder.finish();
+  } else {
+    encoder.sync_flush();
+  }
+  Chunk {
+    len: job.data.len(),
+    crc32: crc32::crc32(&job.data),
+    compressed: encoder.take_output(),
+  }
+}
+
+// Up to CHUNK_SIZE bytes of input, which is only short at the end
+fn read_chunk(input: &mut impl Read) -> io::Result<Vec<u8>> {
+  let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+  input
+    .by_ref()
+    .take(CHUNK_SIZE as u64)
+    .read_to_end(&mut chunk)?;
+  Ok(chunk)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::gzip::{GzEncoder, Gzip};
+
+  // Several chunks' worth, with matches that reach across the cuts
+  fn fixture() -> Vec<u8> {
+    let files: Vec<u8> = ["testpng", "gunzip", "raven", "gunzip"]
+      .iter()
+      .flat_map(|name| std::fs::read(format!("tests/gzip/src/{}", name)).unwrap())
+      .collect();
+    files.repeat(3)
+  }
+
+  fn compress(encoder: ParEncoder, data: &[u8]) -> Vec<u8> {
+    let mut output = vec![];
+    let len = encoder.compress(data, &mut output).unwrap();
+    assert_eq!(len, data.len() as u64);
+    output
+  }
+
+  #[test]
+  fn test_round_trip() {
+    let data = fixture();
+    assert!(data.len() > 3 * CHUNK_SIZE);
+    for &(level, threads) in &[(6, 1), (6, 4), (0, 3), (1, 2), (9, 8)] {
+      let encoder = ParEncoder::with_level(CompressionLevel::new(level).unwrap()).threads(threads);
+      let gzip = Gzip::new(compress(encoder, &data)).unwrap();
+      assert_eq!(gzip.members.len(), 1);
+      assert!(gzip.members[0].crc_is_valid());
+      assert!(gzip.members[0].size_is_valid());
+      assert_eq!(gzip.data, data);
+    }
+  }
+
+  #[test]
+  fn test_empty() {
+    let gzip = compress(ParEncoder::new(), b"");
+    assert_eq!(gzip, GzEncoder::new(vec![]).finish().unwrap());
+  }
+
+  #[test]
+  fn test_near_single_threaded_size() {
+    // The dictionaries keep the cost of cutting the input up small
+    let data = fixture();
+    let parallel = compress(ParEncoder::new().threads(4), &data);
+    let mut encoder = GzEncoder::new(vec![]);
+    encoder.write_all(&data).unwrap();
+    let single = encoder.finish().unwrap();
+    assert!(parallel.len() < single.len() + single.len() / 100);
+  }
+
+  #[test]
+  fn test_read_error() {
+    let data = fixture();
+    let failing = (&data[..3 * CHUNK_SIZE]).chain(FailingReader);
+    let error = ParEncoder::new()
+      .threads(2)
+      .compress(failing, &mut vec![])
+      .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+  }
+
+  struct FailingReader;
+
+  impl Read for FailingReader {
+    fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+      Err(io::ErrorKind::BrokenPipe.into())
+    }
+  }
+
+  #[test]
+  fn test_headers() {
+    let encoder = ParEncoder::with_level(CompressionLevel::best()).headers(
+      HeadersBuilder::new()
+        .filename("dump.sql")
+        .mtime(1_500_000_000),
+    );
+    let gzip = Gzip::new(compress(encoder, b"select 1;")).unwrap();
+    let headers = &gzip.members[0].headers;
+    assert_eq!(headers.filename_latin1().as_deref(), Some("dump.sql"));
+    assert_eq!(headers.mtime, 1_500_000_000);
+    assert_eq!(
+      headers.compression_info,
+      Some(crate::gzip::CompressionInfo::MaximumCompressionSlowestAlgorithm)
+    );
+  }
+}
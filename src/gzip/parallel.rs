@@ -0,0 +1,217 @@
+// Decompressing the members of a file on several threads at once. Each
+// member starts with an empty window, so it can be inflated without knowing
+// anything about the ones before it.
+//
+// What cannot be known without inflating is where the members start. In a
+// BGZF file each block's BC subfield gives its size, so the starts can be
+// read straight off. Otherwise every place that looks like a gzip header is
+// a candidate, and is inflated as if it were one; the real members are the
+// chain of candidates that starts at byte 0 and in which each ends where the
+// next begins. The rest (magic bytes that happen to turn up in compressed
+// data, or in stored copies of other .gz files) cost some wasted work, but
+// usually fail within a few bytes.
+use super::extra::bgzf_block_size;
+use super::workers::run_ordered;
+use super::{check_trailer, GzipError, Headers, MAGIC, TRAILER_LEN};
+use crate::deflate::inflate_raw;
+use std::io::{self, Write};
+
+// A candidate's start, and its compressed length and data, or why it is not
+// a member
+type Decoded = (usize, Result<(usize, Vec<u8>), GzipError>);
+
+// Decompress every member of `bytes` using `threads` threads, writing the
+// data to `output` in order, and returning how much was written. Every
+// member's CRC32 and ISIZE are checked, and anything after the last member
+// is an error, as with the default `ParseOptions`.
+pub fn par_decompress(bytes: &[u8], threads: usize, output: &mut impl Write) -> io::Result<u64> {
+  let starts = member_starts(bytes);
+  let mut offset = 0;
+  let mut written = 0;
+  run_ordered(
+    threads,
+    starts.into_iter(),
+    |start| (start, decode_member(&bytes[start..])),
+    |(start, decoded): Decoded| -> io::Result<()> {
+      // Candidates the chain has already passed are of no use
+      if start < offset {
+        return Ok(());
+      }
+      if start > offset {
+        return Err(GzipError::TrailingData { offset }.into());
+      }
+      let (len, data) = decoded.map_err(|error| error.shifted(offset))?;
+      output.write_all(&data)?;
+      written += data.len() as u64;
+      offset += len;
+      Ok(())
+    },
+  )?;
+  // Candidate 0 is always tried, so the chain has at least started
+  if offset < bytes.len() {
+    return Err(GzipError::TrailingData { offset }.into());
+  }
+  Ok(written)
+}
+
+// Where members might start. While the blocks have BC subfields, as in
+// BGZF, their starts are read straight off; from the first one that does
+// not, every place the magic bytes turn up is a candidate, since BGZF may
+// well be followed by plain members. Always includes 0, so that a file that
+// is not gzip at all gets a proper error.
+fn member_starts(bytes: &[u8]) -> Vec<usize> {
+  let mut starts = vec![0];
+  let mut offset = 0;
+  while let Some(len) = bgzf_block_len(&bytes[offset..]) {
+    offset += len;
+    if offset >= bytes.len() {
+      // Leaving any block that runs past the end for the error to be found at
+      return starts;
+    }
+    starts.push(offset);
+  }
+  starts.extend((offset + 1..bytes.len()).filter(|&i| bytes[i..].starts_with(&MAGIC)));
+  starts
+}
+
+// The length of the block at the start of `bytes`, if its header has a BC
+// subfield
+fn bgzf_block_len(bytes: &[u8]) -> Option<usize> {
+  let mut iter = bytes.iter().cloned();
+  let headers = Headers::new(&mut iter).ok()?;
+  bgzf_block_size(&headers.extra_fields).map(|size| usize::from(size) + 1)
+}
+
+// Inflate the member at the start of `bytes`, checking its trailer. Offsets
+// in errors are from the start of the member.
+fn decode_member(bytes: &[u8]) -> Result<(usize, Vec<u8>), GzipError> {
+  let mut iter = bytes.iter().cloned();
+  let headers = Headers::new(&mut iter)?;
+  let header_len = bytes.len() - iter.len();
+  headers.check_reserved_flags()?;
+  headers.check_crc16(header_len - 2)?;
+
+  let raw =
+    inflate_raw(&bytes[header_len..]).map_err(|error| GzipError::inflate(header_len, error))?;
+  let trailer_start = header_len + raw.bytes_consumed;
+  check_trailer(
+    raw.remainder,
+    trailer_start,
+    raw.crc32,
+    raw.data.len() as u64,
+  )?;
+  Ok((trailer_start + TRAILER_LEN, raw.data))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::deflate::CompressionLevel;
+  use crate::gzip::bgzf::BgzfWriter;
+  use crate::gzip::test::multi_member;
+  use crate::crc32;
+  use crate::gzip::{GzEncoder, Gzip, HeadersBuilder};
+
+  fn compress(data: &[u8], level: u8) -> Vec<u8> {
+    let mut encoder = GzEncoder::with_level(vec![], CompressionLevel::new(level).unwrap());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+  }
+
+  // The shared members, and one more holding stored copies of .gz files,
+  // whose headers look like members but are not
+  fn fixture() -> (Vec<u8>, Vec<u8>) {
+    let (mut gzip, mut data) = multi_member();
+    let raven = std::fs::read("tests/gzip/src/raven").unwrap();
+    let nested = [compress(b"hello", 6), compress(&raven, 6)].concat();
+    gzip.extend(compress(&nested, 0));
+    data.extend(nested);
+    (gzip, data)
+  }
+
+  fn decompress(bytes: &[u8], threads: usize) -> io::Result<Vec<u8>> {
+    let mut output = vec![];
+    let written = par_decompress(bytes, threads, &mut output)?;
+    assert_eq!(written, output.len() as u64);
+    Ok(output)
+  }
+
+  #[test]
+  fn test_multi_member() {
+    let (gzip, data) = fixture();
+    assert_eq!(Gzip::new(gzip.clone()).unwrap().data, data);
+    for &threads in &[0, 1, 2, 8] {
+      assert_eq!(decompress(&gzip, threads).unwrap(), data);
+    }
+  }
+
+  #[test]
+  fn test_bgzf() {
+    let (_, data) = fixture();
+    let mut writer = BgzfWriter::new(vec![]);
+    writer.write_all(&data).unwrap();
+    let bgzf = writer.finish().unwrap();
+    assert_eq!(member_starts(&bgzf).len(), data.len().div_ceil(0xff00) + 1);
+    assert_eq!(decompress(&bgzf, 4).unwrap(), data);
+  }
+
+  #[test]
+  fn test_bgzf_then_plain() {
+    let (_, data) = fixture();
+    let mut writer = BgzfWriter::new(vec![]);
+    writer.write_all(&data[..200_000]).unwrap();
+    let mut bytes = writer.finish().unwrap();
+    let bgzf_len = bytes.len();
+    bytes.extend(compress(&data[200_000..300_000], 6));
+    bytes.extend(compress(&data[300_000..], 1));
+
+    let starts = member_starts(&bytes);
+    assert!(starts.contains(&bgzf_len));
+    assert_eq!(Gzip::new(bytes.clone()).unwrap().data, data);
+    assert_eq!(decompress(&bytes, 4).unwrap(), data);
+  }
+
+  #[test]
+  fn test_errors() {
+    let (mut gzip, _) = fixture();
+    let second = compress(b"", 0);
+    let error = |bytes: &[u8]| decompress(bytes, 4).unwrap_err().to_string();
+
+    assert_eq!(
+      error(b""),
+      GzipError::TruncatedHeader { offset: 0 }.to_string()
+    );
+
+    let len = gzip.len();
+    gzip.extend(b"junk");
+    assert_eq!(
+      error(&gzip),
+      GzipError::TrailingData { offset: len }.to_string()
+    );
+    gzip.truncate(len);
+
+    // A bad CRC32 in the last member is found where it is in the file
+    gzip.extend(&second);
+    let crc_offset = gzip.len() - 8;
+    gzip[crc_offset] ^= 1;
+    assert!(error(&gzip).contains(&format!("byte {}", crc_offset)));
+    gzip.truncate(len);
+
+    // As is a bad header CRC16
+    let headers = HeadersBuilder::new().header_crc(true);
+    let mut encoder = GzEncoder::with_headers(vec![], CompressionLevel::default(), headers);
+    encoder.write_all(b"checked").unwrap();
+    gzip.extend(encoder.finish().unwrap());
+    let crc16 = crc32::crc32(&gzip[len..len + 10]) as u16;
+    gzip[len + 10] ^= 1;
+    assert_eq!(
+      error(&gzip),
+      GzipError::HeaderChecksumMismatch {
+        offset: len + 10,
+        expected: crc16 ^ 1,
+        found: crc16,
+      }
+      .to_string()
+    );
+  }
+}
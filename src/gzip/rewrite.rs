@@ -0,0 +1,218 @@
+use super::{Gzip, GzipError, Headers, ParseOptions, MAGIC};
+use std::io;
+
+// Rewrites the header of a gzip file's first member, as `edit` changes it,
+// and copies everything after the header (the deflate stream, the trailer,
+// and any further members) as it is. Nothing is decompressed: the CRC32 and
+// ISIZE in the trailer only cover the data, so they stay valid. The new
+// header is written as `Headers::write_to` does, so an FHCRC is recomputed
+// and any reserved FLG bits are dropped.
+//
+// Where one member ends cannot be found without inflating it, so the
+// headers of later members are left alone; `rewrite_all_headers` does
+// inflate, to rewrite them all.
+pub fn rewrite_headers(bytes: &[u8], edit: impl FnOnce(&mut Headers)) -> io::Result<Vec<u8>> {
+  let mut iter = bytes.iter().cloned();
+  let mut headers = Headers::new(&mut iter)?;
+  let header_len = bytes.len() - iter.len();
+  edit(&mut headers);
+
+  let mut result = Vec::with_capacity(bytes.len());
+  headers.write_to(&mut result)?;
+  result.extend_from_slice(&bytes[header_len..]);
+  Ok(result)
+}
+
+// Rewrites the header of every member, as `edit` changes each in turn. To
+// find where the members are they are all inflated, and their CRC16s,
+// CRC32s and ISIZEs checked as `verify` does, but the compressed data and
+// trailers are still copied as they are.
+pub fn rewrite_all_headers(
+  bytes: &[u8],
+  mut edit: impl FnMut(&mut Headers),
+) -> io::Result<Vec<u8>> {
+  let gzip = Gzip::with_options(bytes.to_vec(), strict())?;
+  let mut result = Vec::with_capacity(bytes.len());
+  for member in gzip.members {
+    let start = member.offset;
+    let end = start + member.len;
+    let mut iter = bytes[start..end].iter().cloned();
+    let mut headers = Headers::new(&mut iter)?;
+    let header_len = end - start - iter.len();
+    edit(&mut headers);
+    headers.write_to(&mut result)?;
+    result.extend_from_slice(&bytes[start + header_len..end]);
+  }
+  Ok(result)
+}
+
+// Whether there may be members after the first, whose headers
+// `rewrite_headers` would leave alone. This only looks for the magic bytes
+// after the first header, so it can be wrong, but only in saying yes.
+pub fn may_have_later_members(bytes: &[u8]) -> bool {
+  let mut iter = bytes.iter().cloned();
+  match Headers::new(&mut iter) {
+    Ok(_) => bytes[bytes.len() - iter.len()..]
+      .windows(MAGIC.len())
+      .any(|window| window == MAGIC),
+    Err(_) => false,
+  }
+}
+
+// Decompresses every member and checks its header CRC16, CRC32 and ISIZE,
+// for when a rewritten file should be checked before it replaces the
+// original
+pub fn verify(bytes: &[u8]) -> Result<(), GzipError> {
+  Gzip::with_options(bytes.to_vec(), strict()).map(|_| ())
+}
+
+fn strict() -> ParseOptions {
+  ParseOptions {
+    strict: true,
+    ..ParseOptions::default()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::deflate::CompressionLevel;
+  use crate::gzip::{GzEncoder, HeadersBuilder, Os};
+  use std::io::Write;
+
+  fn header_len(headers: &Headers) -> usize {
+    let mut bytes = vec![];
+    headers.write_to(&mut bytes).unwrap();
+    bytes.len()
+  }
+
+  #[test]
+  fn test_rewrite_headers() {
+    let bytes = include_bytes!("../../tests/gzip/compressed/raven");
+    let original = Gzip::new(bytes.to_vec()).unwrap();
+    assert!(original.members[0].headers.filename.is_some());
+    assert_ne!(original.members[0].headers.mtime, 0);
+
+    let rewritten = rewrite_headers(bytes, |headers| {
+      headers.mtime = 0;
+      headers.filename = None;
+      headers.os = Os::Unknown;
+    })
+    .unwrap();
+    verify(&rewritten).unwrap();
+
+    let gzip = Gzip::new(rewritten.clone()).unwrap();
+    let headers = &gzip.members[0].headers;
+    assert_eq!(headers.mtime, 0);
+    assert_eq!(headers.filename, None);
+    assert_eq!(headers.os, Os::Unknown);
+    assert_eq!(gzip.data, original.data);
+
+    // The compressed data and trailer are copied as they were
+    assert_eq!(
+      rewritten[header_len(headers)..],
+      bytes[header_len(&original.members[0].headers)..]
+    );
+  }
+
+  // And where the second starts
+  fn two_members() -> (Vec<u8>, usize) {
+    let mut encoder = GzEncoder::with_headers(
+      vec![],
+      CompressionLevel::default(),
+      HeadersBuilder::new().filename("a.txt").mtime(1),
+    );
+    encoder.write_all(b"first").unwrap();
+    let mut bytes = encoder.finish().unwrap();
+    let second = bytes.len();
+    let mut encoder = GzEncoder::with_headers(
+      vec![],
+      CompressionLevel::default(),
+      HeadersBuilder::new().filename("b.txt").mtime(2),
+    );
+    encoder.write_all(b"second").unwrap();
+    bytes.extend(encoder.finish().unwrap());
+    (bytes, second)
+  }
+
+  #[test]
+  fn test_rewrite_keeps_later_members() {
+    let (bytes, second) = two_members();
+    let second = &bytes[second..];
+
+    let rewritten = rewrite_headers(&bytes, |headers| headers.mtime = 0).unwrap();
+    assert!(rewritten.ends_with(second));
+    let gzip = Gzip::new(rewritten).unwrap();
+    assert_eq!(gzip.members[0].headers.mtime, 0);
+    assert_eq!(gzip.members[1].headers.mtime, 2);
+    assert_eq!(gzip.data, b"firstsecond");
+  }
+
+  #[test]
+  fn test_rewrite_all_headers() {
+    let (bytes, _) = two_members();
+    assert!(may_have_later_members(&bytes));
+    let rewritten = rewrite_all_headers(&bytes, |headers| {
+      headers.mtime = 0;
+      headers.filename = None;
+    })
+    .unwrap();
+    verify(&rewritten).unwrap();
+    let gzip = Gzip::new(rewritten).unwrap();
+    for member in &gzip.members {
+      assert_eq!(member.headers.mtime, 0);
+      assert_eq!(member.headers.filename, None);
+    }
+    assert_eq!(gzip.data, b"firstsecond");
+
+    // Corrupt input is not rewritten
+    let mut bytes = bytes;
+    let len = bytes.len();
+    bytes[len - 8] ^= 1;
+    let error = rewrite_all_headers(&bytes, |_| ()).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+  }
+
+  #[test]
+  fn test_rewrite_recomputes_header_crc() {
+    let mut encoder = GzEncoder::with_headers(
+      vec![],
+      CompressionLevel::default(),
+      HeadersBuilder::new().header_crc(true).filename("name"),
+    );
+    encoder.write_all(b"data").unwrap();
+    let bytes = encoder.finish().unwrap();
+
+    let rewritten = rewrite_headers(&bytes, |headers| headers.filename = None).unwrap();
+    assert_eq!(rewritten.len(), bytes.len() - 5);
+    verify(&rewritten).unwrap();
+  }
+
+  #[test]
+  fn test_verify_finds_corruption() {
+    let mut bytes = include_bytes!("../../tests/gzip/compressed/raven").to_vec();
+    let rewritten = rewrite_headers(&bytes, |headers| headers.mtime = 0).unwrap();
+    verify(&rewritten).unwrap();
+
+    // A damaged CRC32 is copied along with everything else
+    let len = bytes.len();
+    bytes[len - 8] ^= 1;
+    let rewritten = rewrite_headers(&bytes, |headers| headers.mtime = 0).unwrap();
+    match verify(&rewritten) {
+      Err(GzipError::ChecksumMismatch { .. }) => (),
+      other => panic!("expected a checksum mismatch, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn test_single_member() {
+    let bytes = include_bytes!("../../tests/gzip/compressed/raven");
+    assert!(!may_have_later_members(bytes));
+  }
+
+  #[test]
+  fn test_bad_header() {
+    let error = rewrite_headers(&[0x1f, 0x8b, 8], |_| ()).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+  }
+}
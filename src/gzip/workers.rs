@@ -0,0 +1,130 @@
+// A pool of threads that works through a queue of jobs, handing the results
+// back in the order the jobs came in, for `par_decompress` and `ParEncoder`.
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread;
+
+// How many jobs past the one being waited for may be queued, per thread,
+// which bounds how many jobs and results are held at once
+const LOOKAHEAD: usize = 4;
+
+// Run `work` on each of `jobs` on `threads` threads, passing the results to
+// `on_result` in the order of the jobs. Jobs are only taken as there is room
+// for them. The first error from `on_result` is returned at once, and the
+// workers stop after their current job.
+pub(super) fn run_ordered<J, R, E>(
+  threads: usize,
+  jobs: impl Iterator<Item = J>,
+  work: impl Fn(J) -> R + Sync,
+  mut on_result: impl FnMut(R) -> Result<(), E>,
+) -> Result<(), E>
+where
+  J: Send,
+  R: Send,
+{
+  let threads = threads.max(1);
+  let window = threads * LOOKAHEAD;
+  let (job_sender, job_receiver) = mpsc::channel::<(usize, J)>();
+  let (result_sender, result_receiver) = mpsc::channel::<(usize, R)>();
+  let job_receiver = Mutex::new(job_receiver);
+  let work = &work;
+
+  thread::scope(|scope| {
+    for _ in 0..threads {
+      let result_sender = result_sender.clone();
+      let job_receiver = &job_receiver;
+      scope.spawn(move || loop {
+        // The lock is only held while waiting for a job, not while working
+        let (index, job) = match job_receiver.lock().unwrap().recv() {
+          Ok(job) => job,
+          Err(_) => break,
+        };
+        if result_sender.send((index, work(job))).is_err() {
+          break;
+        }
+      });
+    }
+    drop(result_sender);
+
+    // Returning drops both channels (they are moved in here for that), which
+    // stops the workers
+    let (job_sender, result_receiver) = (job_sender, result_receiver);
+    let mut jobs = jobs.fuse();
+    let mut queued = 0;
+    let mut done = HashMap::new();
+    let mut next = 0;
+    loop {
+      while queued < next + window {
+        match jobs.next() {
+          Some(job) => job_sender
+            .send((queued, job))
+            .expect("workers stopped early"),
+          None => break,
+        }
+        queued += 1;
+      }
+      if next == queued {
+        return Ok(());
+      }
+
+      match done.remove(&next) {
+        Some(result) => {
+          on_result(result)?;
+          next += 1;
+        }
+        None => {
+          // Job `next` has been queued, so this cannot hang
+          let (index, result) = result_receiver.recv().expect("workers stopped early");
+          done.insert(index, result);
+        }
+      }
+    }
+  })
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::time::Duration;
+
+  #[test]
+  fn test_in_order() {
+    for &threads in &[0, 1, 3, 16] {
+      let mut results = vec![];
+      let result: Result<(), ()> = run_ordered(
+        threads,
+        0..100_u64,
+        |job| {
+          // Later jobs tend to finish first
+          thread::sleep(Duration::from_micros((100 - job) * 10));
+          job * 2
+        },
+        |result| {
+          results.push(result);
+          Ok(())
+        },
+      );
+      assert_eq!(result, Ok(()));
+      assert_eq!(results, (0..100).map(|job| job * 2).collect::<Vec<_>>());
+    }
+  }
+
+  #[test]
+  fn test_stops_at_error() {
+    let mut taken = 0;
+    let jobs = (0..).inspect(|_| taken += 1);
+    let result = run_ordered(
+      2,
+      jobs,
+      |job: u32| job,
+      |result| match result {
+        10 => Err(result),
+        _ => Ok(()),
+      },
+    );
+    assert_eq!(result, Err(10));
+    // Only a window's worth of jobs past the failed one were taken
+    assert!(taken <= 11 + 2 * LOOKAHEAD);
+  }
+}
@@ -1,6 +1,8 @@
+pub mod adler32;
 pub mod crc32;
 pub mod deflate;
 pub mod gzip;
+pub mod zlib;
 
 #[macro_use]
 extern crate serde_derive;
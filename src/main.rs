@@ -3,10 +3,11 @@ use std::error::Error;
 use std::fs::File;
 use std::io::Read;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 
-use gziprust::gzip::Gzip;
+use gziprust::deflate::CompressionLevel;
+use gziprust::gzip::{self, Gzip, Member, Os, ParseOptions, Policy};
 
 #[derive(Debug, StructOpt)]
 #[structopt(
@@ -14,25 +15,167 @@ use gziprust::gzip::Gzip;
   about = "A tool for decoding and exploring Gzip'd files"
 )]
 pub struct Opt {
+  #[structopt(subcommand)]
+  command: Option<Command>,
+
   /// Activate debug mode
   #[structopt(short = "d", long = "debug")]
   debug: bool,
 
+  /// Fail on a header CRC16, CRC32 or size that does not match
+  #[structopt(long = "strict")]
+  strict: bool,
+
+  /// What to do about reserved FLG bits: error, warn or ignore
+  #[structopt(long = "reserved-flags", default_value = "error")]
+  reserved_flags: Policy,
+
+  /// What to do about data after the last member: error, warn or ignore
+  #[structopt(long = "trailing-data", default_value = "error")]
+  trailing_data: Policy,
+
+  /// Decompress the members on this many threads, writing only the data to
+  /// the output file. Always as with --strict, and any reserved flags or
+  /// trailing data are errors.
+  #[structopt(long = "threads")]
+  threads: Option<usize>,
+
   /// Write detailed byte-by-byte JSON data
   #[structopt(long = "json")]
   json: bool,
 
+  /// Input .gz file
+  #[structopt(parse(from_os_str))]
+  input: Option<PathBuf>,
+
+  /// Output file
+  #[structopt(short = "o", long = "output", parse(from_os_str))]
+  output: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+  /// Change the header of a .gz file, copying the compressed data as it is
+  #[structopt(name = "rewrite")]
+  Rewrite(RewriteOpt),
+  /// Build a random-access index of a .gz file, for `extract`
+  #[structopt(name = "index")]
+  Index(IndexOpt),
+  /// Decompress part of a .gz file, using an index built by `index`
+  #[structopt(name = "extract")]
+  Extract(ExtractOpt),
+  /// Compress a file on several threads, as pigz does
+  #[structopt(name = "compress")]
+  Compress(CompressOpt),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct RewriteOpt {
+  /// Set the modification time to 0 (none recorded)
+  #[structopt(long = "zero-mtime")]
+  zero_mtime: bool,
+
+  /// Leave out the original filename
+  #[structopt(long = "drop-filename")]
+  drop_filename: bool,
+
+  /// Leave out the comment
+  #[structopt(long = "drop-comment")]
+  drop_comment: bool,
+
+  /// Set the OS byte (3 is Unix, 255 unknown)
+  #[structopt(long = "os")]
+  os: Option<u8>,
+
+  /// Decompress the result and check its checksums before writing it. This
+  /// also rewrites the headers of every member, not just the first
+  #[structopt(long = "verify")]
+  verify: bool,
+
   /// Input .gz file
   #[structopt(parse(from_os_str))]
   input: PathBuf,
 
   /// Output file
   #[structopt(short = "o", long = "output", parse(from_os_str))]
+  output: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct IndexOpt {
+  /// Data between checkpoints, in MiB
+  #[structopt(long = "span", default_value = "1")]
+  span: u64,
+
+  /// Input .gz file
+  #[structopt(parse(from_os_str))]
+  input: PathBuf,
+
+  /// Index file to write [default: the input with .gzidx added]
+  #[structopt(short = "o", long = "output", parse(from_os_str))]
+  output: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ExtractOpt {
+  /// Where in the decompressed data to start
+  #[structopt(long = "offset")]
+  offset: u64,
+
+  /// How many bytes to write
+  #[structopt(long = "length")]
+  length: usize,
+
+  /// Index file [default: the input with .gzidx added]
+  #[structopt(long = "index", parse(from_os_str))]
+  index: Option<PathBuf>,
+
+  /// Input .gz file
+  #[structopt(parse(from_os_str))]
+  input: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct CompressOpt {
+  /// Compression level, from 0 (none) to 9 (smallest)
+  #[structopt(long = "level", default_value = "6")]
+  level: u8,
+
+  /// Threads to compress on [default: one for each CPU]
+  #[structopt(long = "threads")]
+  threads: Option<usize>,
+
+  /// Input file
+  #[structopt(parse(from_os_str))]
+  input: PathBuf,
+
+  /// Output file [default: the input with .gz added]
+  #[structopt(short = "o", long = "output", parse(from_os_str))]
   output: Option<PathBuf>,
 }
 
 fn print_gzip_info(gz: &Gzip) {
   println!("Gzip Info");
+  if gz.members.len() > 1 {
+    println!("{} members", gz.members.len());
+  }
+  for (i, member) in gz.members.iter().enumerate() {
+    if gz.members.len() > 1 {
+      println!("----------------------------------");
+      println!("Member {} (at byte {})", i, member.offset);
+    }
+    print_member_info(member);
+  }
+  if let Some(range) = &gz.trailing_data {
+    println!(
+      "Trailing data: {} bytes at byte {}",
+      range.len(),
+      range.start
+    );
+  }
+}
+
+fn print_member_info(gz: &Member) {
   println!("Compression: {:?}", gz.headers.compression);
   println!(
     "Modification Time: {} ({})",
@@ -40,18 +183,26 @@ fn print_gzip_info(gz: &Gzip) {
     gz.headers.mtime,
   );
   println!("Os: {:?}", gz.headers.os);
-  match &gz.headers.filename {
+  match gz.headers.filename_latin1() {
     Some(string) => println!("Original Filename: {}", string),
     None => println!("Original Filename: <unknown>"),
   }
 
-  match &gz.headers.comment {
+  match gz.headers.comment_latin1() {
     Some(string) => println!("Comment: {}", string),
     None => println!("Comment: <none>"),
   }
 
   match &gz.headers.crc16 {
-    Some(v) => println!("Headers CRC16: {}", v),
+    Some(v) => println!(
+      "Headers CRC16: {:x} {}",
+      v,
+      if gz.headers.crc16_is_valid() {
+        "✅"
+      } else {
+        "😲"
+      }
+    ),
     None => println!("Headers CRC16: <none>"),
   }
 
@@ -61,9 +212,22 @@ fn print_gzip_info(gz: &Gzip) {
   }
 
   println!("Is Text Flag: {}", &gz.headers.is_text);
+  if gz.headers.reserved_flags() != 0 {
+    println!("Reserved Flags: {:#010b}", gz.headers.reserved_flags());
+  }
   println!("{} Extra Fields", &gz.headers.extra_fields.len());
   for extra_field in &gz.headers.extra_fields {
-    println!("\t {}: {}", extra_field.id, extra_field.data);
+    let id = String::from_utf8_lossy(&extra_field.id);
+    match (extra_field.decode(), extra_field.description()) {
+      (Some(subfield), _) => println!("\t {}: {:?}", id, subfield),
+      (None, Some(description)) => println!(
+        "\t {} ({}): {} bytes",
+        id,
+        description,
+        extra_field.data.len()
+      ),
+      (None, None) => println!("\t {}: {:02x?}", id, extra_field.data),
+    }
   }
 
   println!(
@@ -83,6 +247,12 @@ fn print_gzip_info(gz: &Gzip) {
 fn print_debug_gzip_info(gz: &Gzip) {
   println!("Decompressed Data: {}", gz.as_string());
 
+  for member in &gz.members {
+    print_debug_member_info(member);
+  }
+}
+
+fn print_debug_member_info(gz: &Member) {
   for (i, block) in gz.blocks.iter().enumerate() {
     println!("==================================");
     println!(
@@ -97,15 +267,171 @@ fn print_debug_gzip_info(gz: &Gzip) {
 }
 
 fn write_serialized_gzip(gz: &Gzip, buffer: std::fs::File) {
-  serde_json::to_writer(buffer, &gz.decode_items).expect("failed to write serialize");
+  let decode_items: Vec<_> = gz
+    .members
+    .iter()
+    .flat_map(|member| &member.decode_items)
+    .collect();
+  serde_json::to_writer(buffer, &decode_items).expect("failed to write serialize");
+}
+
+pub fn rewrite(opts: RewriteOpt) -> Result<(), Box<dyn Error>> {
+  let buf = std::fs::read(&opts.input)?;
+  let edit = |headers: &mut gzip::Headers| {
+    if opts.zero_mtime {
+      headers.mtime = 0;
+    }
+    if opts.drop_filename {
+      headers.filename = None;
+    }
+    if opts.drop_comment {
+      headers.comment = None;
+    }
+    if let Some(os) = opts.os {
+      headers.os = Os::from(os);
+    }
+  };
+  // Verifying inflates every member anyway, which finds where they all
+  // start, so then all of their headers can be rewritten
+  let rewritten = if opts.verify {
+    let rewritten = gzip::rewrite_all_headers(&buf, edit)?;
+    gzip::verify(&rewritten)?;
+    rewritten
+  } else {
+    if gzip::may_have_later_members(&buf) {
+      eprintln!(
+        "Warning: only the first member's header was rewritten; if there are later members, \
+         use --verify to rewrite theirs too"
+      );
+    }
+    gzip::rewrite_headers(&buf, edit)?
+  };
+  std::fs::write(&opts.output, &rewritten)?;
+  println!(
+    "Wrote {} bytes to {:?} ({} before)",
+    rewritten.len(),
+    &opts.output,
+    buf.len()
+  );
+  Ok(())
+}
+
+// Where the index of `input` goes unless told otherwise
+fn index_path(input: &Path) -> PathBuf {
+  let mut path = input.as_os_str().to_owned();
+  path.push(".gzidx");
+  PathBuf::from(path)
+}
+
+pub fn build_index(opts: IndexOpt) -> Result<(), Box<dyn Error>> {
+  let span = match opts.span.checked_mul(1024 * 1024) {
+    Some(span) if span > 0 => span,
+    _ => return Err("the span must be from 1 to 2^44 - 1 MiB".into()),
+  };
+  let mut file = File::open(&opts.input)?;
+  let index = gzip::Index::build(&mut file, span)?;
+  let path = match opts.output {
+    Some(path) => path,
+    None => index_path(&opts.input),
+  };
+  let mut buffer = std::io::BufWriter::new(File::create(&path)?);
+  index.write_to(&mut buffer)?;
+  buffer.flush()?;
+  println!(
+    "Wrote {} checkpoints for {} bytes of data to {:?}",
+    index.checkpoints().len(),
+    index.uncompressed_len(),
+    &path
+  );
+  Ok(())
+}
+
+pub fn extract(opts: ExtractOpt) -> Result<(), Box<dyn Error>> {
+  let path = match opts.index {
+    Some(path) => path,
+    None => index_path(&opts.input),
+  };
+  let index = gzip::Index::read_from(&mut std::io::BufReader::new(File::open(path)?))?;
+  let mut file = File::open(&opts.input)?;
+  let data = index.seek_read(&mut file, opts.offset, opts.length)?;
+  std::io::stdout().write_all(&data)?;
+  Ok(())
+}
+
+pub fn compress(opts: CompressOpt) -> Result<(), Box<dyn Error>> {
+  let level = CompressionLevel::new(opts.level).ok_or("the level must be from 0 to 9")?;
+  let file = File::open(&opts.input)?;
+  // Record the name and modification time, as gzip does
+  let mut header = gzip::HeadersBuilder::new().mtime_from(file.metadata()?.modified()?);
+  if let Some(name) = opts.input.file_name() {
+    header = header.filename(&name.to_string_lossy());
+  }
+  let mut encoder = gzip::ParEncoder::with_level(level).headers(header);
+  if let Some(threads) = opts.threads {
+    encoder = encoder.threads(threads);
+  }
+
+  let path = match opts.output {
+    Some(path) => path,
+    None => {
+      let mut path = opts.input.as_os_str().to_owned();
+      path.push(".gz");
+      PathBuf::from(path)
+    }
+  };
+  let mut buffer = std::io::BufWriter::new(File::create(&path)?);
+  let len = encoder.compress(std::io::BufReader::new(file), &mut buffer)?;
+  println!("Compressed {} bytes to {:?}", len, &path);
+  Ok(())
+}
+
+pub fn par_decompress(buf: &[u8], threads: usize, path: &Path) -> Result<(), Box<dyn Error>> {
+  let mut buffer = std::io::BufWriter::new(File::create(path)?);
+  let written = gzip::par_decompress(buf, threads, &mut buffer)?;
+  buffer.flush()?;
+  println!(
+    "Wrote {} bytes to {:?} using {} threads",
+    written, path, threads
+  );
+  Ok(())
 }
 
 pub fn run(opts: Opt) -> Result<(), Box<dyn Error>> {
+  let input = match opts.command {
+    Some(Command::Rewrite(rewrite_opts)) => return rewrite(rewrite_opts),
+    Some(Command::Index(index_opts)) => return build_index(index_opts),
+    Some(Command::Extract(extract_opts)) => return extract(extract_opts),
+    Some(Command::Compress(compress_opts)) => return compress(compress_opts),
+    None => opts.input.ok_or("an input file is required")?,
+  };
+  if opts.threads.is_some() {
+    // par_decompress takes no ParseOptions, so nothing can be let through
+    if opts.reserved_flags != Policy::Error || opts.trailing_data != Policy::Error {
+      return Err("--threads only works with --reserved-flags and --trailing-data error".into());
+    }
+    if opts.json {
+      return Err("--threads cannot write --json".into());
+    }
+  }
   let mut buf = vec![];
-  let mut file = File::open(&opts.input)?;
+  let mut file = File::open(&input)?;
   let num_read = file.read_to_end(&mut buf)?;
-  println!("Read {} bytes from {:?}", num_read, &opts.input);
-  let gzip = Gzip::new(buf);
+  println!("Read {} bytes from {:?}", num_read, &input);
+  if let Some(threads) = opts.threads {
+    let path = opts.output.ok_or("--threads needs an output file")?;
+    return par_decompress(&buf, threads, &path);
+  }
+  let options = ParseOptions {
+    strict: opts.strict,
+    reserved_flags: opts.reserved_flags,
+    trailing_data: opts.trailing_data,
+    // Only --json and --debug show the symbols
+    trace: opts.json || opts.debug,
+  };
+  let gzip = Gzip::with_options(buf, options)?;
+  for warning in &gzip.warnings {
+    eprintln!("Warning: {}", warning);
+  }
 
   match opts.output {
     Some(path) => {
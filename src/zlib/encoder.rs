@@ -0,0 +1,173 @@
+use super::{Level, DEFLATE, FDICT, MAX_WINDOW_BITS};
+use crate::adler32;
+use crate::deflate::{CompressionLevel, Encoder};
+use std::io::{self, Write};
+
+// Compresses everything written to it into a zlib stream on `inner`, as
+// `GzEncoder` does for gzip. `finish` must be called once all the data has
+// been written, to write the final block and the Adler-32 trailer.
+pub struct ZlibEncoder<W: Write> {
+  inner: W,
+  encoder: Encoder,
+  level: CompressionLevel,
+  header_written: bool,
+  dictionary_id: Option<u32>,
+  adler32: u32,
+}
+
+impl<W: Write> ZlibEncoder<W> {
+  pub fn new(inner: W) -> ZlibEncoder<W> {
+    ZlibEncoder::with_level(inner, CompressionLevel::default())
+  }
+
+  pub fn with_level(inner: W, level: CompressionLevel) -> ZlibEncoder<W> {
+    ZlibEncoder {
+      inner,
+      encoder: Encoder::with_level(level),
+      level,
+      header_written: false,
+      dictionary_id: None,
+      adler32: adler32::initial_value(),
+    }
+  }
+
+  // Compress with a preset dictionary. FDICT is set, and the Adler-32 of the
+  // dictionary written as the DICTID, so the stream can only be decoded with
+  // `Zlib::with_dictionary`.
+  pub fn with_dictionary(inner: W, level: CompressionLevel, dictionary: &[u8]) -> ZlibEncoder<W> {
+    ZlibEncoder {
+      encoder: Encoder::with_dictionary(level, dictionary),
+      dictionary_id: Some(adler32::adler32(dictionary)),
+      ..ZlibEncoder::with_level(inner, level)
+    }
+  }
+
+  pub fn get_ref(&self) -> &W {
+    &self.inner
+  }
+
+  // Write the last of the data and the trailer, returning the inner writer
+  pub fn finish(mut self) -> io::Result<W> {
+    self.write_header()?;
+    self.encoder.finish();
+    self.inner.write_all(&self.encoder.take_output())?;
+    self
+      .inner
+      .write_all(&adler32::finalize(self.adler32).to_be_bytes())?;
+    self.inner.flush()?;
+    Ok(self.inner)
+  }
+
+  fn write_header(&mut self) -> io::Result<()> {
+    if self.header_written {
+      return Ok(());
+    }
+    self.header_written = true;
+
+    // Deflate with a 32K window
+    let cmf = (MAX_WINDOW_BITS - 8) << 4 | DEFLATE;
+    let mut flg = Level::from_compression_level(self.level.level()).to_bits() << 6;
+    if self.dictionary_id.is_some() {
+      flg |= FDICT;
+    }
+    // FCHECK fills the low five bits to make the pair a multiple of 31
+    let fcheck = (31 - u16::from_be_bytes([cmf, flg]) % 31) % 31;
+    self.inner.write_all(&[cmf, flg | fcheck as u8])?;
+    match self.dictionary_id {
+      Some(id) => self.inner.write_all(&id.to_be_bytes()),
+      None => Ok(()),
+    }
+  }
+}
+
+impl<W: Write> Write for ZlibEncoder<W> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.write_header()?;
+    self.adler32 = adler32::update_slice(self.adler32, buf);
+    self.encoder.write(buf);
+    self.inner.write_all(&self.encoder.take_output())?;
+    Ok(buf.len())
+  }
+
+  // Passes on what has been compressed so far. Input that has not yet made
+  // up a whole block stays buffered.
+  fn flush(&mut self) -> io::Result<()> {
+    self.write_header()?;
+    self.inner.write_all(&self.encoder.take_output())?;
+    self.inner.flush()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::zlib::{Header, Zlib};
+  use std::fs;
+
+  fn compress(data: &[u8], level: u8) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::with_level(vec![], CompressionLevel::new(level).unwrap());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+  }
+
+  #[test]
+  fn test_round_trip_fixtures() {
+    for entry in fs::read_dir("tests/gzip/src/").expect("failed to read src dir") {
+      let src = fs::read(entry.expect("failed entry").path()).expect("Failed to read file");
+      let zlib = Zlib::new(compress(&src, 6)).expect("Failed to parse zlib");
+      assert_eq!(zlib.data, src);
+      assert!(zlib.adler32_is_valid());
+    }
+  }
+
+  #[test]
+  fn test_empty() {
+    // The same bytes zlib itself produces
+    assert_eq!(
+      compress(&[], 6),
+      vec![0x78, 0x9c, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01]
+    );
+  }
+
+  #[test]
+  fn test_dictionary() {
+    let src = include_bytes!("../../tests/gzip/src/raven");
+    let (dictionary, data) = src.split_at(1500);
+    let mut encoder = ZlibEncoder::with_dictionary(vec![], CompressionLevel::best(), dictionary);
+    encoder.write_all(data).unwrap();
+    let bytes = encoder.finish().unwrap();
+    // The same header Python's zlib writes
+    assert_eq!(bytes[..6], [0x78, 0xf9, 0x93, 0xfa, 0xfc, 0x06]);
+
+    let zlib = Zlib::with_dictionary(bytes, dictionary).unwrap();
+    assert_eq!(
+      zlib.header.dictionary_id,
+      Some(adler32::adler32(dictionary))
+    );
+    assert_eq!(&zlib.data[..], data);
+    assert!(zlib.adler32_is_valid());
+  }
+
+  #[test]
+  fn test_level_in_header() {
+    let src = include_bytes!("../../tests/gzip/src/raven");
+    for (level, expected) in [
+      (0, Level::Fastest),
+      (1, Level::Fastest),
+      (4, Level::Fast),
+      (6, Level::Default),
+      (9, Level::Maximum),
+    ] {
+      let zlib = Zlib::new(compress(src, level)).unwrap();
+      assert_eq!(
+        zlib.header,
+        Header {
+          window_size: 32768,
+          level: expected,
+          dictionary_id: None,
+        }
+      );
+      assert_eq!(&zlib.data[..], &src[..]);
+    }
+  }
+}
@@ -0,0 +1,520 @@
+mod encoder;
+pub use encoder::ZlibEncoder;
+
+use crate::adler32;
+use crate::deflate::{
+  inflate_to_end, Block, DecodeItem, Inflate, InflateError, InflateErrorKind,
+};
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+// A parsed zlib stream (RFC 1950): a two-byte header, a deflate stream, and
+// the Adler-32 of the uncompressed data. This is the format inside PNG IDAT
+// chunks, PDF FlateDecode streams and git objects.
+#[derive(Debug)]
+pub struct Zlib {
+  pub header: Header,
+  pub blocks: Vec<Block>,
+  pub decode_items: Vec<DecodeItem>, // only from `Zlib::traced`
+  pub data: Vec<u8>,
+  pub adler32: u32,
+  calculated_adler32: u32,
+}
+
+// The Adler-32 trailer, stored big-endian unlike everything in gzip
+const TRAILER_LEN: usize = 4;
+
+impl Zlib {
+  pub fn new(bytes: Vec<u8>) -> Result<Zlib, ZlibError> {
+    Zlib::parse(bytes, None, false)
+  }
+
+  // `new`, also filling in `decode_items`. This records every bit of every
+  // symbol, so it is much slower.
+  pub fn traced(bytes: Vec<u8>) -> Result<Zlib, ZlibError> {
+    Zlib::parse(bytes, None, true)
+  }
+
+  // For a stream with FDICT set, which can only be decoded with the preset
+  // dictionary it was compressed with. The dictionary is checked against the
+  // DICTID in the header. Streams without FDICT do not use it.
+  pub fn with_dictionary(bytes: Vec<u8>, dictionary: &[u8]) -> Result<Zlib, ZlibError> {
+    Zlib::parse(bytes, Some(dictionary), false)
+  }
+
+  fn parse(bytes: Vec<u8>, dictionary: Option<&[u8]>, trace: bool) -> Result<Zlib, ZlibError> {
+    let mut iter = bytes.iter().cloned();
+    let header = Header::new(&mut iter)?;
+    let header_len = bytes.len() - iter.len();
+
+    // The DICTID follows CMF and FLG
+    let dictionary = match (header.dictionary_id, dictionary) {
+      (None, _) => &[][..],
+      (Some(id), None) => return Err(ZlibError::DictionaryRequired { offset: 2, id }),
+      (Some(id), Some(dictionary)) => {
+        let found = adler32::adler32(dictionary);
+        if found != id {
+          return Err(ZlibError::DictionaryMismatch {
+            offset: 2,
+            expected: id,
+            found,
+          });
+        }
+        dictionary
+      }
+    };
+
+    // Matches may only reach back as far as CINFO says
+    let mut inflate = Inflate::with_dictionary(bytes[header_len..].iter().cloned(), dictionary);
+    inflate.limit_window(header.window_size);
+    if trace {
+      inflate.trace();
+    }
+    let inflate_result =
+      inflate_to_end(inflate).map_err(|error| ZlibError::inflate(header_len, error))?;
+
+    let trailer_start = header_len + inflate_result.compressed_len;
+    if bytes.len() - trailer_start < TRAILER_LEN {
+      return Err(ZlibError::TruncatedTrailer {
+        offset: bytes.len(),
+      });
+    }
+    let trailer_end = trailer_start + TRAILER_LEN;
+    if trailer_end < bytes.len() {
+      return Err(ZlibError::TrailingData {
+        offset: trailer_end,
+      });
+    }
+    let mut trailer = [0; TRAILER_LEN];
+    trailer.copy_from_slice(&bytes[trailer_start..trailer_end]);
+
+    Ok(Zlib {
+      header,
+      blocks: inflate_result.blocks,
+      decode_items: inflate_result.decode_items,
+      calculated_adler32: adler32::adler32(&inflate_result.data),
+      data: inflate_result.data,
+      adler32: u32::from_be_bytes(trailer),
+    })
+  }
+
+  pub fn adler32_is_valid(&self) -> bool {
+    self.adler32 == self.calculated_adler32
+  }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Header {
+  pub window_size: usize, // from CINFO, a power of two up to 32K; no match reaches further
+  pub level: Level,       // from FLEVEL
+  pub dictionary_id: Option<u32>, // the Adler-32 of the preset dictionary, if FDICT is set
+}
+
+// The compression method, and the only one RFC 1950 defines
+const DEFLATE: u8 = 8;
+
+// CINFO is the base-two logarithm of the window size, less eight
+const MAX_WINDOW_BITS: u8 = 15;
+
+const FDICT: u8 = 0b10_0000;
+
+impl Header {
+  fn new(bytes: &mut impl Iterator<Item = u8>) -> Result<Header, ZlibError> {
+    let cmf = bytes
+      .next()
+      .ok_or(ZlibError::TruncatedHeader { offset: 0 })?;
+    let flg = bytes
+      .next()
+      .ok_or(ZlibError::TruncatedHeader { offset: 1 })?;
+
+    let method = cmf & 0x0f;
+    if method != DEFLATE {
+      return Err(ZlibError::UnsupportedMethod { offset: 0, method });
+    }
+    let window_bits = (cmf >> 4) + 8;
+    if window_bits > MAX_WINDOW_BITS {
+      return Err(ZlibError::BadWindowSize {
+        offset: 0,
+        window_bits,
+      });
+    }
+
+    // FCHECK makes CMF and FLG, read as a big-endian number, a multiple of 31
+    let check = u16::from_be_bytes([cmf, flg]);
+    if check % 31 != 0 {
+      return Err(ZlibError::BadHeaderCheck { offset: 1, check });
+    }
+
+    let dictionary_id = if flg & FDICT != 0 {
+      let mut id = [0; 4];
+      for (i, byte) in id.iter_mut().enumerate() {
+        *byte = bytes
+          .next()
+          .ok_or(ZlibError::TruncatedHeader { offset: 2 + i })?;
+      }
+      Some(u32::from_be_bytes(id))
+    } else {
+      None
+    };
+
+    Ok(Header {
+      window_size: 1 << window_bits,
+      level: Level::parse(flg >> 6),
+      dictionary_id,
+    })
+  }
+}
+
+// FLEVEL: how hard the compressor tried. It is informational only, and not
+// needed to decompress.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+  Fastest,
+  Fast,
+  Default,
+  Maximum,
+}
+
+impl Level {
+  fn parse(bits: u8) -> Level {
+    match bits & 0b11 {
+      0 => Level::Fastest,
+      1 => Level::Fast,
+      2 => Level::Default,
+      _ => Level::Maximum,
+    }
+  }
+
+  // zlib's mapping from compression levels
+  fn from_compression_level(level: u8) -> Level {
+    match level {
+      0 | 1 => Level::Fastest,
+      2..=5 => Level::Fast,
+      6 => Level::Default,
+      _ => Level::Maximum,
+    }
+  }
+
+  fn to_bits(self) -> u8 {
+    match self {
+      Level::Fastest => 0,
+      Level::Fast => 1,
+      Level::Default => 2,
+      Level::Maximum => 3,
+    }
+  }
+}
+
+/// The ways a zlib stream can fail to parse. Every variant carries the byte
+/// offset (from the start of the stream) at which the problem was found.
+#[derive(Debug, PartialEq)]
+pub enum ZlibError {
+  /// The input ended before the header was complete
+  TruncatedHeader { offset: usize },
+  /// The CM bits named a compression method other than deflate (8)
+  UnsupportedMethod { offset: usize, method: u8 },
+  /// CINFO asked for a window larger than 32K
+  BadWindowSize { offset: usize, window_bits: u8 },
+  /// CMF and FLG together are not a multiple of 31
+  BadHeaderCheck { offset: usize, check: u16 },
+  /// FDICT is set, and the stream needs a preset dictionary to decode
+  DictionaryRequired { offset: usize, id: u32 },
+  /// The dictionary given does not have the Adler-32 in the header
+  DictionaryMismatch {
+    offset: usize,
+    expected: u32,
+    found: u32,
+  },
+  /// The compressed data is not a valid deflate stream
+  Inflate { offset: usize, error: InflateError },
+  /// The input ended before the 4-byte Adler-32 trailer
+  TruncatedTrailer { offset: usize },
+  /// There are bytes after the trailer
+  TrailingData { offset: usize },
+}
+
+impl ZlibError {
+  // Wraps an error from a deflate stream that started at byte `start`
+  fn inflate(start: usize, error: InflateError) -> ZlibError {
+    ZlibError::Inflate {
+      offset: start + error.bit_offset / 8,
+      error,
+    }
+  }
+
+  pub fn offset(&self) -> usize {
+    use ZlibError::*;
+    match *self {
+      TruncatedHeader { offset }
+      | UnsupportedMethod { offset, .. }
+      | BadWindowSize { offset, .. }
+      | BadHeaderCheck { offset, .. }
+      | DictionaryRequired { offset, .. }
+      | DictionaryMismatch { offset, .. }
+      | Inflate { offset, .. }
+      | TruncatedTrailer { offset }
+      | TrailingData { offset } => offset,
+    }
+  }
+}
+
+impl fmt::Display for ZlibError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    use ZlibError::*;
+    match self {
+      TruncatedHeader { offset } => write!(f, "header truncated at byte {}", offset),
+      UnsupportedMethod { offset, method } => write!(
+        f,
+        "unsupported compression method {} at byte {}",
+        method, offset
+      ),
+      BadWindowSize {
+        offset,
+        window_bits,
+      } => write!(
+        f,
+        "window size 2^{} at byte {} is larger than 32K",
+        window_bits, offset
+      ),
+      BadHeaderCheck { offset, check } => write!(
+        f,
+        "header check at byte {} failed ({:#06x} is not a multiple of 31)",
+        offset, check
+      ),
+      DictionaryRequired { offset, id } => write!(
+        f,
+        "stream needs a preset dictionary (Adler-32 {:08x}, at byte {})",
+        id, offset
+      ),
+      DictionaryMismatch {
+        offset,
+        expected,
+        found,
+      } => write!(
+        f,
+        "stream needs the preset dictionary with Adler-32 {:08x} (at byte {}), not {:08x}",
+        expected, offset, found
+      ),
+      Inflate { offset, error } => write!(f, "corrupt data at byte {}: {}", offset, error),
+      TruncatedTrailer { offset } => write!(f, "trailer truncated at byte {}", offset),
+      TrailingData { offset } => write!(f, "unexpected data after the trailer at byte {}", offset),
+    }
+  }
+}
+
+impl Error for ZlibError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      ZlibError::Inflate { error, .. } => Some(error),
+      _ => None,
+    }
+  }
+}
+
+impl From<ZlibError> for io::Error {
+  fn from(error: ZlibError) -> io::Error {
+    let kind = match &error {
+      ZlibError::TruncatedHeader { .. } | ZlibError::TruncatedTrailer { .. } => {
+        io::ErrorKind::UnexpectedEof
+      }
+      ZlibError::Inflate { error, .. } if error.kind == InflateErrorKind::UnexpectedEnd => {
+        io::ErrorKind::UnexpectedEof
+      }
+      _ => io::ErrorKind::InvalidData,
+    };
+    io::Error::new(kind, error)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::fs;
+
+  #[test]
+  fn test_fixtures() {
+    let src = fs::read("tests/gzip/src/gunzip").unwrap();
+
+    let zlib = Zlib::new(fs::read("tests/zlib/gunzip.zz").unwrap()).unwrap();
+    assert_eq!(zlib.data, src);
+    assert!(zlib.adler32_is_valid());
+    assert_eq!(
+      zlib.header,
+      Header {
+        window_size: 32768,
+        level: Level::Maximum,
+        dictionary_id: None,
+      }
+    );
+
+    let zlib = Zlib::new(fs::read("tests/zlib/gunzip-window-1k.zz").unwrap()).unwrap();
+    assert_eq!(zlib.data, src);
+    assert!(zlib.adler32_is_valid());
+    assert_eq!(zlib.header.window_size, 1024);
+    assert_eq!(zlib.header.level, Level::Fastest);
+  }
+
+  #[test]
+  fn test_traced() {
+    let bytes = fs::read("tests/zlib/gunzip.zz").unwrap();
+    assert!(Zlib::new(bytes.clone()).unwrap().decode_items.is_empty());
+    let zlib = Zlib::traced(bytes).unwrap();
+    assert!(!zlib.decode_items.is_empty());
+    assert_eq!(zlib.data, fs::read("tests/gzip/src/gunzip").unwrap());
+  }
+
+  #[test]
+  fn test_dictionary() {
+    // Made by Python's zlib, with `zdict` set to the first 1500 bytes
+    let bytes = fs::read("tests/zlib/raven-dictionary.zz").unwrap();
+    let src = fs::read("tests/gzip/src/raven").unwrap();
+    let (dictionary, data) = src.split_at(1500);
+
+    let zlib = Zlib::with_dictionary(bytes.clone(), dictionary).unwrap();
+    assert_eq!(zlib.header.dictionary_id, Some(0x93fa_fc06));
+    assert_eq!(zlib.data, data);
+    assert!(zlib.adler32_is_valid());
+
+    assert_eq!(
+      Zlib::new(bytes).unwrap_err(),
+      ZlibError::DictionaryRequired {
+        offset: 2,
+        id: 0x93fa_fc06
+      }
+    );
+
+    // A dictionary is not needed without FDICT
+    let zlib = Zlib::with_dictionary(fs::read("tests/zlib/gunzip.zz").unwrap(), dictionary);
+    assert_eq!(
+      zlib.unwrap().data,
+      fs::read("tests/gzip/src/gunzip").unwrap()
+    );
+  }
+
+  #[test]
+  fn test_bad_adler32() {
+    let mut bytes = fs::read("tests/zlib/gunzip.zz").unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 1;
+    assert!(!Zlib::new(bytes).unwrap().adler32_is_valid());
+  }
+
+  mod errors {
+    use super::*;
+
+    fn gunzip() -> Vec<u8> {
+      fs::read("tests/zlib/gunzip.zz").unwrap()
+    }
+
+    #[test]
+    fn bad_header() {
+      assert_eq!(
+        Zlib::new(vec![0x78]).unwrap_err(),
+        ZlibError::TruncatedHeader { offset: 1 }
+      );
+      // CM 7, with FCHECK fixed up
+      assert_eq!(
+        Zlib::new(vec![0x77, 0x09]).unwrap_err(),
+        ZlibError::UnsupportedMethod {
+          offset: 0,
+          method: 7
+        }
+      );
+      // CINFO 8, a 64K window
+      assert_eq!(
+        Zlib::new(vec![0x88, 0x1c]).unwrap_err(),
+        ZlibError::BadWindowSize {
+          offset: 0,
+          window_bits: 16
+        }
+      );
+      let mut bytes = gunzip();
+      bytes[1] ^= 1;
+      assert_eq!(
+        Zlib::new(bytes).unwrap_err(),
+        ZlibError::BadHeaderCheck {
+          offset: 1,
+          check: 0x78db
+        }
+      );
+    }
+
+    #[test]
+    fn dictionary_required() {
+      assert_eq!(
+        Zlib::new(vec![0x78, 0xbb, 0x12, 0x34]).unwrap_err(),
+        ZlibError::TruncatedHeader { offset: 4 }
+      );
+      assert_eq!(
+        Zlib::new(vec![0x78, 0xbb, 0x12, 0x34, 0x56, 0x78, 0x03, 0x00]).unwrap_err(),
+        ZlibError::DictionaryRequired {
+          offset: 2,
+          id: 0x1234_5678
+        }
+      );
+    }
+
+    #[test]
+    fn dictionary_mismatch() {
+      let bytes = fs::read("tests/zlib/raven-dictionary.zz").unwrap();
+      assert_eq!(
+        Zlib::with_dictionary(bytes, b"not it").unwrap_err(),
+        ZlibError::DictionaryMismatch {
+          offset: 2,
+          expected: 0x93fa_fc06,
+          found: adler32::adler32(b"not it"),
+        }
+      );
+    }
+
+    #[test]
+    fn distance_past_window() {
+      // The same stream, but with CINFO claiming a 1K window
+      let mut bytes = gunzip();
+      bytes[0] = 0x28;
+      bytes[1] = 0xcf;
+      match Zlib::new(bytes).unwrap_err() {
+        ZlibError::Inflate { error, .. } => match error.kind {
+          InflateErrorKind::DistanceTooFar {
+            distance,
+            available,
+          } => {
+            assert!(distance > 1024);
+            assert_eq!(available, 1024);
+          }
+          kind => panic!("Expected DistanceTooFar, got {:?}", kind),
+        },
+        error => panic!("Expected an inflate error, got {:?}", error),
+      }
+    }
+
+    #[test]
+    fn bad_trailer() {
+      let bytes = gunzip();
+      assert_eq!(
+        Zlib::new(bytes[..bytes.len() - 1].to_vec()).unwrap_err(),
+        ZlibError::TruncatedTrailer {
+          offset: bytes.len() - 1
+        }
+      );
+      let len = bytes.len();
+      assert_eq!(
+        Zlib::new([&bytes[..], &[0][..]].concat()).unwrap_err(),
+        ZlibError::TrailingData { offset: len }
+      );
+    }
+
+    #[test]
+    fn corrupt_data() {
+      // A reserved block type straight after the header
+      match Zlib::new(vec![0x78, 0x9c, 0b111]).unwrap_err() {
+        ZlibError::Inflate { offset, error } => {
+          assert_eq!(offset, 2);
+          assert_eq!(error.kind, InflateErrorKind::ReservedBlockType);
+        }
+        e => panic!("Unexpected error {:?}", e),
+      }
+    }
+  }
+}